@@ -1,9 +1,18 @@
+use minifb::Key;
+use minifb::KeyRepeat;
 use minifb::WindowOptions;
 use minifb::Window;
+use std::env;
+use std::fs::File;
+use std::io::{
+    BufWriter,
+    Write
+};
 use std::path::Path;
 
 use crate::nes::{
     Nes,
+    controller,
     ppu::SCREEN_WIDTH,
     ppu::SCREEN_HEIGHT
 };
@@ -11,6 +20,32 @@ use crate::nes::{
 pub mod nes;
 
 const FRAMES_PER_SECOND: usize = 60;
+// Where .sav files go, next to the ROM when unset
+const SAVE_DIRECTORY_VARIABLE: &str = "NEST_SAVE_DIR";
+// Disk system BIOS to boot .fds images with
+const FDS_BIOS_VARIABLE: &str = "NEST_FDS_BIOS";
+// Set to boot disk images with the built-in BIOS routines instead
+const FDS_HLE_VARIABLE: &str = "NEST_FDS_HLE";
+// Raw mono 32 bit float PCM at apu::SAMPLE_RATE is written here when set, e.g. for
+// `aplay -f FLOAT_LE -r 44100 -c 1`
+const AUDIO_FILE_VARIABLE: &str = "NEST_AUDIO_FILE";
+// Set to start nestest in its automated mode at $C000 instead of at the reset vector
+const NESTEST_VARIABLE: &str = "NEST_NESTEST";
+
+// Famicom Disk System: eject the disk, insert the next side (flipping it or changing disks)
+const EJECT_DISK_KEY: Key = Key::F1;
+const NEXT_DISK_SIDE_KEY: Key = Key::F2;
+
+const KEY_BINDINGS: [(Key, u8); 8] = [
+    (Key::Z, controller::BUTTON_A),
+    (Key::X, controller::BUTTON_B),
+    (Key::RightShift, controller::BUTTON_SELECT),
+    (Key::Enter, controller::BUTTON_START),
+    (Key::Up, controller::BUTTON_UP),
+    (Key::Down, controller::BUTTON_DOWN),
+    (Key::Left, controller::BUTTON_LEFT),
+    (Key::Right, controller::BUTTON_RIGHT)
+];
 
 fn main() {
     let mut window = match Window::new("Nest", SCREEN_WIDTH, SCREEN_HEIGHT, WindowOptions::default()) {
@@ -24,14 +59,64 @@ fn main() {
     window.set_target_fps(FRAMES_PER_SECOND);
 
     let mut nes: Nes = Nes::new();
+    if let Ok(directory) = env::var(SAVE_DIRECTORY_VARIABLE) {
+        nes.set_save_directory(Path::new(&directory));
+    }
+    if let Ok(bios) = env::var(FDS_BIOS_VARIABLE) {
+        nes.set_fds_bios(Path::new(&bios));
+    }
+    nes.set_hle_fds_bios(env::var_os(FDS_HLE_VARIABLE).is_some());
+    nes.set_nestest(env::var_os(NESTEST_VARIABLE).is_some());
 
     let rom_path = Path::new("test_roms/nestest.nes");
-    nes.load_rom(rom_path);
+    if let Err(err) = nes.load_rom(rom_path) {
+        println!("Couldn't load {}: {}", rom_path.display(), err);
+        return;
+    }
 
     nes.reset();
 
+    let mut audio_file: Option<BufWriter<File>> = match env::var(AUDIO_FILE_VARIABLE) {
+        Ok(path) => match File::create(&path) {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(err) => {
+                println!("Couldn't create audio file {}: {}", path, err);
+                None
+            }
+        },
+        Err(_) => None
+    };
+
     while window.is_open() {
+        let buttons: u8 = KEY_BINDINGS.iter()
+            .filter(|(key, _)| window.is_key_down(*key))
+            .fold(0, |buttons, (_, button)| buttons | button);
+        nes.set_buttons(0, buttons);
+
+        if nes.disk_sides() > 0 {
+            if window.is_key_pressed(EJECT_DISK_KEY, KeyRepeat::No) {
+                nes.eject_disk();
+            } else if window.is_key_pressed(NEXT_DISK_SIDE_KEY, KeyRepeat::No) {
+                let side: usize = nes.inserted_disk().map_or(0, |side| (side + 1) % nes.disk_sides());
+                nes.insert_disk(side);
+            }
+        }
+
         nes.step();
         nes.draw(&mut window);
+
+        // drained every frame so the APU never has to drop any
+        let samples: Vec<f32> = nes.take_samples();
+        if let Some(file) = &mut audio_file {
+            let bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+            if let Err(err) = file.write_all(&bytes) {
+                println!("Couldn't write audio file: {}", err);
+                audio_file = None;
+            }
+        }
     };
+
+    if let Err(err) = nes.save() {
+        println!("Couldn't write save file: {}", err);
+    }
 }
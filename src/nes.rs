@@ -1,36 +1,87 @@
+use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::path::{
+    Path,
+    PathBuf
+};
 
 use crate::nes::{
+    apu::Apu,
+    controller::Controller,
     cpu::Cpu,
     cpu::CpuFlags,
+    disk::Disk,
+    mapper::fds::Fds,
+    mapper::Mapper,
+    mapper::NoCartridge,
     mbc::Mbc,
     ppu::Ppu,
+    rom::Cartridge,
+    rom::RomError,
+    unif::Unif,
     ppu::SCREEN_WIDTH,
     ppu::SCREEN_HEIGHT
 };
 
 use minifb::Window;
 
+pub mod apu;
+pub mod archive;
+pub mod controller;
+pub mod mapper;
 pub mod mbc;
 pub mod cpu;
+pub mod disk;
+pub mod fds_bios;
+pub mod ips;
 pub mod ppu;
+pub mod rom;
+pub mod unif;
 
 const CYCLES_PER_FRAME: u32 = 29781;
+// Battery RAM is flushed this often so a crash loses at most a few seconds of progress
+const SAVE_INTERVAL_FRAMES: u32 = 60 * 5;
+// Disk system BIOS looked for in the working directory unless set otherwise
+const FDS_BIOS_FILE: &str = "disksys.rom";
+// nestest's automated mode runs every test without the menu when started here
+const NESTEST_START: u16 = 0xC000;
+// CPU cycles that pass per step while the CPU is stopped
+const HALTED_CYCLES: u32 = 3;
     
 pub struct Nes {
     cpu: Cpu,
     mbc: Mbc,
-    ppu: Ppu
+    save_directory: Option<PathBuf>, // next to the ROM when not set
+    save_path: Option<PathBuf>, // sidecar file for the cartridge's battery RAM or flash
+    saved: Vec<u8>, // what the save file holds, to only write it when the game changed something
+    frames_since_save: u32,
+    fds_bios: PathBuf,
+    hle_fds_bios: bool, // boot disks with the built-in BIOS instead of the file
+    bios_trapped: bool, // the loaded disk runs on the built-in BIOS
+    unemulated_bios_routine: Option<u16>, // where the built-in BIOS stopped the CPU
+    nestest: bool // start at $C000 instead of the reset vector
+}
+
+impl Default for Nes {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Nes {
     pub fn new() -> Self {
         Self {
             mbc: Mbc {
-                memory: [0; 0x10000],
-                rom: vec![0; 0xFFFF]
+                ram: [0; 0x800],
+                ppu: RefCell::new(Ppu::new()),
+                apu: RefCell::new(Apu::new()),
+                controllers: RefCell::new([Controller::default(); 2]),
+                cartridge: RefCell::new(Box::new(NoCartridge)),
+                dma_cycles: 0
             },
         
             cpu: Cpu {
@@ -45,47 +96,357 @@ impl Nes {
                     carry: 0
                 }
             },
-            ppu: Ppu {
-                screen_buffer: [0xFF000000; SCREEN_WIDTH*SCREEN_HEIGHT]
-            }
+
+            save_directory: None,
+            save_path: None,
+            saved: Vec::new(),
+            frames_since_save: 0,
+            fds_bios: PathBuf::from(FDS_BIOS_FILE),
+            hle_fds_bios: false,
+            bios_trapped: false,
+            unemulated_bios_routine: None,
+            nestest: false
         }
     }    
 
     pub fn draw(&self, window: &mut Window){
-        window.update_with_buffer(&self.ppu.screen_buffer, SCREEN_WIDTH, SCREEN_HEIGHT).unwrap();
+        window.update_with_buffer(&self.mbc.ppu.borrow().screen_buffer, SCREEN_WIDTH, SCREEN_HEIGHT).unwrap();
+    }
+
+    // Button state for controller port 0 or 1, see the BUTTON_ constants in controller.rs
+    pub fn set_buttons(&mut self, port: usize, buttons: u8) {
+        self.mbc.controllers.get_mut()[port].buttons = buttons;
+    }
+
+    // Audio produced since the last call, mono samples at apu::SAMPLE_RATE
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.mbc.apu.get_mut().samples.drain(..).collect()
     }
 
     pub fn step(&mut self){
         let mut cycles: u32 = 0;
         
         while cycles < CYCLES_PER_FRAME {
-            cycles += self.cpu.step(&mut self.mbc);
+            let mut step_cycles: u32 = if self.bios_trapped && self.cpu.pc >= fds_bios::BIOS_START {
+                match fds_bios::call(&mut self.cpu, &mut self.mbc) {
+                    Ok(cycles) => cycles,
+                    // the CPU stays put on the routine, the rest of the console keeps running
+                    Err(address) => {
+                        if self.unemulated_bios_routine.is_none() {
+                            println!("Disk system BIOS routine ${:04X} isn't emulated, stopping the CPU", address);
+                        }
+                        self.unemulated_bios_routine = Some(address);
+                        HALTED_CYCLES
+                    }
+                }
+            } else {
+                self.cpu.step(&mut self.mbc)
+            };
+            step_cycles += std::mem::take(&mut self.mbc.dma_cycles);
+            self.mbc.tick(step_cycles);
+
+            let interrupt_cycles: u32 = if self.mbc.nmi() {
+                self.cpu.nmi(&mut self.mbc)
+            } else if self.mbc.irq() {
+                self.cpu.irq(&mut self.mbc)
+            } else {
+                0
+            };
+            self.mbc.tick(interrupt_cycles);
+            step_cycles += interrupt_cycles;
+            cycles += step_cycles;
             println!("State: {}", self.cpu);
         }
+
+        self.frames_since_save += 1;
+        if self.frames_since_save >= SAVE_INTERVAL_FRAMES {
+            self.frames_since_save = 0;
+            if let Err(err) = self.save() {
+                println!("Couldn't write save file: {}", err);
+            }
+        }
     }
 
     pub fn reset(&mut self){
-        self.cpu.pc = 0xC000;//(self.mbc.read(0xFFFC) as u16) << 8 | self.mbc.read(0xFFFD) as u16;
+        self.unemulated_bios_routine = None;
+        self.cpu.pc = if self.nestest { NESTEST_START } else { self.mbc.read_u16(0xFFFC) };
         println!("CPU PC is 0x{:04x}", self.cpu.pc);
     }
 
-    pub fn load_rom(&mut self, path: &Path) {
-        let display = path.display();
-        let mut file = match File::open(&path){
-            Err(why) => panic!("Couldn't Open ROM File {}: {}", display, why),
-            Ok(file) => file
-        };
+    pub fn load_rom(&mut self, path: &Path) -> Result<(), RomError> {
+        self.load_rom_entry(path, None)
+    }
+
+    // Like load_rom, but takes the zip entry named `entry` rather than the first ROM in the zip
+    pub fn load_rom_entry(&mut self, path: &Path, entry: Option<&str>) -> Result<(), RomError> {
+        let mut file = File::open(path)?;
 
         let mut rom_data = Vec::new();
-        file.read_to_end(&mut rom_data).expect("Rom Too Big!");
-    
-        for i in 0..0x4000 { // PRGRAM
-            self.mbc.memory[0x8000 + i] = rom_data[i + 0x10];
+        file.read_to_end(&mut rom_data)?;
+
+        // archived ROMs are named (and saved) after the file inside
+        let (rom_path, rom_data): (PathBuf, Vec<u8>) = archive::unpack(path, rom_data, entry)?;
+        let path: &Path = &rom_path;
+
+        // disk writes are kept as a patch so the image itself stays untouched
+        let quick_disk: bool = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("qd"));
+        let disk: bool = quick_disk || Disk::is_disk_image(&rom_data);
+        let (mut board, save_extension): (Box<dyn Mapper>, &str) = if disk {
+            (self.load_disk(path, &rom_data, quick_disk)?, "ips")
+        } else if Unif::is_unif(&rom_data) {
+            let unif: Unif = Unif::from_bytes(&rom_data)?;
+            println!("UNIF board {}", unif.board);
+            (Self::load_cartridge(path, unif.cartridge)?, "sav")
+        } else {
+            (Self::load_cartridge(path, Cartridge::from_bytes(&rom_data)?)?, "sav")
+        };
+
+        // the outgoing cartridge's progress since the last periodic flush would be lost otherwise
+        self.save()?;
+
+        let save_path: PathBuf = self.save_path_for(path, save_extension);
+        // disks only have a save once written to, but any earlier patch still has to go on
+        if board.save_ram().is_some() || board.disk_sides() > 0 {
+            if let Ok(data) = fs::read(&save_path) {
+                println!("Loaded save {}", save_path.display());
+                board.load_save_ram(&data);
+            }
+        }
+        self.saved = board.save_ram().map(<[u8]>::to_vec).unwrap_or_default();
+        self.save_path = Some(save_path);
+        self.frames_since_save = 0;
+        self.mbc.load_cartridge(board);
+        // only once the board is in, a failed load leaves the old one running as it was
+        self.bios_trapped = disk && self.hle_fds_bios;
+        self.unemulated_bios_routine = None;
+
+        Ok(())
+    }
+
+    fn load_cartridge(path: &Path, cartridge: Cartridge) -> Result<Box<dyn Mapper>, RomError> {
+        let header = &cartridge.header;
+        println!("Loaded {} ({:?}): mapper {}.{}, {} KB PRG, {} KB CHR, {:?} mirroring, {:?} timing", path.display(), header.format, header.mapper, header.submapper, cartridge.prg_rom.len() / 1024, cartridge.chr_rom.len() / 1024, header.mirroring, header.timing);
+        if let Some(name) = mapper::submapper_name(header.mapper, header.submapper) {
+            println!("Submapper {}.{}: {}", header.mapper, header.submapper, name);
+        }
+        mapper::from_cartridge(cartridge)
+    }
+
+    fn load_disk(&mut self, path: &Path, data: &[u8], quick_disk: bool) -> Result<Box<dyn Mapper>, RomError> {
+        let disk: Disk = Disk::from_bytes(data, quick_disk)?;
+        let bios: Vec<u8> = if self.hle_fds_bios {
+            fds_bios::rom()
+        } else {
+            fs::read(&self.fds_bios).map_err(|err| RomError::MissingBios(self.fds_bios.clone(), err))?
+        };
+        if bios.len() != fds_bios::BIOS_SIZE {
+            return Err(RomError::InvalidBios(self.fds_bios.clone(), bios.len()));
+        }
+        println!("Loaded {} (FDS): {} disk sides", path.display(), disk.sides.len());
+        Ok(Box::new(Fds::new(disk, bios)))
+    }
+
+    // Makes reset start at nestest's automation entry point rather than the reset vector
+    pub fn set_nestest(&mut self, enabled: bool) {
+        self.nestest = enabled;
+    }
+
+    // BIOS used for disk images loaded afterwards
+    pub fn set_fds_bios(&mut self, path: &Path) {
+        self.fds_bios = path.to_path_buf();
+    }
+
+    // Built-in stand-in for the BIOS's disk routines, for running disk images without a BIOS dump
+    pub fn set_hle_fds_bios(&mut self, enabled: bool) {
+        self.hle_fds_bios = enabled;
+    }
+
+    // BIOS routine the built-in BIOS doesn't emulate that the loaded disk called, which
+    // stopped the CPU
+    pub fn unemulated_bios_routine(&self) -> Option<u16> {
+        self.unemulated_bios_routine
+    }
+
+    // Number of disk sides of a loaded disk image, 0 for cartridges
+    pub fn disk_sides(&self) -> usize {
+        self.mbc.cartridge.borrow().disk_sides()
+    }
+
+    // Side in the drive, None while ejected
+    pub fn inserted_disk(&self) -> Option<usize> {
+        self.mbc.cartridge.borrow().inserted_disk()
+    }
+
+    pub fn insert_disk(&mut self, side: usize) {
+        self.mbc.cartridge.get_mut().insert_disk(Some(side));
+    }
+
+    pub fn eject_disk(&mut self) {
+        self.mbc.cartridge.get_mut().insert_disk(None);
+    }
+
+    // Directory save files go to instead of next to the ROM, applies to ROMs loaded afterwards
+    pub fn set_save_directory(&mut self, directory: &Path) {
+        self.save_directory = Some(directory.to_path_buf());
+    }
+
+    // <rom>.sav (or .ips for disks) in the save directory if one is set, next to the ROM otherwise
+    fn save_path_for(&self, rom_path: &Path, extension: &str) -> PathBuf {
+        let save_path: PathBuf = rom_path.with_extension(extension);
+        match (&self.save_directory, save_path.file_name()) {
+            (Some(directory), Some(file_name)) => directory.join(file_name),
+            _ => save_path
+        }
+    }
+
+    // Writes the cartridge's battery backed memory to its save file if it changed since the last write
+    pub fn save(&mut self) -> io::Result<()> {
+        let cartridge = self.mbc.cartridge.borrow();
+        let (Some(path), Some(data)) = (&self.save_path, cartridge.save_ram()) else {
+            return Ok(());
+        };
+        if data == self.saved.as_slice() {
+            return Ok(());
         }
 
-        for i in 0..0x4000 { // PRGRAM Mirror
-            self.mbc.memory[0xC000 + i] = rom_data[i + 0x10];
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
         }
+        // written beside the save and renamed over it, so a crash mid-write leaves the old save intact
+        let mut temporary_path: OsString = path.clone().into_os_string();
+        temporary_path.push(".tmp");
+        let mut file: File = File::create(&temporary_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&temporary_path, path)?;
+        self.saved = data.to_vec();
+        Ok(())
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A disk file block pair: header with the file's ID, load address and size, then its data
+    fn file(number: u8, id: u8, address: u16, data: &[u8]) -> Vec<u8> {
+        let mut blocks: Vec<u8> = vec![3, number, id];
+        blocks.extend_from_slice(b"FILENAME");
+        blocks.extend_from_slice(&address.to_le_bytes());
+        blocks.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        blocks.push(0); // program file
+        blocks.push(4);
+        blocks.extend_from_slice(data);
+        blocks
     }
-}
\ No newline at end of file
+
+    // Disk side booting into `program` at $6000, with an RTI at $6100 for the NMIs. Boot files
+    // are the ones with an ID up to the info block's boot file ID, the last file isn't one.
+    fn boot_disk(program: &[u8]) -> Vec<u8> {
+        let mut side: Vec<u8> = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(56, 0);
+        side[0x19] = 1;
+        side.extend_from_slice(&[2, 4]);
+        side.extend(file(0, 0, 0x6000, program));
+        side.extend(file(1, 0, 0x6100, &[0x40]));
+        side.extend(file(2, 1, 0xDFF6, &[0x00, 0x61, 0x00, 0x61, 0x00, 0x61, 0x00, 0x60, 0x00, 0x61]));
+        side.extend(file(3, 2, 0x7000, &[0xAA]));
+        side
+    }
+
+    // Console reset into a disk with `program` on the built-in BIOS
+    fn boot(test: &str, program: &[u8]) -> Nes {
+        let directory: PathBuf = scratch_directory(test);
+        let path: PathBuf = directory.join("boot.fds");
+        fs::write(&path, boot_disk(program)).unwrap();
+
+        let mut nes: Nes = Nes::new();
+        nes.set_hle_fds_bios(true);
+        let loaded: Result<(), RomError> = nes.load_rom(&path);
+        fs::remove_dir_all(&directory).unwrap();
+        loaded.unwrap();
+
+        nes.reset();
+        assert_eq!(nes.cpu.pc, 0xEE24);
+        nes
+    }
+
+    // MMC1 image with battery RAM
+    fn battery_cartridge() -> Vec<u8> {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([2, 1, 0x12, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom.extend(vec![0; 0x8000 + 0x2000]);
+        rom
+    }
+
+    fn scratch_directory(test: &str) -> PathBuf {
+        let directory: PathBuf = std::env::temp_dir().join(format!("nest-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn hle_bios_boots_a_disk() {
+        // JMP $6000
+        let mut nes: Nes = boot("hle-boot", &[0x4C, 0x00, 0x60]);
+        nes.step();
+        assert_eq!((0..3).map(|offset| nes.mbc.read(0x6000 + offset)).collect::<Vec<u8>>(), [0x4C, 0x00, 0x60]);
+        assert_eq!(nes.mbc.read_u16(0xDFFC), 0x6000);
+        assert_eq!(nes.mbc.read(0x7000), 0x00);
+        assert_eq!(nes.cpu.pc, 0x6000);
+        assert_eq!(nes.unemulated_bios_routine(), None);
+    }
+
+    #[test]
+    fn hle_bios_reads_pads() {
+        // JSR ReadDownPads, collect the newly pressed buttons at $0300, JMP $6000
+        let mut nes: Nes = boot("hle-pads", &[0x20, 0x1A, 0xEA, 0xA5, 0xF5, 0x0D, 0x00, 0x03, 0x8D, 0x00, 0x03, 0x4C, 0x00, 0x60]);
+        nes.set_buttons(0, controller::BUTTON_A | controller::BUTTON_RIGHT);
+        nes.set_buttons(1, controller::BUTTON_START);
+        nes.step();
+        // the BIOS orders buttons A, B, Select, Start, Up, Down, Left, Right from bit 7 down
+        assert_eq!((nes.mbc.read(0xF7), nes.mbc.read(0xF8)), (0x81, 0x10));
+        // each button shows up as newly pressed on the first read only
+        assert_eq!(nes.mbc.read(0xF5), 0x00);
+        assert_eq!(nes.mbc.read(0x0300), 0x81);
+        assert_eq!(nes.unemulated_bios_routine(), None);
+    }
+
+    #[test]
+    fn hle_bios_stops_on_unemulated_routines() {
+        // JSR VRAMStructWrite, JMP $6003
+        let mut nes: Nes = boot("hle-unemulated", &[0x20, 0xBB, 0xE7, 0x4C, 0x03, 0x60]);
+        nes.step();
+        assert_eq!(nes.unemulated_bios_routine(), Some(0xE7BB));
+        assert_eq!(nes.cpu.pc, 0xE7BB);
+    }
+
+    #[test]
+    fn failed_loads_keep_the_bios_trap() {
+        let directory: PathBuf = scratch_directory("failed-load");
+        let (disk, cartridge, broken): (PathBuf, PathBuf, PathBuf) = (directory.join("boot.fds"), directory.join("game.nes"), directory.join("broken.nes"));
+        fs::write(&disk, boot_disk(&[0x4C, 0x00, 0x60])).unwrap();
+        fs::write(&cartridge, battery_cartridge()).unwrap();
+        fs::write(&broken, b"NES\x1A").unwrap();
+
+        let mut nes: Nes = Nes::new();
+        nes.set_hle_fds_bios(true);
+        nes.load_rom(&disk).unwrap();
+        // a ROM that doesn't parse leaves the disk running on the built-in BIOS
+        assert!(nes.load_rom(&broken).is_err());
+        assert!(nes.bios_trapped);
+
+        nes.load_rom(&cartridge).unwrap();
+        assert!(!nes.bios_trapped);
+        // the outgoing game's save can't be written over a directory, the disk isn't loaded
+        // and the cartridge keeps running untrapped
+        nes.mbc.write(0x6000, 0x55);
+        nes.save_path = Some(directory.clone());
+        let loaded: Result<(), RomError> = nes.load_rom(&disk);
+        fs::remove_dir_all(&directory).unwrap();
+        fs::remove_file(directory.with_extension("tmp")).unwrap();
+        assert!(loaded.is_err());
+        assert!(!nes.bios_trapped);
+        assert_eq!(nes.disk_sides(), 0);
+    }
+}
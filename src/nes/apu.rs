@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+
+// Only the register file and frame counter are modeled so far, the 2A03's own sound channels
+// are not. Pulse is the 2A03 pulse unit for the expansion chips that copy it.
+
+const FOUR_STEP_PERIOD: u32 = 29830;
+const FIVE_STEP_PERIOD: u32 = 37282;
+
+pub const SAMPLE_RATE: u32 = 44100;
+const CPU_CLOCK: u32 = 1789773;
+// Samples kept when nothing drains them, about a second
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize;
+
+pub const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1]
+];
+
+/// Pulse channel with envelope and length counter but no sweep unit. Periods under 8
+/// are not silenced, which matches MMC5; the 2A03 mutes those through its sweep unit.
+#[derive(Default)]
+pub struct Pulse {
+    enabled: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    pub length: u8,
+    halt: bool, // doubles as the envelope loop flag
+    constant: bool,
+    volume: u8, // constant volume, or the envelope period
+    envelope_start: bool,
+    envelope_divider: u8,
+    envelope_decay: u8
+}
+
+impl Pulse {
+    // Registers 0-3 of the channel ($4000-$4003 layout)
+    pub fn write(&mut self, register: u16, value: u8) {
+        match register & 0b11 {
+            0 => {
+                self.duty = value >> 6;
+                self.halt = value & 0b0010_0000 != 0;
+                self.constant = value & 0b0001_0000 != 0;
+                self.volume = value & 0x0F;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0b111) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[value as usize >> 3];
+                }
+                self.step = 0;
+                self.envelope_start = true;
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    // Clocked every other CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    // Quarter frame clock
+    pub fn clock_envelope(&mut self) {
+        if self.envelope_start {
+            self.envelope_start = false;
+            self.envelope_decay = 15;
+            self.envelope_divider = self.volume;
+        } else if self.envelope_divider == 0 {
+            self.envelope_divider = self.volume;
+            if self.envelope_decay > 0 {
+                self.envelope_decay -= 1;
+            } else if self.halt {
+                self.envelope_decay = 15;
+            }
+        } else {
+            self.envelope_divider -= 1;
+        }
+    }
+
+    // Half frame clock
+    pub fn clock_length(&mut self) {
+        if !self.halt && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.length == 0 || DUTY_TABLE[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else if self.constant {
+            self.volume
+        } else {
+            self.envelope_decay
+        }
+    }
+}
+
+pub struct Apu {
+    pub registers: [u8; 0x18],
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    cycle: u32,
+
+    // mixed output at SAMPLE_RATE, waiting to be taken by the frontend
+    pub samples: VecDeque<f32>,
+    sample_clock: u32
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            registers: [0; 0x18],
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            cycle: 0,
+            samples: VecDeque::new(),
+            sample_clock: 0
+        }
+    }
+
+    // $4015, reading acknowledges the frame interrupt
+    pub fn read_status(&mut self) -> u8 {
+        let status: u8 = if self.frame_irq { 0b0100_0000 } else { 0 };
+        self.frame_irq = false;
+        status
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8) {
+        self.registers[(address - 0x4000) as usize] = value;
+        if address == 0x4017 {
+            self.five_step = value & 0b1000_0000 != 0;
+            self.irq_inhibit = value & 0b0100_0000 != 0;
+            if self.irq_inhibit {
+                self.frame_irq = false;
+            }
+            self.cycle = 0;
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq
+    }
+
+    // Level of the 2A03's own channels, silent until they're emulated
+    pub fn output(&self) -> f32 {
+        0.0
+    }
+
+    // Counts CPU cycles against the sample rate, true on the cycles where a sample should be mixed
+    pub fn sample_due(&mut self) -> bool {
+        self.sample_clock += SAMPLE_RATE;
+        if self.sample_clock >= CPU_CLOCK {
+            self.sample_clock -= CPU_CLOCK;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Adds a sample of the console's output, the 2A03 mixed with the cartridge's expansion audio
+    pub fn mix(&mut self, expansion: f32) {
+        // when nothing drains the buffer the oldest samples make room
+        if self.samples.len() >= MAX_BUFFERED_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(self.output() + expansion);
+    }
+
+    // Called once per CPU cycle
+    pub fn step(&mut self) {
+        self.cycle += 1;
+        if self.five_step {
+            if self.cycle >= FIVE_STEP_PERIOD {
+                self.cycle = 0;
+            }
+        } else if self.cycle >= FOUR_STEP_PERIOD {
+            self.cycle = 0;
+            if !self.irq_inhibit {
+                self.frame_irq = true;
+            }
+        }
+    }
+}
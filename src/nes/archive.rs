@@ -0,0 +1,129 @@
+use std::io::{
+    Cursor,
+    Read
+};
+use std::path::{
+    Path,
+    PathBuf
+};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::nes::rom::RomError;
+
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+// Entries picked out of a zip when no name is given
+const ROM_EXTENSIONS: [&str; 4] = ["nes", "fds", "qd", "unf"];
+
+fn has_rom_extension(name: &str) -> bool {
+    Path::new(name).extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| ROM_EXTENSIONS.iter().any(|known| known.eq_ignore_ascii_case(extension)))
+}
+
+// `entry` matches either the full path inside the zip or just its file name
+fn entry_matches(name: &str, entry: &str) -> bool {
+    name == entry || Path::new(name).file_name().is_some_and(|file_name| file_name == entry)
+}
+
+/// Unpacks ROMs stored in .zip and .gz archives. Returns the ROM data along with the path the
+/// ROM would have on its own (the entry's name next to the zip, the .gz path without ".gz"),
+/// which is what the format and save file are worked out from. Anything else passes through.
+pub fn unpack(path: &Path, data: Vec<u8>, entry: Option<&str>) -> Result<(PathBuf, Vec<u8>), RomError> {
+    if data.starts_with(&ZIP_MAGIC) {
+        let mut archive: ZipArchive<Cursor<Vec<u8>>> = ZipArchive::new(Cursor::new(data))?;
+        let name: String = (0..archive.len())
+            .filter_map(|index| archive.name_for_index(index))
+            .find(|name| match entry {
+                Some(entry) => entry_matches(name, entry),
+                None => has_rom_extension(name)
+            })
+            .map(str::to_string)
+            .ok_or_else(|| RomError::MissingArchiveEntry(entry.map(str::to_string)))?;
+
+        let mut rom: Vec<u8> = Vec::new();
+        archive.by_name(&name)?.read_to_end(&mut rom)?;
+        let file_name: &str = Path::new(&name).file_name().and_then(|file_name| file_name.to_str()).unwrap_or(&name);
+        Ok((path.with_file_name(file_name), rom))
+    } else if data.starts_with(&GZIP_MAGIC) {
+        let mut rom: Vec<u8> = Vec::new();
+        GzDecoder::new(data.as_slice()).read_to_end(&mut rom)?;
+        let is_gz: bool = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gz"));
+        Ok((if is_gz { path.with_extension("") } else { path.to_path_buf() }, rom))
+    } else {
+        Ok((path.to_path_buf(), data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer: ZipWriter<Cursor<Vec<u8>>> = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn zip_picks_the_first_rom() {
+        let data: Vec<u8> = zip(&[("readme.txt", b"hello"), ("music.nsf", b"NESM"), ("games/b.NES", b"b"), ("a.fds", b"a")]);
+        let (path, rom) = unpack(Path::new("/roms/pack.zip"), data, None).unwrap();
+        assert_eq!(path, Path::new("/roms/b.NES"));
+        assert_eq!(rom, b"b");
+    }
+
+    #[test]
+    fn zip_named_entry() {
+        let data: Vec<u8> = zip(&[("games/a.nes", b"a"), ("games/b.nes", b"b")]);
+        let (path, rom) = unpack(Path::new("/roms/pack.zip"), data.clone(), Some("b.nes")).unwrap();
+        assert_eq!(path, Path::new("/roms/b.nes"));
+        assert_eq!(rom, b"b");
+
+        let (_, rom) = unpack(Path::new("/roms/pack.zip"), data, Some("games/a.nes")).unwrap();
+        assert_eq!(rom, b"a");
+    }
+
+    #[test]
+    fn zip_missing_entry() {
+        let data: Vec<u8> = zip(&[("a.nes", b"a"), ("music.nsf", b"NESM")]);
+        let result = unpack(Path::new("pack.zip"), data, Some("c.nes"));
+        assert!(matches!(result, Err(RomError::MissingArchiveEntry(Some(entry))) if entry == "c.nes"));
+
+        let data: Vec<u8> = zip(&[("readme.txt", b"hello"), ("music.nsf", b"NESM")]);
+        let result = unpack(Path::new("pack.zip"), data, None);
+        assert!(matches!(result, Err(RomError::MissingArchiveEntry(None))));
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let rom: Vec<u8> = (0..=255).cycle().take(0x6010).collect();
+        let mut encoder: GzEncoder<Vec<u8>> = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&rom).unwrap();
+        let data: Vec<u8> = encoder.finish().unwrap();
+
+        let (path, unpacked) = unpack(Path::new("/roms/game.nes.gz"), data.clone(), None).unwrap();
+        assert_eq!(path, Path::new("/roms/game.nes"));
+        assert_eq!(unpacked, rom);
+
+        // gzipped data under another name keeps its path
+        let (path, _) = unpack(Path::new("/roms/game.nes"), data, None).unwrap();
+        assert_eq!(path, Path::new("/roms/game.nes"));
+
+        let (path, plain) = unpack(Path::new("/roms/game.nes"), rom.clone(), None).unwrap();
+        assert_eq!(path, Path::new("/roms/game.nes"));
+        assert_eq!(plain, rom);
+    }
+}
@@ -0,0 +1,35 @@
+pub const BUTTON_A: u8 = 1 << 0;
+pub const BUTTON_B: u8 = 1 << 1;
+pub const BUTTON_SELECT: u8 = 1 << 2;
+pub const BUTTON_START: u8 = 1 << 3;
+pub const BUTTON_UP: u8 = 1 << 4;
+pub const BUTTON_DOWN: u8 = 1 << 5;
+pub const BUTTON_LEFT: u8 = 1 << 6;
+pub const BUTTON_RIGHT: u8 = 1 << 7;
+
+/// Standard controller: a parallel-in serial-out shift register latched by $4016.
+#[derive(Clone, Copy, Default)]
+pub struct Controller {
+    pub buttons: u8,
+    shift: u8,
+    strobe: bool
+}
+
+impl Controller {
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return 0x40 | (self.buttons & 1);
+        }
+        let bit: u8 = self.shift & 1;
+        // official controllers return 1s once all eight buttons are shifted out
+        self.shift = (self.shift >> 1) | 0x80;
+        0x40 | bit
+    }
+}
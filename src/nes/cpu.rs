@@ -34,12 +34,50 @@ impl CpuFlags {
         self.zero = false;
         self.carry = 0;
     }
+
+    // Status register as it's pushed to the stack, bit 5 always reads back set
+    pub fn to_byte(&self, break_flag: bool) -> u8 {
+        (self.negative as u8) << 7 | (self.overflow as u8) << 6 | 1 << 5 | (break_flag as u8) << 4 |
+            (self.decimal as u8) << 3 | (self.interrupt_disable as u8) << 2 | (self.zero as u8) << 1 | self.carry
+    }
+
+    pub fn set_from_byte(&mut self, status_reg: u8) {
+        self.negative = status_reg & 0b1000_0000 != 0;
+        self.overflow = status_reg & 0b0100_0000 != 0;
+        self.decimal = status_reg & 0b0000_1000 != 0;
+        self.interrupt_disable = status_reg & 0b0000_0100 != 0;
+        self.zero = status_reg & 0b0000_0010 != 0;
+        self.carry = status_reg & 0b0000_0001;
+    }
 }
 
 impl Cpu {
     pub fn step_pc(&mut self, amount: u16) {
         self.pc = self.pc.wrapping_add(amount);
     }
+    // Pushes PC and status the same way JSR/PHP do, then jumps through the given vector
+    fn interrupt(&mut self, memory: &mut Mbc, vector: u16) -> u32 {
+        self.sp = self.sp.wrapping_sub(2);
+        memory.write_u16(self.sp as u16, self.pc);
+        memory.write(self.sp.wrapping_sub(1) as u16, self.flags.to_byte(false));
+        self.sp = self.sp.wrapping_sub(1);
+        self.flags.interrupt_disable = true;
+        self.pc = memory.read_u16(vector);
+        7
+    }
+
+    pub fn nmi(&mut self, memory: &mut Mbc) -> u32 {
+        self.interrupt(memory, 0xFFFA)
+    }
+
+    // Services a pending IRQ unless the interrupt disable flag masks it, returns the cycles taken
+    pub fn irq(&mut self, memory: &mut Mbc) -> u32 {
+        if self.flags.interrupt_disable {
+            return 0;
+        }
+        self.interrupt(memory, 0xFFFE)
+    }
+
     pub fn step(&mut self, memory: &mut Mbc) -> u32 {
         let opcode: u8 = memory.read(self.pc);
 
@@ -109,7 +147,7 @@ impl Cpu {
                 self.flags.zero = self.a == 0;
                 println!("ADC A,{:02X}", memory.read(address + self.x as u16));
                 self.step_pc(2);
-                if (address & 0x00FF + self.x as u16) > 0xFF {
+                if ((address & 0x00FF) + self.x as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -126,7 +164,7 @@ impl Cpu {
                 self.flags.zero = self.a == 0;
                 println!("ADC A,{:02X}", memory.read(address + self.y as u16));
                 self.step_pc(2);
-                if (address & 0x00FF + self.y as u16) > 0xFF {
+                if ((address & 0x00FF) + self.y as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -226,7 +264,7 @@ impl Cpu {
                 self.flags.zero = self.a == 0;
                 println!("SBC A,{:02X}", memory.read(address + self.x as u16));
                 self.step_pc(2);
-                if (address & 0x00FF + self.x as u16) > 0xFF {
+                if ((address & 0x00FF) + self.x as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -243,7 +281,7 @@ impl Cpu {
                 self.flags.zero = self.a == 0;
                 println!("SBC A,{:02X}", memory.read(address + self.y as u16));
                 self.step_pc(2);
-                if (address & 0x00FF + self.y as u16) > 0xFF {
+                if ((address & 0x00FF) + self.y as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -322,7 +360,7 @@ impl Cpu {
                 self.flags.zero = self.a == 0;
                 println!("AND A,{:02X}", memory.read(address + self.x as u16));
                 self.step_pc(2);
-                if (address & 0x00FF + self.x as u16) > 0xFF {
+                if ((address & 0x00FF) + self.x as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -335,7 +373,7 @@ impl Cpu {
                 self.flags.zero = self.a == 0;
                 println!("AND A,{:02X}", memory.read(address + self.y as u16));
                 self.step_pc(2);
-                if (address & 0x00FF + self.y as u16) > 0xFF {
+                if ((address & 0x00FF) + self.y as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -653,7 +691,7 @@ impl Cpu {
                 self.flags.zero = self.a == 0;
                 println!("ORA 0x{:02X}", memory.read(address + self.x as u16));
                 self.step_pc(2);
-                if (address & 0x00FF + self.x as u16) > 0xFF {
+                if ((address & 0x00FF) + self.x as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -666,7 +704,7 @@ impl Cpu {
                 self.flags.zero = self.a == 0;
                 println!("ORA 0x{:02X}", memory.read(address + self.y as u16));
                 self.step_pc(2);
-                if (address & 0x00FF + self.y as u16) > 0xFF {
+                if ((address & 0x00FF) + self.y as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -739,7 +777,7 @@ impl Cpu {
                 self.flags.zero = self.a == 0;
                 println!("EOR 0x{:02X}", memory.read(address + self.x as u16));
                 self.step_pc(2);
-                if (address & 0x00FF + self.x as u16) > 0xFF {
+                if ((address & 0x00FF) + self.x as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -752,7 +790,7 @@ impl Cpu {
                 self.flags.zero = self.a == 0;
                 println!("EOR 0x{:02X}", memory.read(address + self.y as u16));
                 self.step_pc(2);
-                if (address & 0x00FF + self.y as u16) > 0xFF {
+                if ((address & 0x00FF) + self.y as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -932,6 +970,15 @@ impl Cpu {
                 println!("RTS");
                 6
             }
+            /*-------------------------------RTI-------------------------------------*/
+            0x40 => {
+                let status_reg: u8 = memory.read(self.sp as u16);
+                self.flags.set_from_byte(status_reg);
+                self.pc = memory.read_u16(self.sp.wrapping_add(1) as u16);
+                self.sp = self.sp.wrapping_add(3);
+                println!("RTI");
+                6
+            }
             /*-----------------------------------------------------------------------*/
 
             /*-------------------------------PHA-------------------------------------*/
@@ -1032,7 +1079,7 @@ impl Cpu {
                 let start_page: u8 = ((self.pc & 0xFF00) >> 8) as u8;
                 let offset: u8  = memory.read(self.pc);
                 self.step_pc(1);
-                self.pc = if self.flags.zero == false { self.pc.wrapping_add(offset as u16) } else { self.pc };
+                self.pc = if !self.flags.zero { self.pc.wrapping_add(offset as u16) } else { self.pc };
                 let end_page: u8 = ((self.pc & 0xFF00) >> 8) as u8;
 
                 if start_page == end_page { // if branch stays on current page
@@ -1046,7 +1093,7 @@ impl Cpu {
                 let start_page: u8 = ((self.pc & 0x00FF) >> 8) as u8;
                 let offset: u8  = memory.read(self.pc);
                 self.step_pc(1);
-                self.pc = if self.flags.negative == false { self.pc.wrapping_add(offset as u16) } else { self.pc };
+                self.pc = if !self.flags.negative { self.pc.wrapping_add(offset as u16) } else { self.pc };
                 let end_page: u8 = ((self.pc & 0x00FF) >> 8) as u8;
 
                 if start_page == end_page { // if branch stays on current page
@@ -1060,7 +1107,7 @@ impl Cpu {
                 let start_page: u8 = ((self.pc & 0x00FF) >> 8) as u8;
                 let offset: u8  = memory.read(self.pc);
                 self.step_pc(1);
-                self.pc = if self.flags.overflow == false { self.pc.wrapping_add(offset as u16) } else { self.pc };
+                self.pc = if !self.flags.overflow { self.pc.wrapping_add(offset as u16) } else { self.pc };
                 let end_page: u8 = ((self.pc & 0x00FF) >> 8) as u8;
 
                 if start_page == end_page { // if branch stays on current page
@@ -1204,7 +1251,7 @@ impl Cpu {
                     self.flags.negative = (res & 0b1000_0000) != 0;
                 }
                 self.step_pc(2);
-                if (address & 0x00FF + self.x as u16) > 0xFF {
+                if ((address & 0x00FF) + self.x as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -1228,7 +1275,7 @@ impl Cpu {
                     self.flags.negative = (res & 0b1000_0000) != 0;
                 }
                 self.step_pc(2);
-                if (address & 0x00FF + self.y as u16) > 0xFF {
+                if ((address & 0x00FF) + self.y as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -1451,7 +1498,7 @@ impl Cpu {
                 self.flags.negative = (self.a & 0b1000_0000) != 0;
                 self.flags.zero = self.a == 0;
                 self.step_pc(2);
-                if (address & 0x00FF + self.x as u16) > 0xFF {
+                if ((address & 0x00FF) + self.x as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -1463,7 +1510,7 @@ impl Cpu {
                 self.flags.negative = (self.a & 0b1000_0000) != 0;
                 self.flags.zero = self.a == 0;
                 self.step_pc(2);
-                if (address & 0x00FF + self.y as u16) > 0xFF {
+                if ((address & 0x00FF) + self.y as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -1529,7 +1576,7 @@ impl Cpu {
                 self.flags.negative = (self.x & 0b1000_0000) != 0;
                 self.flags.zero = self.x == 0;
                 self.step_pc(2);
-                if (address & 0x00FF + self.y as u16) > 0xFF {
+                if ((address & 0x00FF) + self.y as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -1574,7 +1621,7 @@ impl Cpu {
                 self.flags.negative = (self.y & 0b1000_0000) != 0;
                 self.flags.zero = self.y == 0;
                 self.step_pc(2);
-                if (address & 0x00FF + self.x as u16) > 0xFF {
+                if ((address & 0x00FF) + self.x as u16) > 0xFF {
                     5
                 } else{
                     4
@@ -0,0 +1,114 @@
+use crate::nes::rom::RomError;
+
+pub const SIDE_SIZE: usize = 65500;
+const QUICK_DISK_SIDE_SIZE: usize = 0x10000;
+const HEADER_SIZE: usize = 16;
+
+const MAGIC: [u8; 4] = *b"FDS\x1A";
+// Every side starts with the disk info block: block type 1 and the "*NINTENDO-HVC*" signature
+const SIDE_MAGIC: &[u8; 15] = b"\x01*NINTENDO-HVC*";
+
+/// Famicom Disk System image. Sides are kept in the .fds layout: the blocks of the side one
+/// after another without gaps or CRCs, zero padded to 65500 bytes.
+pub struct Disk {
+    pub sides: Vec<Vec<u8>>
+}
+
+// Length of the block at the start of `block`, None past the last one. File data blocks take
+// their size from the file header block before them, which is tracked in `file_size`.
+pub fn block_length(block: &[u8], file_size: &mut usize) -> Option<usize> {
+    match block.first()? {
+        1 => Some(56),
+        2 => Some(2),
+        3 if block.len() >= 16 => {
+            *file_size = block[13] as usize | (block[14] as usize) << 8;
+            Some(16)
+        }
+        4 => Some(1 + *file_size),
+        _ => None
+    }
+}
+
+impl Disk {
+    pub fn is_disk_image(data: &[u8]) -> bool {
+        data.starts_with(&MAGIC) || data.starts_with(SIDE_MAGIC)
+    }
+
+    // .fds images come with or without a 16 byte header, .qd images (`quick_disk`) have
+    // 64 KB sides that keep the CRC after every block
+    pub fn from_bytes(data: &[u8], quick_disk: bool) -> Result<Self, RomError> {
+        let data: &[u8] = if data.starts_with(&MAGIC) { &data[HEADER_SIZE.min(data.len())..] } else { data };
+        let side_size: usize = if quick_disk { QUICK_DISK_SIDE_SIZE } else { SIDE_SIZE };
+
+        let mut sides: Vec<Vec<u8>> = Vec::new();
+        for (index, side) in data.chunks(side_size).enumerate() {
+            if !side.starts_with(SIDE_MAGIC) {
+                return Err(RomError::InvalidDiskSide(index));
+            }
+            let mut side: Vec<u8> = if quick_disk { strip_crcs(side) } else { side.to_vec() };
+            side.resize(SIDE_SIZE, 0);
+            sides.push(side);
+        }
+        if sides.is_empty() {
+            return Err(RomError::NoDiskSides);
+        }
+        Ok(Self { sides })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.sides.concat()
+    }
+}
+
+fn strip_crcs(side: &[u8]) -> Vec<u8> {
+    let mut stripped: Vec<u8> = Vec::with_capacity(SIDE_SIZE);
+    let mut position: usize = 0;
+    let mut file_size: usize = 0;
+    while let Some(length) = block_length(&side[position..], &mut file_size) {
+        let end: usize = (position + length).min(side.len());
+        stripped.extend_from_slice(&side[position..end]);
+        position = (end + 2).min(side.len());
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Disk info block, file amount block, and the header and data blocks of one 4 byte file
+    fn blocks() -> Vec<Vec<u8>> {
+        let mut info: Vec<u8> = SIDE_MAGIC.to_vec();
+        info.resize(56, 0);
+        let mut header: Vec<u8> = vec![3; 16];
+        header[13..15].copy_from_slice(&4u16.to_le_bytes());
+        vec![info, vec![2, 1], header, vec![4, 0xDE, 0xAD, 0xBE, 0xEF]]
+    }
+
+    #[test]
+    fn quick_disk_sides_lose_their_crcs() {
+        let mut fds: Vec<u8> = MAGIC.to_vec();
+        fds.resize(HEADER_SIZE, 0);
+        fds.extend(blocks().concat());
+        fds.resize(HEADER_SIZE + SIDE_SIZE, 0);
+
+        let mut quick_disk: Vec<u8> = Vec::new();
+        for block in blocks() {
+            quick_disk.extend(block);
+            quick_disk.extend([0x12, 0x34]);
+        }
+        quick_disk.resize(QUICK_DISK_SIDE_SIZE, 0);
+
+        let fds: Disk = Disk::from_bytes(&fds, false).unwrap();
+        let quick_disk: Disk = Disk::from_bytes(&quick_disk, true).unwrap();
+        assert_eq!(quick_disk.sides, fds.sides);
+        assert_eq!(quick_disk.sides[0].len(), SIDE_SIZE);
+    }
+
+    #[test]
+    fn rejects_images_without_sides() {
+        let mut header: Vec<u8> = MAGIC.to_vec();
+        header.resize(HEADER_SIZE, 0);
+        assert!(matches!(Disk::from_bytes(&header, false), Err(RomError::NoDiskSides)));
+    }
+}
@@ -0,0 +1,437 @@
+use crate::nes::{
+    cpu::Cpu,
+    disk::{
+        block_length,
+        SIDE_SIZE
+    },
+    mbc::Mbc
+};
+
+pub const BIOS_START: u16 = 0xE000;
+pub const BIOS_SIZE: usize = 0x2000;
+
+// Entry points games call, at the addresses the real BIOS has them
+const DELAY_132: u16 = 0xE149;
+const DELAY_MS: u16 = 0xE153;
+const DISABLE_PF_OBJ: u16 = 0xE161;
+const ENABLE_PF_OBJ: u16 = 0xE16B;
+const DISABLE_OBJ: u16 = 0xE170;
+const ENABLE_OBJ: u16 = 0xE178;
+const DISABLE_PF: u16 = 0xE17E;
+const ENABLE_PF: u16 = 0xE185;
+const NMI: u16 = 0xE18A;
+const VINT_WAIT: u16 = 0xE1B2;
+const VINT_WAIT_LOOP: u16 = 0xE1B3; // where VINTWait idles until the NMI comes
+const IRQ: u16 = 0xE1C7;
+const LOAD_FILES: u16 = 0xE1F8;
+const APPEND_FILE: u16 = 0xE237;
+const WRITE_FILE: u16 = 0xE239;
+const ADJUST_FILE_COUNT: u16 = 0xE2BB;
+const SET_FILE_COUNT_1: u16 = 0xE301;
+const SET_FILE_COUNT: u16 = 0xE305;
+const SPRITE_DMA: u16 = 0xE9C8;
+const READ_PADS: u16 = 0xE9EB;
+const READ_DOWN_PADS: u16 = 0xEA1A;
+const READ_OR_DOWN_PADS: u16 = 0xEA1F;
+const READ_DOWN_VERIFY_PADS: u16 = 0xEA36;
+const READ_OR_DOWN_VERIFY_PADS: u16 = 0xEA4C;
+const SET_SCROLL: u16 = 0xEAEA;
+const RESET: u16 = 0xEE24;
+
+// Game vectors at the end of PRG RAM the BIOS hands interrupts and the reset off to
+const GAME_NMI_VECTORS: [u16; 3] = [0xDFF6, 0xDFF8, 0xDFFA];
+const GAME_RESET_VECTOR: u16 = 0xDFFC;
+const GAME_IRQ_VECTOR: u16 = 0xDFFE;
+
+// Zero page copies of $2000/$2001/$2005 and the $0100/$0101 interrupt action flags
+const PPU_CTRL_MIRROR: u16 = 0xFF;
+const PPU_MASK_MIRROR: u16 = 0xFE;
+const SCROLL_X_MIRROR: u16 = 0xFD;
+const SCROLL_Y_MIRROR: u16 = 0xFC;
+const NMI_ACTION: u16 = 0x0100;
+const IRQ_ACTION: u16 = 0x0101;
+
+// Error codes returned in A
+const DISK_NOT_SET: u8 = 0x01;
+const DISK_ID_MISMATCH: [u8; 10] = [0x04, 0x05, 0x05, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B];
+const DISK_FULL: u8 = 0x30;
+
+// Where the pad routines leave the buttons: $F5/$F6 what ReadPads read or the newly pressed
+// buttons for the "down" variants, which keep what they read in $F7/$F8, and $00/$01 what the
+// expansion port read
+const PADS: u16 = 0xF5;
+const HELD_PADS: u16 = 0xF7;
+const EXPANSION_PADS: u16 = 0x00;
+
+const DISK_ID_OFFSET: usize = 0x0F;
+const BOOT_FILE_OFFSET: usize = 0x19;
+
+const CYCLES_PER_MS: u32 = 1790;
+
+// Stand-in BIOS image, only the vectors matter since execution in it is trapped
+pub fn rom() -> Vec<u8> {
+    let mut rom: Vec<u8> = vec![0; BIOS_SIZE];
+    for (vector, target) in [(0xFFFA, NMI), (0xFFFC, RESET), (0xFFFE, IRQ)] {
+        let offset: usize = vector - BIOS_START as usize;
+        rom[offset..offset + 2].copy_from_slice(&target.to_le_bytes());
+    }
+    rom
+}
+
+struct DiskFile {
+    header: Vec<u8>, // block 3: number, ID, name, load address, size, type
+    data: Vec<u8> // block 4 without the block code
+}
+
+struct DiskSide {
+    info: Vec<u8>, // block 1
+    files: Vec<DiskFile>
+}
+
+impl DiskSide {
+    fn parse(side: &[u8]) -> Option<Self> {
+        let mut file_size: usize = 0;
+        let mut position: usize = 0;
+        let mut next_block = |code: u8| -> Option<Vec<u8>> {
+            let length: usize = block_length(side.get(position..)?, &mut file_size)?;
+            let block: &[u8] = side.get(position..position + length)?;
+            position += length;
+            if block[0] == code { Some(block.to_vec()) } else { None }
+        };
+
+        let info: Vec<u8> = next_block(1)?;
+        let count: u8 = next_block(2)?[1];
+        let mut files: Vec<DiskFile> = Vec::new();
+        for _ in 0..count {
+            let Some(header) = next_block(3) else { break };
+            let Some(data) = next_block(4) else { break };
+            files.push(DiskFile { header, data: data[1..].to_vec() });
+        }
+        Some(Self { info, files })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut side: Vec<u8> = self.info.clone();
+        side.extend_from_slice(&[2, self.files.len() as u8]);
+        for file in &self.files {
+            side.extend_from_slice(&file.header);
+            side.push(4);
+            side.extend_from_slice(&file.data);
+        }
+        side
+    }
+
+    // Compares the 10 byte disk ID a game passes against the disk info block, $FF matches anything
+    fn check_id(&self, memory: &Mbc, address: u16) -> Result<(), u8> {
+        for (index, error) in DISK_ID_MISMATCH.iter().enumerate() {
+            let expected: u8 = memory.read(address.wrapping_add(index as u16));
+            if expected != 0xFF && expected != self.info[DISK_ID_OFFSET + index] {
+                return Err(*error);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn push(cpu: &mut Cpu, memory: &mut Mbc, value: u8) {
+    memory.write(cpu.sp.wrapping_sub(1) as u16, value);
+    cpu.sp = cpu.sp.wrapping_sub(1);
+}
+
+fn pop(cpu: &mut Cpu, memory: &Mbc) -> u8 {
+    let value: u8 = memory.read(cpu.sp as u16);
+    cpu.sp = cpu.sp.wrapping_add(1);
+    value
+}
+
+// Return address the JSR into the BIOS left on the stack, pointing at its last byte
+fn caller(cpu: &Cpu, memory: &Mbc) -> u16 {
+    memory.read_u16(cpu.sp as u16)
+}
+
+// Inline pointer parameter `index` following the JSR
+fn parameter(cpu: &Cpu, memory: &Mbc, index: u16) -> u16 {
+    memory.read_u16(caller(cpu, memory).wrapping_add(1 + index * 2))
+}
+
+// Returns to the caller, past `parameters` inline pointers
+fn return_from(cpu: &mut Cpu, memory: &Mbc, parameters: u16) {
+    cpu.pc = caller(cpu, memory).wrapping_add(1 + parameters * 2);
+    cpu.sp = cpu.sp.wrapping_add(2);
+}
+
+fn return_from_interrupt(cpu: &mut Cpu, memory: &Mbc) {
+    let status: u8 = pop(cpu, memory);
+    cpu.flags.set_from_byte(status);
+    cpu.pc = memory.read_u16(cpu.sp as u16);
+    cpu.sp = cpu.sp.wrapping_add(2);
+}
+
+fn set_result(cpu: &mut Cpu, error: u8) {
+    cpu.a = error;
+    cpu.flags.zero = error == 0;
+    cpu.flags.negative = error & 0x80 != 0;
+}
+
+fn write_ppu_mask(memory: &mut Mbc, and: u8, or: u8) {
+    let mask: u8 = (memory.read(PPU_MASK_MIRROR) & and) | or;
+    memory.write(PPU_MASK_MIRROR, mask);
+    memory.write(0x2001, mask);
+}
+
+fn write_ppu_ctrl(memory: &mut Mbc, value: u8) {
+    memory.write(PPU_CTRL_MIRROR, value);
+    memory.write(0x2000, value);
+}
+
+fn set_ppu_address(memory: &mut Mbc, address: u16) {
+    memory.read(0x2002);
+    memory.write(0x2006, (address >> 8) as u8);
+    memory.write(0x2006, address as u8);
+}
+
+fn inserted_side(memory: &Mbc) -> Option<DiskSide> {
+    DiskSide::parse(&memory.cartridge.borrow().disk_side()?)
+}
+
+// Copies a file to where its header says: CPU memory for program files, PPU memory otherwise
+fn load_file(memory: &mut Mbc, file: &DiskFile) {
+    let address: u16 = u16::from_le_bytes([file.header[11], file.header[12]]);
+    if file.header[15] == 0 {
+        for (offset, value) in file.data.iter().enumerate() {
+            memory.write(address.wrapping_add(offset as u16), *value);
+        }
+    } else {
+        set_ppu_address(memory, address);
+        for value in &file.data {
+            memory.write(0x2007, *value);
+        }
+    }
+}
+
+// Loads every file whose ID passes `wanted`, returning the error code and how many were loaded
+fn load_files(memory: &mut Mbc, disk_id: Option<u16>, wanted: impl Fn(u8) -> bool) -> (u8, u8) {
+    let Some(side) = inserted_side(memory) else {
+        return (DISK_NOT_SET, 0);
+    };
+    if let Some(disk_id) = disk_id {
+        if let Err(error) = side.check_id(memory, disk_id) {
+            return (error, 0);
+        }
+    }
+
+    let mut loaded: u8 = 0;
+    for file in side.files.iter().filter(|file| wanted(file.header[2])) {
+        load_file(memory, file);
+        loaded += 1;
+    }
+    (0, loaded)
+}
+
+// Writes a file after the first `number` files of the side, dropping the ones behind it
+fn write_file(memory: &mut Mbc, disk_id: u16, file_header: u16, number: Option<u8>) -> u8 {
+    let Some(mut side) = inserted_side(memory) else {
+        return DISK_NOT_SET;
+    };
+    if let Err(error) = side.check_id(memory, disk_id) {
+        return error;
+    }
+
+    // file ID, name, load address, size and type as they go on the disk, then where the data comes from
+    let fields: Vec<u8> = (0..17).map(|offset| memory.read(file_header.wrapping_add(offset))).collect();
+    let size: u16 = u16::from_le_bytes([fields[11], fields[12]]);
+    let source: u16 = u16::from_le_bytes([fields[14], fields[15]]);
+    let data: Vec<u8> = if fields[16] == 0 {
+        (0..size).map(|offset| memory.read(source.wrapping_add(offset))).collect()
+    } else {
+        set_ppu_address(memory, source);
+        memory.read(0x2007); // the first read only fills the PPU's read buffer
+        (0..size).map(|_| memory.read(0x2007)).collect()
+    };
+
+    let number: usize = number.map_or(side.files.len(), |number| number as usize).min(side.files.len());
+    let mut header: Vec<u8> = vec![3, number as u8];
+    header.extend_from_slice(&fields[..14]);
+    side.files.truncate(number);
+    side.files.push(DiskFile { header, data });
+
+    let bytes: Vec<u8> = side.to_bytes();
+    if bytes.len() > SIDE_SIZE {
+        return DISK_FULL;
+    }
+    memory.cartridge.get_mut().write_disk_side(&bytes);
+    0
+}
+
+fn set_file_count(memory: &mut Mbc, disk_id: u16, count: impl Fn(u8) -> u8) -> u8 {
+    let Some(mut side) = inserted_side(memory) else {
+        return DISK_NOT_SET;
+    };
+    if let Err(error) = side.check_id(memory, disk_id) {
+        return error;
+    }
+    let count: usize = count(side.files.len() as u8) as usize;
+    side.files.truncate(count);
+    memory.cartridge.get_mut().write_disk_side(&side.to_bytes());
+    0
+}
+
+// Both controllers with A in bit 7 down to Right in bit 0, from the pads and the expansion port
+fn read_pads(memory: &mut Mbc) -> ([u8; 2], [u8; 2]) {
+    memory.write(0x4016, 1);
+    memory.write(0x4016, 0);
+    let (mut pads, mut expansion): ([u8; 2], [u8; 2]) = ([0; 2], [0; 2]);
+    for _ in 0..8 {
+        for (port, address) in [0x4016, 0x4017].into_iter().enumerate() {
+            let value: u8 = memory.read(address);
+            pads[port] = pads[port] << 1 | (value & 1);
+            expansion[port] = expansion[port] << 1 | (value >> 1 & 1);
+        }
+    }
+    (pads, expansion)
+}
+
+// Powers up the way the BIOS leaves things for a game: boot files loaded, NMIs going to the
+// game's third vector, then jumps through the game's reset vector. There's no error screen,
+// a disk that doesn't boot just leaves the game's reset vector unset.
+fn reset(cpu: &mut Cpu, memory: &mut Mbc) {
+    memory.write(0x4023, 0x83);
+    memory.write(0x4025, 0x2E);
+    write_ppu_ctrl(memory, 0x10);
+    write_ppu_mask(memory, 0x00, 0x06);
+
+    let boot_file: u8 = inserted_side(memory).map_or(0, |side| side.info[BOOT_FILE_OFFSET]);
+    load_files(memory, None, |id| id <= boot_file);
+
+    memory.write(NMI_ACTION, 0xC0);
+    memory.write(IRQ_ACTION, 0xC0);
+    write_ppu_ctrl(memory, 0x90);
+    cpu.flags.interrupt_disable = true;
+    cpu.pc = memory.read_u16(GAME_RESET_VECTOR);
+}
+
+// Runs the BIOS routine at the CPU's PC in place of the real one and returns the cycles taken,
+// or the address if nothing is emulated there
+pub fn call(cpu: &mut Cpu, memory: &mut Mbc) -> Result<u32, u16> {
+    match cpu.pc {
+        RESET => reset(cpu, memory),
+        NMI => match memory.read(NMI_ACTION) >> 6 {
+            // the NMI VINTWait is waiting for: drop the interrupt and return from VINTWait
+            0 => {
+                let ctrl: u8 = memory.read(PPU_CTRL_MIRROR) & 0x7F;
+                write_ppu_ctrl(memory, ctrl);
+                cpu.sp = cpu.sp.wrapping_add(3);
+                let action: u8 = pop(cpu, memory);
+                memory.write(NMI_ACTION, action);
+                return_from(cpu, memory, 0);
+            }
+            action => cpu.pc = memory.read_u16(GAME_NMI_VECTORS[action as usize - 1])
+        },
+        IRQ => {
+            if memory.read(IRQ_ACTION) >> 6 == 3 {
+                cpu.pc = memory.read_u16(GAME_IRQ_VECTOR);
+            } else {
+                memory.read(0x4030);
+                return_from_interrupt(cpu, memory);
+            }
+        }
+        VINT_WAIT => {
+            let action: u8 = memory.read(NMI_ACTION);
+            push(cpu, memory, action);
+            memory.write(NMI_ACTION, 0x00);
+            let ctrl: u8 = memory.read(PPU_CTRL_MIRROR) | 0x80;
+            write_ppu_ctrl(memory, ctrl);
+            cpu.pc = VINT_WAIT_LOOP;
+        }
+        VINT_WAIT_LOOP => return Ok(3),
+        DELAY_132 => {
+            return_from(cpu, memory, 0);
+            return Ok(132);
+        }
+        DELAY_MS => {
+            return_from(cpu, memory, 0);
+            return Ok(cpu.y as u32 * CYCLES_PER_MS);
+        }
+        DISABLE_PF_OBJ | ENABLE_PF_OBJ | DISABLE_OBJ | ENABLE_OBJ | DISABLE_PF | ENABLE_PF => {
+            let (and, or): (u8, u8) = match cpu.pc {
+                DISABLE_PF_OBJ => (0xE7, 0x00),
+                ENABLE_PF_OBJ => (0xFF, 0x18),
+                DISABLE_OBJ => (0xEF, 0x00),
+                ENABLE_OBJ => (0xFF, 0x10),
+                DISABLE_PF => (0xF7, 0x00),
+                _ => (0xFF, 0x08)
+            };
+            write_ppu_mask(memory, and, or);
+            cpu.a = memory.read(PPU_MASK_MIRROR);
+            return_from(cpu, memory, 0);
+        }
+        LOAD_FILES => {
+            let disk_id: u16 = parameter(cpu, memory, 0);
+            let list: u16 = parameter(cpu, memory, 1);
+            // up to 20 file IDs, ended by $FF
+            let ids: Vec<u8> = (0..20).map(|index| memory.read(list.wrapping_add(index)))
+                .take_while(|&id| id != 0xFF)
+                .collect();
+            let (error, loaded) = load_files(memory, Some(disk_id), |id| ids.contains(&id));
+            set_result(cpu, error);
+            cpu.y = loaded;
+            return_from(cpu, memory, 2);
+        }
+        APPEND_FILE | WRITE_FILE => {
+            let disk_id: u16 = parameter(cpu, memory, 0);
+            let file_header: u16 = parameter(cpu, memory, 1);
+            let number: Option<u8> = if cpu.pc == APPEND_FILE || cpu.a == 0xFF { None } else { Some(cpu.a) };
+            let error: u8 = write_file(memory, disk_id, file_header, number);
+            set_result(cpu, error);
+            return_from(cpu, memory, 2);
+        }
+        ADJUST_FILE_COUNT | SET_FILE_COUNT_1 | SET_FILE_COUNT => {
+            let disk_id: u16 = parameter(cpu, memory, 0);
+            let a: u8 = cpu.a;
+            let error: u8 = match cpu.pc {
+                ADJUST_FILE_COUNT => set_file_count(memory, disk_id, |count| count.saturating_sub(a)),
+                SET_FILE_COUNT_1 => set_file_count(memory, disk_id, |_| a.wrapping_add(1)),
+                _ => set_file_count(memory, disk_id, |_| a)
+            };
+            set_result(cpu, error);
+            return_from(cpu, memory, 1);
+        }
+        SPRITE_DMA => {
+            memory.write(0x2003, 0x00);
+            memory.write(0x4014, 0x02);
+            return_from(cpu, memory, 0);
+        }
+        READ_PADS => {
+            let (pads, expansion) = read_pads(memory);
+            for port in 0..2 {
+                memory.write(PADS + port, pads[port as usize]);
+                memory.write(EXPANSION_PADS + port, expansion[port as usize]);
+            }
+            return_from(cpu, memory, 0);
+        }
+        READ_DOWN_PADS | READ_OR_DOWN_PADS | READ_DOWN_VERIFY_PADS | READ_OR_DOWN_VERIFY_PADS => {
+            // there's no DPCM to corrupt the reads, so verifying changes nothing
+            let or_expansion: bool = matches!(cpu.pc, READ_OR_DOWN_PADS | READ_OR_DOWN_VERIFY_PADS);
+            let (pads, expansion) = read_pads(memory);
+            for port in 0..2 {
+                let held: u8 = if or_expansion { pads[port as usize] | expansion[port as usize] } else { pads[port as usize] };
+                let previous: u8 = memory.read(HELD_PADS + port);
+                memory.write(PADS + port, held & !previous);
+                memory.write(HELD_PADS + port, held);
+            }
+            return_from(cpu, memory, 0);
+        }
+        SET_SCROLL => {
+            memory.read(0x2002);
+            let (x, y, ctrl): (u8, u8, u8) = (memory.read(SCROLL_X_MIRROR), memory.read(SCROLL_Y_MIRROR), memory.read(PPU_CTRL_MIRROR));
+            memory.write(0x2005, x);
+            memory.write(0x2005, y);
+            memory.write(0x2000, ctrl);
+            return_from(cpu, memory, 0);
+        }
+        // anything else, whether a routine the stand-in lacks or a jump into the middle of one,
+        // can't be answered without derailing the game, the caller stops the CPU instead
+        address => return Err(address)
+    }
+    Ok(6)
+}
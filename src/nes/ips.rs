@@ -0,0 +1,118 @@
+const MAGIC: &[u8; 5] = b"PATCH";
+const END: &[u8; 3] = b"EOF";
+const MAX_RECORD_SIZE: usize = 0xFFFF;
+
+/// IPS patch turning `original` into `modified`, both the same length: records of a 3 byte
+/// offset, a 2 byte size and the bytes to put there, between "PATCH" and "EOF".
+pub fn diff(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    let mut patch: Vec<u8> = MAGIC.to_vec();
+    let mut offset: usize = 0;
+    while offset < modified.len() {
+        if original.get(offset) == Some(&modified[offset]) {
+            offset += 1;
+            continue;
+        }
+
+        let start: usize = offset;
+        while offset < modified.len() && offset - start < MAX_RECORD_SIZE && original.get(offset) != Some(&modified[offset]) {
+            offset += 1;
+        }
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((offset - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&modified[start..offset]);
+    }
+    patch.extend_from_slice(END);
+    patch
+}
+
+// Applies the records of an IPS patch, including run length encoded ones, stopping at the
+// first malformed record. Returns false if `patch` isn't an IPS patch at all.
+pub fn apply(data: &mut Vec<u8>, patch: &[u8]) -> bool {
+    if !patch.starts_with(MAGIC) {
+        return false;
+    }
+
+    let mut position: usize = MAGIC.len();
+    while position + 5 <= patch.len() && &patch[position..position + 3] != END {
+        let offset: usize = (patch[position] as usize) << 16 | (patch[position + 1] as usize) << 8 | patch[position + 2] as usize;
+        let size: usize = (patch[position + 3] as usize) << 8 | patch[position + 4] as usize;
+        position += 5;
+
+        let bytes: Vec<u8> = if size == 0 {
+            if position + 3 > patch.len() {
+                break;
+            }
+            let run: usize = (patch[position] as usize) << 8 | patch[position + 1] as usize;
+            let value: u8 = patch[position + 2];
+            position += 3;
+            vec![value; run]
+        } else {
+            if position + size > patch.len() {
+                break;
+            }
+            position += size;
+            patch[position - size..position].to_vec()
+        };
+
+        if data.len() < offset + bytes.len() {
+            data.resize(offset + bytes.len(), 0);
+        }
+        data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Offsets and sizes of the records in a patch made by `diff`
+    fn records(patch: &[u8]) -> Vec<(usize, usize)> {
+        let mut records: Vec<(usize, usize)> = Vec::new();
+        let mut position: usize = MAGIC.len();
+        while &patch[position..position + 3] != END {
+            let offset: usize = (patch[position] as usize) << 16 | (patch[position + 1] as usize) << 8 | patch[position + 2] as usize;
+            let size: usize = (patch[position + 3] as usize) << 8 | patch[position + 4] as usize;
+            records.push((offset, size));
+            position += 5 + size;
+        }
+        records
+    }
+
+    #[test]
+    fn diff_round_trips() {
+        let original: Vec<u8> = (0..0x1000).map(|index| index as u8).collect();
+        let mut modified: Vec<u8> = original.clone();
+        modified[0] = 0xAA;
+        modified[0x100..0x110].fill(0x55);
+        modified[0xFFF] = 0x00;
+
+        let patch: Vec<u8> = diff(&original, &modified);
+        assert_eq!(records(&patch), [(0, 1), (0x100, 0x10), (0xFFF, 1)]);
+        let mut patched: Vec<u8> = original.clone();
+        assert!(apply(&mut patched, &patch));
+        assert_eq!(patched, modified);
+    }
+
+    #[test]
+    fn diff_splits_long_runs() {
+        let original: Vec<u8> = vec![0; 0x20000];
+        let modified: Vec<u8> = (0..0x20000).map(|index| (index % 251 + 1) as u8).collect();
+
+        let patch: Vec<u8> = diff(&original, &modified);
+        assert_eq!(records(&patch), [(0, 0xFFFF), (0xFFFF, 0xFFFF), (0x1FFFE, 2)]);
+        let mut patched: Vec<u8> = original.clone();
+        assert!(apply(&mut patched, &patch));
+        assert_eq!(patched, modified);
+    }
+
+    #[test]
+    fn apply_run_length_records() {
+        let mut data: Vec<u8> = vec![0; 0x10];
+        // a run of 4 0x77 bytes at 2 and a plain record growing the data past its end
+        let patch: &[u8] = b"PATCH\x00\x00\x02\x00\x00\x00\x04\x77\x00\x00\x0F\x00\x02\x01\x02EOF";
+        assert!(apply(&mut data, patch));
+        assert_eq!(data, [0, 0, 0x77, 0x77, 0x77, 0x77, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2]);
+        assert!(!apply(&mut data, b"NOTIPS"));
+    }
+}
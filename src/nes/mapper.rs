@@ -0,0 +1,267 @@
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring,
+    RomError
+};
+
+pub mod action53;
+pub mod axrom;
+pub mod ay8910;
+pub mod bandai_fcg;
+pub mod bnrom;
+pub mod camerica;
+pub mod cnrom;
+pub mod discrete_latch;
+pub mod eeprom;
+pub mod fds;
+pub mod fds_audio;
+pub mod flash;
+pub mod fme7;
+pub mod four_screen;
+pub mod gtrom;
+pub mod irem_g101;
+pub mod irem_h3001;
+pub mod mmc1;
+pub mod mmc2;
+pub mod mmc3;
+pub mod mmc5;
+pub mod namco108;
+pub mod namco163;
+pub mod nrom;
+pub mod opll;
+pub mod ss88006;
+pub mod sunsoft4;
+pub mod taito;
+pub mod trainer;
+pub mod unrom512;
+pub mod uxrom;
+pub mod vrc4;
+pub mod vrc6;
+pub mod vrc7;
+pub mod vrc_irq;
+
+/// Everything the console can see of a cartridge board. The CPU bus hands every access
+/// in $4020-$FFFF to `cpu_read`/`cpu_write`, the PPU hands pattern table accesses
+/// ($0000-$1FFF) to `ppu_read`/`ppu_write`.
+pub trait Mapper {
+    fn cpu_read(&mut self, address: u16) -> u8;
+    fn cpu_write(&mut self, address: u16, value: u8);
+    fn ppu_read(&mut self, address: u16) -> u8;
+    fn ppu_write(&mut self, address: u16, value: u8);
+    fn mirroring(&self) -> Mirroring;
+
+    // IRQ line into the CPU, true while the board is asserting it
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // Nametable accesses the board answers itself (ExRAM, fill mode, extra VRAM...).
+    // `None`/`false` leaves them to the console's nametable RAM, paged by `mirroring`.
+    fn nametable_read(&mut self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    fn nametable_write(&mut self, _address: u16, _value: u8) -> bool {
+        false
+    }
+
+    // Pattern table slots the board maps to a page (0/1) of the console's nametable RAM
+    fn chr_ciram_page(&self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    // CPU writes to $2000-$3FFF, for boards that snoop the PPU's registers
+    fn ppu_register_write(&mut self, _address: u16, _value: u8) {}
+
+    // Called with every address the PPU drives onto its bus: pattern, nametable and palette
+    // accesses while rendering as well as $2006/$2007 accesses from the CPU
+    fn ppu_address(&mut self, _address: u16) {}
+
+    // Called once per CPU cycle
+    fn cpu_cycle(&mut self) {}
+
+    // Called once per rendered scanline
+    fn scanline(&mut self) {}
+
+    // Current level of the board's expansion audio, roughly on the scale of the 2A03's own output (0.0-1.0)
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    // Battery backed memory the board wants persisted, if any
+    fn save_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn load_save_ram(&mut self, _data: &[u8]) {}
+
+    // Disk drive of boards that have one: how many disk sides there are and which one is in
+    // the drive. Inserting `None` ejects the disk.
+    fn disk_sides(&self) -> usize {
+        0
+    }
+
+    fn inserted_disk(&self) -> Option<usize> {
+        None
+    }
+
+    fn insert_disk(&mut self, _side: Option<usize>) {}
+
+    // Contents of the inserted disk side in the .fds layout, for the built-in BIOS to work on
+    fn disk_side(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn write_disk_side(&mut self, _data: &[u8]) {}
+}
+
+/// Board used before a ROM is loaded, reads back nothing and ignores writes.
+pub struct NoCartridge;
+
+impl Mapper for NoCartridge {
+    fn cpu_read(&mut self, _address: u16) -> u8 { 0 }
+    fn cpu_write(&mut self, _address: u16, _value: u8) {}
+    fn ppu_read(&mut self, _address: u16) -> u8 { 0 }
+    fn ppu_write(&mut self, _address: u16, _value: u8) {}
+    fn mirroring(&self) -> Mirroring { Mirroring::Horizontal }
+}
+
+/// Pattern memory of a board: CHR ROM when the image has any, otherwise writable CHR RAM.
+pub struct Chr {
+    pub data: Vec<u8>,
+    pub writable: bool
+}
+
+impl Chr {
+    pub fn new(cartridge: &Cartridge) -> Self {
+        if cartridge.chr_rom.is_empty() {
+            // NES 2.0 headers give the CHR RAM size, iNES ones leave it at the usual 8 KB
+            let size: usize = (cartridge.header.chr_ram_size + cartridge.header.chr_nvram_size).max(0x2000);
+            Self { data: vec![0; size], writable: true }
+        } else {
+            Self { data: cartridge.chr_rom.clone(), writable: false }
+        }
+    }
+
+    pub fn read(&self, bank: usize, bank_size: usize, address: u16) -> u8 {
+        self.data[bank_offset(self.data.len(), bank, bank_size, address)]
+    }
+
+    pub fn write(&mut self, bank: usize, bank_size: usize, address: u16, value: u8) {
+        if self.writable {
+            let offset: usize = bank_offset(self.data.len(), bank, bank_size, address);
+            self.data[offset] = value;
+        }
+    }
+}
+
+/// Offset of `address` inside `bank` (of `bank_size` bytes), wrapped to the size of the memory
+/// the way an unconnected high bank line would.
+pub fn bank_offset(len: usize, bank: usize, bank_size: usize, address: u16) -> usize {
+    (bank * bank_size + (address as usize & (bank_size - 1))) % len
+}
+
+// Bank number `from_end` banks back from the end of the memory (1 for the last one) for the
+// banks boards fix to the top of the address space, wrapped around on images that are smaller
+pub fn bank_from_end(len: usize, bank_size: usize, from_end: usize) -> usize {
+    let banks: usize = len.div_ceil(bank_size).max(1);
+    (banks - from_end % banks) % banks
+}
+
+// Work RAM at $6000-$7FFF, sized from the header (battery backed or not)
+pub fn prg_ram(cartridge: &Cartridge) -> Vec<u8> {
+    vec![0; cartridge.header.prg_ram_size + cartridge.header.prg_nvram_size]
+}
+
+// NES 2.0 submapper 2 marks discrete boards where the ROM also drives the data bus during
+// register writes, so the latched value is ANDed with the byte at the write address
+pub fn bus_conflicts(cartridge: &Cartridge) -> bool {
+    cartridge.header.submapper == 2
+}
+
+// Copies a save file back into battery backed memory, tolerating size mismatches
+pub fn restore(memory: &mut [u8], data: &[u8]) {
+    let size: usize = data.len().min(memory.len());
+    memory[..size].copy_from_slice(&data[..size]);
+}
+
+// Known NES 2.0 submappers and how the boards behind them differ. Submapper 0 means the
+// header doesn't say, the mapper then either guesses from the ROM sizes or accepts all variants.
+pub const SUBMAPPERS: [(u16, u8, &str); 24] = [
+    (2, 1, "UxROM without bus conflicts"),
+    (2, 2, "UxROM with bus conflicts"),
+    (3, 1, "CNROM without bus conflicts"),
+    (3, 2, "CNROM with bus conflicts"),
+    (4, 1, "MMC6, 1 KB of protectable RAM at $7000"),
+    (4, 4, "MMC3A/Sharp, old style IRQ that doesn't fire on reload to 0"),
+    (7, 1, "AxROM without bus conflicts"),
+    (7, 2, "AxROM with bus conflicts"),
+    (16, 4, "Bandai FCG-1/2, registers at $6000-$7FFF and no EEPROM"),
+    (16, 5, "Bandai LZ93D50, registers at $8000-$FFFF and a 24C02 EEPROM"),
+    (21, 1, "VRC4a, registers on A1/A2"),
+    (21, 2, "VRC4c, registers on A6/A7"),
+    (23, 1, "VRC4f, registers on A0/A1"),
+    (23, 2, "VRC4e, registers on A2/A3"),
+    (23, 3, "VRC2b, registers on A0/A1"),
+    (25, 1, "VRC4b, registers on A1/A0"),
+    (25, 2, "VRC4d, registers on A3/A2"),
+    (25, 3, "VRC2c, registers on A1/A0"),
+    (32, 1, "Major League, single screen mirroring and no PRG mode"),
+    (34, 1, "NINA-001, registers at $7FFD-$7FFF and 4 KB CHR banks"),
+    (34, 2, "BNROM, 32 KB PRG register at $8000-$FFFF with bus conflicts"),
+    (71, 1, "Fire Hawk, single screen select at $8000-$9FFF"),
+    (85, 1, "VRC7b, registers on A3"),
+    (85, 2, "VRC7a, registers on A4")
+];
+
+pub fn submapper_name(mapper: u16, submapper: u8) -> Option<&'static str> {
+    SUBMAPPERS.iter()
+        .find(|(number, sub, _)| *number == mapper && *sub == submapper)
+        .map(|(_, _, name)| *name)
+}
+
+pub fn from_cartridge(mut cartridge: Cartridge) -> Result<Box<dyn Mapper>, RomError> {
+    let four_screen: bool = cartridge.header.mirroring == Mirroring::FourScreen;
+    let trainer: Option<Vec<u8>> = cartridge.trainer.take();
+    let mut board: Box<dyn Mapper> = board(cartridge)?;
+    if four_screen {
+        board = Box::new(four_screen::FourScreen::new(board));
+    }
+    if let Some(trainer) = trainer {
+        board = Box::new(trainer::Trainer::new(board, trainer));
+    }
+    Ok(board)
+}
+
+fn board(cartridge: Cartridge) -> Result<Box<dyn Mapper>, RomError> {
+    match cartridge.header.mapper {
+        0 => Ok(Box::new(nrom::Nrom::new(cartridge))),
+        1 => Ok(Box::new(mmc1::Mmc1::new(cartridge, false))),
+        2 => Ok(Box::new(uxrom::Uxrom::new(cartridge))),
+        3 => Ok(Box::new(cnrom::Cnrom::new(cartridge))),
+        4 | 118 | 119 => Ok(Box::new(mmc3::Mmc3::new(cartridge))),
+        5 => Ok(Box::new(mmc5::Mmc5::new(cartridge))),
+        7 => Ok(Box::new(axrom::Axrom::new(cartridge))),
+        9 | 10 => Ok(Box::new(mmc2::Mmc2::new(cartridge))),
+        11 | 66 | 79 | 140 => Ok(Box::new(discrete_latch::DiscreteLatch::new(cartridge))),
+        16 | 153 | 159 => Ok(Box::new(bandai_fcg::BandaiFcg::new(cartridge))),
+        18 => Ok(Box::new(ss88006::Ss88006::new(cartridge))),
+        19 => Ok(Box::new(namco163::Namco163::new(cartridge))),
+        21 | 22 | 23 | 25 => Ok(Box::new(vrc4::Vrc4::new(cartridge))),
+        24 | 26 => Ok(Box::new(vrc6::Vrc6::new(cartridge))),
+        28 => Ok(Box::new(action53::Action53::new(cartridge))),
+        30 => Ok(Box::new(unrom512::Unrom512::new(cartridge))),
+        32 => Ok(Box::new(irem_g101::IremG101::new(cartridge))),
+        33 | 80 => Ok(Box::new(taito::Taito::new(cartridge))),
+        34 => Ok(Box::new(bnrom::Bnrom::new(cartridge))),
+        65 => Ok(Box::new(irem_h3001::IremH3001::new(cartridge))),
+        68 => Ok(Box::new(sunsoft4::Sunsoft4::new(cartridge))),
+        69 => Ok(Box::new(fme7::Fme7::new(cartridge))),
+        71 => Ok(Box::new(camerica::Camerica::new(cartridge))),
+        85 => Ok(Box::new(vrc7::Vrc7::new(cartridge))),
+        111 => Ok(Box::new(gtrom::Gtrom::new(cartridge))),
+        155 => Ok(Box::new(mmc1::Mmc1::new(cartridge, true))),
+        206 => Ok(Box::new(namco108::Namco108::new(cartridge))),
+        mapper => Err(RomError::UnsupportedMapper(mapper))
+    }
+}
@@ -0,0 +1,112 @@
+use crate::nes::mapper::{
+    bank_offset,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 28, the Action 53 multicart board. A register select port at $5000-$5FFF and a data
+/// port at $8000-$FFFF reach four registers: CHR bank, inner PRG bank, mode and outer PRG bank.
+/// The outer bank picks a game's block of up to 256 KB, the mode says how big that block is and
+/// whether the game inside banks like NROM, BNROM or UNROM.
+pub struct Action53 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+
+    select: u8,
+    chr_bank: u8,
+    inner_bank: u8,
+    mode: u8, // mirroring (bits 0-1), PRG bank mode (bits 2-3), game size (bits 4-5)
+    outer_bank: u8
+}
+
+impl Action53 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let mut chr: Chr = Chr::new(&cartridge);
+        if chr.writable {
+            chr.data.resize(0x8000, 0);
+        }
+
+        Self {
+            chr,
+            select: 0,
+            chr_bank: 0,
+            inner_bank: 0,
+            mode: 0,
+            // powers up in the last 32 KB where the menu lives
+            outer_bank: 0xFF,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    // 16 KB bank at `address`: the outer bank supplies the bits above the game size,
+    // the inner bank the ones below unless this half is fixed by the UNROM modes
+    fn prg_bank(&self, address: u16) -> usize {
+        let half: usize = (address as usize >> 14) & 1;
+        let outer: usize = (self.outer_bank as usize) << 1;
+        let bank_mode: u8 = (self.mode >> 2) & 0b11;
+        let mask: usize = (2 << ((self.mode >> 4) & 0b11)) - 1;
+
+        let inner: usize = match bank_mode {
+            0 | 1 => (self.inner_bank as usize) << 1 | half,
+            // UNROM with $8000 fixed, or the usual $C000 fixed
+            2 | 3 if half == bank_mode as usize & 1 => return outer | half,
+            _ => self.inner_bank as usize
+        };
+        (outer & !mask) | (inner & mask)
+    }
+
+    fn one_screen(&self) -> bool {
+        self.mode & 0b10 == 0
+    }
+
+    fn write_data(&mut self, value: u8) {
+        // one screen games pick their page with bit 4 of the CHR or PRG bank writes
+        if self.select & 0x80 == 0 && self.one_screen() {
+            self.mode = (self.mode & !1) | (value >> 4) & 1;
+        }
+        match self.select & 0x81 {
+            0x00 => self.chr_bank = value & 0b11,
+            0x01 => self.inner_bank = value & 0x0F,
+            0x80 => self.mode = value & 0x3F,
+            _ => self.outer_bank = value
+        }
+    }
+}
+
+impl Mapper for Action53 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => self.prg_rom[bank_offset(self.prg_rom.len(), self.prg_bank(address), 0x4000, address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5FFF => self.select = value,
+            0x8000..=0xFFFF => self.write_data(value),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_bank as usize, 0x2000, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_bank as usize, 0x2000, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mode & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal
+        }
+    }
+}
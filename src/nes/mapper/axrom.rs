@@ -0,0 +1,57 @@
+use crate::nes::mapper::{
+    bank_offset,
+    bus_conflicts,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 7, one switchable 32 KB bank and a register bit picking which
+/// nametable the whole screen uses.
+pub struct Axrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    bus_conflicts: bool,
+    bank: u8
+}
+
+impl Axrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            chr: Chr::new(&cartridge),
+            bus_conflicts: bus_conflicts(&cartridge),
+            bank: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => self.prg_rom[bank_offset(self.prg_rom.len(), (self.bank & 0x0F) as usize, 0x8000, address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.bank = if self.bus_conflicts { value & self.cpu_read(address) } else { value };
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(0, 0x2000, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(0, 0x2000, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.bank & 0b1_0000 != 0 { Mirroring::SingleScreenUpper } else { Mirroring::SingleScreenLower }
+    }
+}
@@ -0,0 +1,318 @@
+// Tone, noise and envelope counters all run off the clock divided by 16
+const PRESCALER_CYCLES: u8 = 16;
+
+// Each 5 bit level is 1.5 dB apart, the 4 bit channel volumes skip every other one
+const LEVEL_STEP_DB: f32 = 1.5;
+// Output of one channel at full level
+const OUTPUT_SCALE: f32 = 0.12;
+
+/// AY-3-8910 compatible PSG as found in the Sunsoft 5B: three square wave channels that can
+/// each mix in the shared noise generator, with fixed volumes or the shared envelope.
+pub struct Ay8910 {
+    address: u8,
+    registers: [u8; 0x10],
+    levels: [f32; 32],
+    prescaler: u8,
+
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+
+    noise_counter: u16,
+    noise_shift: u32, // 17 bit LFSR
+
+    envelope_counter: u16,
+    envelope_step: u8,
+    envelope_holding: bool,
+    envelope_attack: bool
+}
+
+impl Ay8910 {
+    pub fn new() -> Self {
+        let mut levels: [f32; 32] = [0.0; 32];
+        for (level, output) in levels.iter_mut().enumerate().skip(1) {
+            *output = 10f32.powf((level as f32 - 31.0) * LEVEL_STEP_DB / 20.0);
+        }
+
+        Self {
+            address: 0,
+            registers: [0; 0x10],
+            levels,
+            prescaler: 0,
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_counter: 0,
+            noise_shift: 1,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_holding: false,
+            envelope_attack: false
+        }
+    }
+
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        // the upper address bits act as a chip select
+        if self.address & 0xF0 != 0 {
+            return;
+        }
+        self.registers[self.address as usize] = value;
+        if self.address == 0x0D {
+            self.envelope_counter = 0;
+            self.envelope_step = 0;
+            self.envelope_holding = false;
+            self.envelope_attack = value & 0b0100 != 0;
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        (self.registers[channel * 2] as u16 | (self.registers[channel * 2 + 1] as u16 & 0x0F) << 8).max(1)
+    }
+
+    fn noise_period(&self) -> u16 {
+        (self.registers[6] as u16 & 0x1F).max(1)
+    }
+
+    fn envelope_period(&self) -> u16 {
+        (self.registers[0x0B] as u16 | (self.registers[0x0C] as u16) << 8).max(1)
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack { self.envelope_step } else { 31 - self.envelope_step }
+    }
+
+    // Shape bits: continue, attack, alternate, hold
+    fn step_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+
+        let shape: u8 = self.registers[0x0D];
+        if shape & 0b1000 == 0 {
+            // one shot shapes drop to silence and stay there
+            self.envelope_attack = false;
+            self.envelope_holding = true;
+        } else if shape & 0b0001 != 0 {
+            if shape & 0b0010 != 0 {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_holding = true;
+        } else {
+            if shape & 0b0010 != 0 {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        self.prescaler += 1;
+        if self.prescaler < PRESCALER_CYCLES {
+            return;
+        }
+        self.prescaler = 0;
+
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_period(channel) {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        // the noise generator shifts at half the rate of a tone with the same period
+        self.noise_counter += 1;
+        if self.noise_counter >= self.noise_period() * 2 {
+            self.noise_counter = 0;
+            let feedback: u32 = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+            self.noise_shift = (self.noise_shift >> 1) | feedback << 16;
+        }
+
+        self.envelope_counter += 1;
+        if self.envelope_counter >= self.envelope_period() {
+            self.envelope_counter = 0;
+            self.step_envelope();
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        let mixer: u8 = self.registers[7];
+        let noise: bool = self.noise_shift & 1 != 0;
+
+        let mut output: f32 = 0.0;
+        for channel in 0..3 {
+            let tone_disabled: bool = mixer & (1 << channel) != 0;
+            let noise_disabled: bool = mixer & (0b1000 << channel) != 0;
+            if !((self.tone_outputs[channel] || tone_disabled) && (noise || noise_disabled)) {
+                continue;
+            }
+
+            let volume: u8 = self.registers[8 + channel];
+            let level: u8 = if volume & 0x10 != 0 {
+                self.envelope_level()
+            } else if volume & 0x0F == 0 {
+                0
+            } else {
+                (volume & 0x0F) * 2 + 1
+            };
+            output += self.levels[level as usize];
+        }
+        output * OUTPUT_SCALE
+    }
+}
+
+impl Default for Ay8910 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(psg: &mut Ay8910, register: u8, value: u8) {
+        psg.write_address(register);
+        psg.write_data(value);
+    }
+
+    fn clock(psg: &mut Ay8910, cycles: u32) {
+        for _ in 0..cycles {
+            psg.clock();
+        }
+    }
+
+    // Envelope levels after each of `steps` envelope periods of 16 cycles
+    fn envelope(shape: u8, steps: usize) -> Vec<u8> {
+        let mut psg = Ay8910::new();
+        write(&mut psg, 0x0B, 1);
+        write(&mut psg, 0x0D, shape);
+        let mut levels: Vec<u8> = vec![psg.envelope_level()];
+        for _ in 0..steps {
+            clock(&mut psg, PRESCALER_CYCLES as u32);
+            levels.push(psg.envelope_level());
+        }
+        levels
+    }
+
+    #[test]
+    fn tone_period() {
+        let mut psg = Ay8910::new();
+        write(&mut psg, 0, 0x02);
+        write(&mut psg, 1, 0x01);
+        write(&mut psg, 7, 0b11_1110);
+        write(&mut psg, 8, 0x0F);
+        assert_eq!(psg.output(), 0.0);
+
+        // the square flips every period * 16 cycles
+        clock(&mut psg, 0x102 * 16 - 1);
+        assert_eq!(psg.output(), 0.0);
+        clock(&mut psg, 1);
+        assert_eq!(psg.output(), OUTPUT_SCALE);
+        clock(&mut psg, 0x102 * 16);
+        assert_eq!(psg.output(), 0.0);
+    }
+
+    #[test]
+    fn volume_levels() {
+        let mut psg = Ay8910::new();
+        // tone and noise both off leaves the channel high
+        write(&mut psg, 7, 0b11_1111);
+        write(&mut psg, 8, 0x0F);
+        assert_eq!(psg.output(), OUTPUT_SCALE);
+
+        // each volume step is 3 dB
+        write(&mut psg, 8, 0x0E);
+        assert!((psg.output() / OUTPUT_SCALE - 10f32.powf(-3.0 / 20.0)).abs() < 1e-6);
+        write(&mut psg, 8, 0x07);
+        assert!((psg.output() / OUTPUT_SCALE - 10f32.powf(-24.0 / 20.0)).abs() < 1e-6);
+        write(&mut psg, 8, 0x00);
+        assert_eq!(psg.output(), 0.0);
+
+        // all three channels add up
+        for channel in 8..11 {
+            write(&mut psg, channel, 0x0F);
+        }
+        assert!((psg.output() - 3.0 * OUTPUT_SCALE).abs() < 1e-6);
+    }
+
+    #[test]
+    fn noise() {
+        let mut psg = Ay8910::new();
+        write(&mut psg, 6, 1);
+        write(&mut psg, 7, 0b11_0111);
+        write(&mut psg, 8, 0x0F);
+        assert_eq!(psg.output(), OUTPUT_SCALE);
+
+        // shifts every 2 * period * 16 cycles
+        clock(&mut psg, 31);
+        assert_eq!(psg.output(), OUTPUT_SCALE);
+        clock(&mut psg, 1);
+        assert_eq!(psg.output(), 0.0);
+
+        // the 17 bit LFSR runs through every non-zero state before repeating, half of them odd
+        let mut psg = Ay8910::new();
+        write(&mut psg, 6, 1);
+        let mut highs: u32 = 0;
+        for shift in 1..(1 << 17) {
+            highs += psg.noise_shift & 1;
+            clock(&mut psg, 32);
+            assert_eq!(psg.noise_shift == 1, shift == (1 << 17) - 1);
+        }
+        assert_eq!(highs, 1 << 16);
+    }
+
+    #[test]
+    fn envelope_shapes() {
+        let decay: Vec<u8> = (0..32).rev().collect();
+        let attack: Vec<u8> = (0..32).collect();
+
+        // \___
+        assert_eq!(envelope(0x00, 33), [decay.as_slice(), &[0, 0]].concat());
+        // /___
+        assert_eq!(envelope(0x04, 33), [attack.as_slice(), &[0, 0]].concat());
+        // \\\\
+        assert_eq!(envelope(0x08, 63), [decay.as_slice(), &decay].concat());
+        // \/\/
+        assert_eq!(envelope(0x0A, 63), [decay.as_slice(), &attack].concat());
+        // \¯¯¯
+        assert_eq!(envelope(0x0B, 33), [decay.as_slice(), &[31, 31]].concat());
+        // /¯¯¯
+        assert_eq!(envelope(0x0D, 33), [attack.as_slice(), &[31, 31]].concat());
+        // /___ again through the hold bit
+        assert_eq!(envelope(0x0F, 33), [attack.as_slice(), &[0, 0]].concat());
+    }
+
+    #[test]
+    fn envelope_drives_the_volume() {
+        let mut psg = Ay8910::new();
+        write(&mut psg, 7, 0b11_1111);
+        write(&mut psg, 8, 0x10);
+        write(&mut psg, 0x0B, 2);
+        write(&mut psg, 0x0D, 0x0D);
+        assert_eq!(psg.output(), 0.0);
+        clock(&mut psg, 32 * 31);
+        assert_eq!(psg.output(), OUTPUT_SCALE);
+
+        // writing the shape restarts it
+        write(&mut psg, 0x0D, 0x00);
+        assert_eq!(psg.output(), OUTPUT_SCALE);
+        clock(&mut psg, 32);
+        assert!(psg.output() < OUTPUT_SCALE);
+    }
+
+    #[test]
+    fn upper_address_bits_deselect_the_chip() {
+        let mut psg = Ay8910::new();
+        write(&mut psg, 0x18, 0x0F);
+        write(&mut psg, 7, 0b11_1111);
+        assert_eq!(psg.output(), 0.0);
+    }
+}
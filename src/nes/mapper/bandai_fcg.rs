@@ -0,0 +1,201 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    eeprom::{
+        Chip,
+        Eeprom
+    },
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mappers 16, 153 and 159, Bandai's FCG-1/2 and LZ93D50 boards. A 16 KB PRG bank, eight 1 KB
+/// CHR banks and a 16 bit IRQ counter clocked by the CPU. Saves go to a serial EEPROM read and
+/// written through $800D: a 24C02 on mapper 16 and a 24C01 on mapper 159. Mapper 153 has 8 KB of
+/// battery backed work RAM instead and uses the CHR registers to pick a 256 KB PRG half.
+pub struct BandaiFcg {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+    eeprom: Option<Eeprom>,
+    outer_bank: bool, // mapper 153
+    low_registers: bool, // FCG-1/2 registers at $6000-$7FFF
+    high_registers: bool, // LZ93D50 registers at $8000-$FFFF
+
+    chr_banks: [u8; 8],
+    prg_bank: u8,
+    mirroring: u8,
+    control: u8, // $800D: EEPROM clock (bit 5), data (bit 6) and read enable (bit 7), or work RAM enable
+
+    irq_enabled: bool,
+    irq_latch: u16,
+    irq_counter: u16,
+    irq_pending: bool
+}
+
+impl BandaiFcg {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let mapper: u16 = cartridge.header.mapper;
+        let submapper: u8 = cartridge.header.submapper;
+
+        Self {
+            prg_ram: if mapper == 153 { prg_ram(&cartridge) } else { Vec::new() },
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            eeprom: match (mapper, submapper) {
+                (159, _) => Some(Eeprom::new(Chip::X24C01)),
+                (16, 0 | 5) => Some(Eeprom::new(Chip::C24C02)),
+                _ => None
+            },
+            outer_bank: mapper == 153,
+            // submapper 4 is the FCG-1/2, 5 the LZ93D50, 0 could be either so answer on both
+            low_registers: mapper == 16 && submapper != 5,
+            high_registers: mapper != 16 || submapper != 4,
+            chr_banks: [0; 8],
+            prg_bank: 0,
+            mirroring: 0,
+            control: 0,
+            irq_enabled: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_pending: false,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let outer: usize = if self.outer_bank && self.chr_banks[..4].iter().any(|bank| bank & 1 != 0) { 0x10 } else { 0 };
+        let bank: usize = match address {
+            0x8000..=0xBFFF => outer | (self.prg_bank & 0x0F) as usize,
+            _ if self.outer_bank => outer | 0x0F,
+            _ => bank_from_end(self.prg_rom.len(), 0x4000, 1)
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x4000, address)
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.control & 0x20 != 0
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0x0..=0x7 => self.chr_banks[register as usize] = value,
+            0x8 => self.prg_bank = value,
+            0x9 => self.mirroring = value & 0b11,
+            0xA => {
+                self.irq_enabled = value & 1 != 0;
+                self.irq_pending = false;
+                // the LZ93D50 starts counting from the latch, the FCG-1/2 is written directly
+                if self.high_registers {
+                    self.irq_counter = self.irq_latch;
+                }
+            }
+            0xB | 0xC => {
+                let shift: u16 = (register - 0xB) * 8;
+                let value: u16 = (value as u16) << shift;
+                let mask: u16 = !(0xFF << shift);
+                if self.high_registers {
+                    self.irq_latch = (self.irq_latch & mask) | value;
+                } else {
+                    self.irq_counter = (self.irq_counter & mask) | value;
+                }
+            }
+            0xD => {
+                self.control = value;
+                if let Some(eeprom) = &mut self.eeprom {
+                    eeprom.write(value & 0x20 != 0, value & 0x40 != 0);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for BandaiFcg {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[bank_offset(self.prg_ram.len(), 0, 0x2000, address)],
+            // SDA comes back on bit 4 while the host has let go of the line
+            0x6000..=0x7FFF => match &self.eeprom {
+                Some(eeprom) if self.control & 0x80 != 0 => (eeprom.output as u8) << 4,
+                _ => 0
+            },
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let offset: usize = bank_offset(self.prg_ram.len(), 0, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+            0x6000..=0x7FFF if self.low_registers => self.write_register(address & 0x0F, value),
+            0x8000..=0xFFFF if self.high_registers => self.write_register(address & 0x0F, value),
+            _ => {}
+        }
+    }
+
+    // Mapper 153 only has 8 KB of CHR RAM, its CHR registers hold the outer PRG bank
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        if self.outer_bank {
+            self.chr.read(0, 0x2000, address)
+        } else {
+            self.chr.read(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address)
+        }
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.outer_bank {
+            self.chr.write(0, 0x2000, address, value);
+        } else {
+            self.chr.write(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address, value);
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_cycle(&mut self) {
+        if self.irq_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    // The EEPROM holds the save whether or not the header sets the battery bit
+    fn save_ram(&self) -> Option<&[u8]> {
+        match &self.eeprom {
+            Some(eeprom) => Some(&eeprom.data),
+            None if self.battery => Some(&self.prg_ram),
+            None => None
+        }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        match &mut self.eeprom {
+            Some(eeprom) => restore(&mut eeprom.data, data),
+            None => restore(&mut self.prg_ram, data)
+        }
+    }
+}
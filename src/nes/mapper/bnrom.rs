@@ -0,0 +1,154 @@
+use crate::nes::mapper::{
+    bank_offset,
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 34, two unrelated boards sharing a number. BNROM (submapper 2) switches 32 KB of
+/// PRG through $8000-$FFFF with bus conflicts. NINA-001 (submapper 1) has work RAM and
+/// registers at $7FFD-$7FFF for 32 KB of PRG and two 4 KB CHR banks. Old dumps are told
+/// apart by NINA-001 being the one with CHR ROM.
+pub struct Bnrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+    mirroring: Mirroring,
+    nina001: bool,
+
+    prg_bank: u8,
+    chr_banks: [u8; 2]
+}
+
+impl Bnrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let nina001: bool = match cartridge.header.submapper {
+            1 => true,
+            2 => false,
+            _ => !cartridge.chr_rom.is_empty()
+        };
+
+        Self {
+            prg_ram: if nina001 { prg_ram(&cartridge) } else { Vec::new() },
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            mirroring: cartridge.header.mirroring,
+            nina001,
+            prg_bank: 0,
+            chr_banks: [0, 1],
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn chr_bank(&self, address: u16) -> (usize, usize) {
+        if self.nina001 {
+            (self.chr_banks[(address >> 12) as usize & 1] as usize, 0x1000)
+        } else {
+            (0, 0x2000)
+        }
+    }
+}
+
+impl Mapper for Bnrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => self.prg_ram[bank_offset(self.prg_ram.len(), 0, 0x2000, address)],
+            0x8000..=0xFFFF => self.prg_rom[bank_offset(self.prg_rom.len(), self.prg_bank as usize, 0x8000, address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        // NINA-001's registers sit on top of the RAM, which keeps the written value too
+        if self.nina001 {
+            match address {
+                0x7FFD => self.prg_bank = value & 1,
+                0x7FFE => self.chr_banks[0] = value & 0x0F,
+                0x7FFF => self.chr_banks[1] = value & 0x0F,
+                _ => {}
+            }
+        }
+
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let offset: usize = bank_offset(self.prg_ram.len(), 0, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+            0x8000..=0xFFFF if !self.nina001 => self.prg_bank = value & self.cpu_read(address),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        let (bank, size) = self.chr_bank(address);
+        self.chr.read(bank, size, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let (bank, size) = self.chr_bank(address);
+        self.chr.write(bank, size, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 64 KB of PRG and 8 KB of CHR ROM (or RAM), banks filled with their own number
+    fn mapper(chr_rom: bool, submapper: u8) -> Bnrom {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([4, chr_rom as u8, 0x20, 0x28, submapper << 4, 0, 0x07, 0x07, 0, 0, 0, 0]);
+        for bank in 0..2 {
+            rom.extend(vec![bank; 0x8000]);
+        }
+        if chr_rom {
+            rom.extend((0..2).flat_map(|bank| vec![bank; 0x1000]));
+        }
+        Bnrom::new(Cartridge::from_bytes(&rom).unwrap())
+    }
+
+    #[test]
+    fn old_dumps_are_told_apart_by_chr_rom() {
+        let mut nina001 = mapper(true, 0);
+        nina001.cpu_write(0x7FFD, 1);
+        nina001.cpu_write(0x7FFE, 1);
+        assert_eq!((nina001.cpu_read(0x8000), nina001.ppu_read(0x0000)), (1, 1));
+        assert_eq!(nina001.cpu_read(0x7FFE), 1);
+
+        let mut bnrom = mapper(false, 0);
+        bnrom.cpu_write(0x8000, 1);
+        assert_eq!(bnrom.cpu_read(0x8000), 0, "bus conflict with the 0 in ROM");
+        bnrom.cpu_write(0x7FFD, 1);
+        assert_eq!(bnrom.cpu_read(0x8000), 0);
+        assert_eq!(bnrom.cpu_read(0x7FFD), 0, "no work RAM");
+    }
+
+    #[test]
+    fn submapper_overrides_chr_rom() {
+        let mut bnrom = mapper(true, 2);
+        bnrom.cpu_write(0x7FFD, 1);
+        assert_eq!(bnrom.cpu_read(0x8000), 0);
+
+        let mut nina001 = mapper(false, 1);
+        nina001.cpu_write(0x7FFD, 1);
+        assert_eq!(nina001.cpu_read(0x8000), 1);
+    }
+}
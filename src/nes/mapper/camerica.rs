@@ -0,0 +1,66 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 71, Camerica/Codemasters BF909x. A 16 KB PRG bank selected at $C000-$FFFF with the
+/// last bank fixed at $C000. The Fire Hawk board (submapper 1) adds a single screen
+/// select at $8000-$9FFF.
+pub struct Camerica {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    fire_hawk: bool,
+    prg_bank: u8
+}
+
+impl Camerica {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let fire_hawk: bool = cartridge.header.submapper == 1;
+        Self {
+            chr: Chr::new(&cartridge),
+            mirroring: if fire_hawk { Mirroring::SingleScreenLower } else { cartridge.header.mirroring },
+            fire_hawk,
+            prg_bank: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+}
+
+impl Mapper for Camerica {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xBFFF => self.prg_rom[bank_offset(self.prg_rom.len(), self.prg_bank as usize, 0x4000, address)],
+            0xC000..=0xFFFF => self.prg_rom[bank_offset(self.prg_rom.len(), bank_from_end(self.prg_rom.len(), 0x4000, 1), 0x4000, address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF if self.fire_hawk => {
+                self.mirroring = if value & 0b1_0000 != 0 { Mirroring::SingleScreenUpper } else { Mirroring::SingleScreenLower };
+            }
+            0xC000..=0xFFFF => self.prg_bank = value & 0x0F,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(0, 0x2000, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(0, 0x2000, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
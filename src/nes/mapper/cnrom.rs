@@ -0,0 +1,58 @@
+use crate::nes::mapper::{
+    bank_offset,
+    bus_conflicts,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 3, fixed PRG like NROM with a switchable 8 KB CHR bank.
+pub struct Cnrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    chr_bank: u8
+}
+
+impl Cnrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            chr: Chr::new(&cartridge),
+            mirroring: cartridge.header.mirroring,
+            bus_conflicts: bus_conflicts(&cartridge),
+            chr_bank: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => self.prg_rom[bank_offset(self.prg_rom.len(), 0, 0x8000, address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.chr_bank = if self.bus_conflicts { value & self.cpu_read(address) } else { value };
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_bank as usize, 0x2000, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_bank as usize, 0x2000, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
@@ -0,0 +1,141 @@
+use crate::nes::mapper::{
+    bank_offset,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+// How a board decodes its latch and splits it between the banks
+struct Wiring {
+    decode_mask: u16, // the latch is written where address & decode_mask == decode
+    decode: u16,
+    bus_conflicts: bool, // decoded over the PRG ROM, which drives the data bus too
+    prg_shift: u8,
+    prg_mask: u8,
+    chr_shift: u8,
+    chr_mask: u8
+}
+
+fn wiring(mapper: u16) -> Wiring {
+    match mapper {
+        // Color Dreams: $8000-$FFFF, PRG bits 0-1, CHR bits 4-7
+        11 => Wiring { decode_mask: 0x8000, decode: 0x8000, bus_conflicts: true, prg_shift: 0, prg_mask: 0b11, chr_shift: 4, chr_mask: 0x0F },
+        // NINA-03/06: $4100 mirrored through $5FFF wherever A8 is set, PRG bit 3, CHR bits 0-2
+        79 => Wiring { decode_mask: 0xE100, decode: 0x4100, bus_conflicts: false, prg_shift: 3, prg_mask: 0b1, chr_shift: 0, chr_mask: 0b111 },
+        // Jaleco JF-11/JF-14: $6000-$7FFF, PRG bits 4-5, CHR bits 0-3
+        140 => Wiring { decode_mask: 0xE000, decode: 0x6000, bus_conflicts: false, prg_shift: 4, prg_mask: 0b11, chr_shift: 0, chr_mask: 0x0F },
+        // GxROM: $8000-$FFFF, PRG bits 4-5, CHR bits 0-1
+        _ => Wiring { decode_mask: 0x8000, decode: 0x8000, bus_conflicts: true, prg_shift: 4, prg_mask: 0b11, chr_shift: 0, chr_mask: 0b11 }
+    }
+}
+
+/// Mappers 11 (Color Dreams), 66 (GxROM), 79 (AVE NINA-03/06) and 140 (Jaleco JF-11/JF-14):
+/// a single latch selecting a 32 KB PRG bank and an 8 KB CHR bank, which the boards only
+/// differ in the decoding and bit positions of.
+pub struct DiscreteLatch {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    wiring: Wiring,
+    latch: u8
+}
+
+impl DiscreteLatch {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            chr: Chr::new(&cartridge),
+            mirroring: cartridge.header.mirroring,
+            wiring: wiring(cartridge.header.mapper),
+            latch: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_bank(&self) -> usize {
+        ((self.latch >> self.wiring.prg_shift) & self.wiring.prg_mask) as usize
+    }
+
+    fn chr_bank(&self) -> usize {
+        ((self.latch >> self.wiring.chr_shift) & self.wiring.chr_mask) as usize
+    }
+}
+
+impl Mapper for DiscreteLatch {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => self.prg_rom[bank_offset(self.prg_rom.len(), self.prg_bank(), 0x8000, address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address & self.wiring.decode_mask == self.wiring.decode {
+            self.latch = if self.wiring.bus_conflicts { value & self.cpu_read(address) } else { value };
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_bank(), 0x2000, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_bank(), 0x2000, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Four 32 KB PRG banks starting with their number and otherwise 0xFF, so writes further in
+    // don't lose bits to bus conflicts, and sixteen 8 KB CHR banks filled with their number
+    fn board(mapper: u8) -> DiscreteLatch {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([8, 16, (mapper & 0x0F) << 4, mapper & 0xF0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        for bank in 0..4 {
+            rom.push(bank);
+            rom.extend(vec![0xFF; 0x8000 - 1]);
+        }
+        for bank in 0..16 {
+            rom.extend(vec![bank; 0x2000]);
+        }
+        DiscreteLatch::new(Cartridge::from_bytes(&rom).unwrap())
+    }
+
+    fn banks(board: &mut DiscreteLatch) -> (u8, u8) {
+        (board.cpu_read(0x8000), board.ppu_read(0x0000))
+    }
+
+    #[test]
+    fn latch_selects_prg_and_chr_banks() {
+        for (mapper, address, value, expected) in [(11, 0x8001, 0x32, (2, 3)), (66, 0xFFFF, 0x13, (1, 3)), (79, 0x5F00, 0x0D, (1, 5)), (140, 0x7FFF, 0x2A, (2, 10))] {
+            let mut board: DiscreteLatch = board(mapper);
+            board.cpu_write(address, value);
+            assert_eq!(banks(&mut board), expected, "mapper {}", mapper);
+        }
+    }
+
+    #[test]
+    fn latch_ignores_undecoded_addresses() {
+        for (mapper, address) in [(79, 0x4000), (79, 0x6100), (140, 0x8001), (140, 0x5000)] {
+            let mut board: DiscreteLatch = board(mapper);
+            board.cpu_write(address, 0xFF);
+            assert_eq!(banks(&mut board), (0, 0), "mapper {} at ${:04X}", mapper, address);
+        }
+    }
+
+    #[test]
+    fn rom_boards_have_bus_conflicts() {
+        let mut board: DiscreteLatch = board(66);
+        // the ROM drives 0 at $8000 in bank 0
+        board.cpu_write(0x8000, 0x13);
+        assert_eq!(banks(&mut board), (0, 0));
+    }
+}
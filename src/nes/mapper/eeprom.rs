@@ -0,0 +1,281 @@
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    X24C01, // 128 bytes, no device address, bits sent LSB first
+    C24C02 // 256 bytes, standard I2C device address, bits sent MSB first
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    Device,
+    Address,
+    Read,
+    Write,
+    Ack,
+    WaitAck
+}
+
+/// Serial EEPROM bit-banged over I2C: SCL and SDA from the board's registers in, SDA out.
+/// A start condition (SDA falling while SCL is high) begins a transfer, a stop condition (SDA
+/// rising while SCL is high) ends it, data bits are taken on SCL rising edges.
+pub struct Eeprom {
+    pub data: Vec<u8>,
+    chip: Chip,
+    state: State,
+    next_state: State,
+
+    scl: bool,
+    sda: bool,
+    pub output: bool,
+
+    address: usize,
+    shift: u8,
+    bit: u8,
+    acknowledged: bool
+}
+
+impl Eeprom {
+    pub fn new(chip: Chip) -> Self {
+        Self {
+            data: vec![0; match chip { Chip::X24C01 => 0x80, Chip::C24C02 => 0x100 }],
+            chip,
+            state: State::Idle,
+            next_state: State::Idle,
+            scl: false,
+            sda: false,
+            output: true,
+            address: 0,
+            shift: 0,
+            bit: 0,
+            acknowledged: false
+        }
+    }
+
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        if self.scl && scl && self.sda && !sda {
+            self.start();
+        } else if self.scl && scl && !self.sda && sda {
+            self.state = State::Idle;
+            self.output = true;
+        } else if !self.scl && scl {
+            self.rising_edge(sda);
+        } else if self.scl && !scl {
+            self.falling_edge();
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn start(&mut self) {
+        self.state = match self.chip {
+            Chip::X24C01 => State::Address,
+            Chip::C24C02 => State::Device
+        };
+        self.bit = 0;
+        self.shift = 0;
+        self.output = true;
+    }
+
+    fn shift_in(&mut self, value: bool) {
+        if self.bit < 8 {
+            self.shift = match self.chip {
+                Chip::X24C01 => self.shift | (value as u8) << self.bit,
+                Chip::C24C02 => self.shift << 1 | value as u8
+            };
+            self.bit += 1;
+        }
+    }
+
+    fn shift_out(&mut self) {
+        if self.bit < 8 {
+            let byte: u8 = self.data[self.address];
+            let position: u8 = match self.chip {
+                Chip::X24C01 => self.bit,
+                Chip::C24C02 => 7 - self.bit
+            };
+            self.output = byte >> position & 1 != 0;
+            self.bit += 1;
+        }
+    }
+
+    fn acknowledge(&mut self, next_state: State) {
+        self.state = State::Ack;
+        self.next_state = next_state;
+    }
+
+    fn rising_edge(&mut self, sda: bool) {
+        match self.state {
+            State::Device | State::Address | State::Write => self.shift_in(sda),
+            State::Read => self.shift_out(),
+            State::Ack => self.output = false,
+            // the host pulls SDA low to ask for another byte
+            State::WaitAck => self.acknowledged = !sda,
+            State::Idle => {}
+        }
+    }
+
+    fn falling_edge(&mut self) {
+        let size: usize = self.data.len();
+        match self.state {
+            State::Device if self.bit == 8 => {
+                if self.shift & 0xF0 != 0xA0 {
+                    self.state = State::Idle;
+                } else if self.shift & 1 != 0 {
+                    self.acknowledge(State::Read);
+                } else {
+                    self.acknowledge(State::Address);
+                }
+            }
+            // the 24C01 sends a 7 bit address with the read/write bit on top
+            State::Address if self.bit == 8 => match self.chip {
+                Chip::X24C01 => {
+                    self.address = (self.shift & 0x7F) as usize;
+                    self.acknowledge(if self.shift & 0x80 != 0 { State::Read } else { State::Write });
+                }
+                Chip::C24C02 => {
+                    self.address = self.shift as usize;
+                    self.acknowledge(State::Write);
+                }
+            },
+            State::Write if self.bit == 8 => {
+                self.data[self.address] = self.shift;
+                self.address = (self.address + 1) % size;
+                self.acknowledge(State::Write);
+            }
+            State::Read if self.bit == 8 => {
+                self.address = (self.address + 1) % size;
+                self.state = State::WaitAck;
+                self.output = true;
+            }
+            State::Ack => {
+                self.state = self.next_state;
+                self.bit = 0;
+                self.shift = 0;
+                self.output = true;
+            }
+            State::WaitAck => {
+                self.state = if self.acknowledged { State::Read } else { State::Idle };
+                self.bit = 0;
+                self.output = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bit-banging host: SDA only changes while SCL is low, except for start and stop conditions
+    fn start(eeprom: &mut Eeprom) {
+        eeprom.write(false, true);
+        eeprom.write(true, true);
+        eeprom.write(true, false);
+        eeprom.write(false, false);
+    }
+
+    fn stop(eeprom: &mut Eeprom) {
+        eeprom.write(false, false);
+        eeprom.write(true, false);
+        eeprom.write(true, true);
+    }
+
+    // Clocks one bit out of the host, or with SDA released one bit in
+    fn clock(eeprom: &mut Eeprom, sda: bool) -> bool {
+        eeprom.write(false, sda);
+        eeprom.write(true, sda);
+        let bit: bool = eeprom.output;
+        eeprom.write(false, sda);
+        bit
+    }
+
+    fn bit_order(chip: Chip) -> [u8; 8] {
+        match chip {
+            Chip::X24C01 => [0, 1, 2, 3, 4, 5, 6, 7],
+            Chip::C24C02 => [7, 6, 5, 4, 3, 2, 1, 0]
+        }
+    }
+
+    // Sends a byte and returns whether the chip acknowledged it
+    fn send(eeprom: &mut Eeprom, byte: u8) -> bool {
+        for position in bit_order(eeprom.chip) {
+            clock(eeprom, byte >> position & 1 != 0);
+        }
+        !clock(eeprom, true)
+    }
+
+    // Reads a byte, asking for another one after it when `more` is set
+    fn receive(eeprom: &mut Eeprom, more: bool) -> u8 {
+        let mut byte: u8 = 0;
+        for position in bit_order(eeprom.chip) {
+            byte |= (clock(eeprom, true) as u8) << position;
+        }
+        clock(eeprom, !more);
+        byte
+    }
+
+    #[test]
+    fn x24c01_round_trip() {
+        let mut eeprom: Eeprom = Eeprom::new(Chip::X24C01);
+        start(&mut eeprom);
+        assert!(send(&mut eeprom, 0x7E)); // address 0x7E, write
+        for value in [0x12, 0x34, 0x56] {
+            assert!(send(&mut eeprom, value));
+        }
+        stop(&mut eeprom);
+        // sequential writes wrap around the 128 bytes
+        assert_eq!((eeprom.data[0x7E], eeprom.data[0x7F], eeprom.data[0x00]), (0x12, 0x34, 0x56));
+
+        start(&mut eeprom);
+        assert!(send(&mut eeprom, 0x80 | 0x7E)); // address 0x7E, read
+        let read: Vec<u8> = vec![receive(&mut eeprom, true), receive(&mut eeprom, true), receive(&mut eeprom, false)];
+        stop(&mut eeprom);
+        assert_eq!(read, [0x12, 0x34, 0x56]);
+    }
+
+    #[test]
+    fn c24c02_round_trip() {
+        let mut eeprom: Eeprom = Eeprom::new(Chip::C24C02);
+        start(&mut eeprom);
+        assert!(send(&mut eeprom, 0xA0));
+        assert!(send(&mut eeprom, 0x40));
+        for value in [0xDE, 0xAD, 0xBE, 0xEF] {
+            assert!(send(&mut eeprom, value));
+        }
+        stop(&mut eeprom);
+        assert_eq!(eeprom.data[0x40..0x44], [0xDE, 0xAD, 0xBE, 0xEF]);
+
+        // random read: a dummy write sets the address, a repeated start switches to reading
+        start(&mut eeprom);
+        assert!(send(&mut eeprom, 0xA0));
+        assert!(send(&mut eeprom, 0x41));
+        start(&mut eeprom);
+        assert!(send(&mut eeprom, 0xA1));
+        let read: Vec<u8> = vec![receive(&mut eeprom, true), receive(&mut eeprom, true), receive(&mut eeprom, false)];
+        stop(&mut eeprom);
+        assert_eq!(read, [0xAD, 0xBE, 0xEF]);
+    }
+
+    #[test]
+    fn c24c02_ignores_other_devices() {
+        let mut eeprom: Eeprom = Eeprom::new(Chip::C24C02);
+        start(&mut eeprom);
+        assert!(!send(&mut eeprom, 0xB0));
+        assert!(!send(&mut eeprom, 0x00));
+        assert!(!send(&mut eeprom, 0x55));
+        stop(&mut eeprom);
+        assert!(eeprom.data.iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn c24c02_reads_stop_without_an_ack() {
+        let mut eeprom: Eeprom = Eeprom::new(Chip::C24C02);
+        eeprom.data[0] = 0x5A;
+        start(&mut eeprom);
+        assert!(send(&mut eeprom, 0xA1));
+        assert_eq!(receive(&mut eeprom, false), 0x5A);
+        // the chip lets go of SDA once the host doesn't acknowledge
+        assert_eq!(receive(&mut eeprom, false), 0xFF);
+    }
+}
@@ -0,0 +1,581 @@
+use crate::nes::disk::{
+    block_length,
+    Disk,
+    SIDE_SIZE
+};
+use crate::nes::ips;
+use crate::nes::mapper::{
+    fds_audio::FdsAudio,
+    Mapper
+};
+use crate::nes::rom::Mirroring;
+
+const PRG_RAM_SIZE: usize = 0x8000;
+const CHR_RAM_SIZE: usize = 0x2000;
+
+// The drive moves about one byte past the head every 150 CPU cycles. Sides start with
+// 28300 bits of lead-in and every block is followed by a 976 bit gap before the next one.
+const BYTE_CYCLES: u32 = 150;
+const HEAD_RETURN_CYCLES: u32 = 50000;
+const LEAD_IN_BYTES: usize = 28300 / 8;
+const GAP_BYTES: usize = 976 / 8;
+const GAP_END: u8 = 0x80;
+// A disk swapped by the user reads as ejected for a while so the game notices the change
+const INSERT_DELAY_CYCLES: u32 = 1_000_000;
+
+/// The Famicom Disk System's RAM adaptor: 32 KB of PRG RAM at $6000-$DFFF, the BIOS at
+/// $E000-$FFFF, 8 KB of CHR RAM, a timer IRQ, the wavetable sound channel and the disk
+/// drive, which shifts one byte at a time between the disk and $4024/$4031 and raises an IRQ
+/// for each. Sides are held as the drive sees them, with gaps, start marks and CRCs, and
+/// written back to the .fds layout to keep an IPS patch of what the game saved.
+pub struct Fds {
+    bios: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+
+    original: Vec<u8>, // the sides as loaded, what the patch is taken against
+    sides: Vec<Vec<u8>>,
+    patch: Option<Vec<u8>>, // none until the disk first differs from the original
+    written: bool, // sides changed since the patch was last taken
+    side: Option<usize>,
+    insert_delay: u32,
+
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_repeat: bool,
+    timer_enabled: bool,
+    timer_irq: bool,
+    disk_registers_enabled: bool,
+    sound_registers_enabled: bool,
+
+    control: u8, // $4025: motor (bit 0), transfer reset (bit 1), read mode (bit 2), mirroring (bit 3), CRC (bit 4), transfer (bit 6), IRQ (bit 7)
+    write_data: u8,
+    read_data: u8,
+    transfer_complete: bool,
+    disk_irq: bool,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    position: usize,
+    delay: u32,
+    crc: u16,
+    previous_crc_control: bool,
+
+    audio: FdsAudio
+}
+
+// CRC-16 the drive appends to every block, over the start mark and the block
+fn update_crc(crc: u16, value: u8) -> u16 {
+    let mut crc: u16 = crc;
+    for bit in 0..8 {
+        let carry: bool = crc & 1 != 0;
+        crc >>= 1;
+        if carry {
+            crc ^= 0x8408;
+        }
+        if value & (1 << bit) != 0 {
+            crc ^= 0x8000;
+        }
+    }
+    crc
+}
+
+// Lays a side out the way it passes under the head
+fn raw_side(side: &[u8]) -> Vec<u8> {
+    let mut raw: Vec<u8> = vec![0; LEAD_IN_BYTES];
+    let mut position: usize = 0;
+    let mut file_size: usize = 0;
+    while let Some(length) = block_length(&side[position..], &mut file_size) {
+        let block: &[u8] = &side[position..(position + length).min(side.len())];
+        let crc: u16 = [GAP_END].iter().chain(block).chain(&[0, 0]).fold(0, |crc, &value| update_crc(crc, value));
+        raw.push(GAP_END);
+        raw.extend_from_slice(block);
+        raw.extend_from_slice(&crc.to_le_bytes());
+        raw.resize(raw.len() + GAP_BYTES, 0);
+        position += block.len();
+    }
+    raw.resize(raw.len().max(LEAD_IN_BYTES + SIDE_SIZE), 0);
+    raw
+}
+
+// Picks the blocks back out of a raw side, the opposite of `raw_side`
+fn side_from_raw(raw: &[u8]) -> Vec<u8> {
+    let mut side: Vec<u8> = Vec::with_capacity(SIDE_SIZE);
+    let mut position: usize = 0;
+    let mut file_size: usize = 0;
+    loop {
+        while position < raw.len() && raw[position] == 0 {
+            position += 1;
+        }
+        if position >= raw.len() || raw[position] != GAP_END {
+            break;
+        }
+        position += 1;
+        let Some(length) = block_length(&raw[position..], &mut file_size) else {
+            break;
+        };
+        let end: usize = (position + length).min(raw.len());
+        side.extend_from_slice(&raw[position..end]);
+        position = end + 2;
+    }
+    side.resize(SIDE_SIZE, 0);
+    side
+}
+
+impl Fds {
+    pub fn new(disk: Disk, bios: Vec<u8>) -> Self {
+        Self {
+            bios,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr_ram: vec![0; CHR_RAM_SIZE],
+            original: disk.to_bytes(),
+            sides: disk.sides.iter().map(|side| raw_side(side)).collect(),
+            patch: None,
+            written: false,
+            side: Some(0),
+            insert_delay: 0,
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_repeat: false,
+            timer_enabled: false,
+            timer_irq: false,
+            disk_registers_enabled: false,
+            sound_registers_enabled: false,
+            control: 0,
+            write_data: 0,
+            read_data: 0,
+            transfer_complete: false,
+            disk_irq: false,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            position: 0,
+            delay: 0,
+            crc: 0,
+            previous_crc_control: false,
+            audio: FdsAudio::new()
+        }
+    }
+
+    fn motor_on(&self) -> bool {
+        self.control & 0x01 != 0
+    }
+
+    fn transfer_reset(&self) -> bool {
+        self.control & 0x02 != 0
+    }
+
+    fn read_mode(&self) -> bool {
+        self.control & 0x04 != 0
+    }
+
+    fn crc_control(&self) -> bool {
+        self.control & 0x10 != 0
+    }
+
+    fn transferring(&self) -> bool {
+        self.control & 0x40 != 0
+    }
+
+    fn disk_irq_enabled(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+
+    fn disk_inserted(&self) -> bool {
+        self.side.is_some() && self.insert_delay == 0
+    }
+
+    // Re-takes the patch after the game is done writing
+    fn update_patch(&mut self) {
+        if self.written {
+            self.written = false;
+            let image: Vec<u8> = self.sides.iter().flat_map(|raw| side_from_raw(raw)).collect();
+            // once there is a save an unchanged disk still needs its empty patch written over it
+            if self.patch.is_some() || image != self.original {
+                self.patch = Some(ips::diff(&self.original, &image));
+            }
+        }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.control = value;
+        self.disk_irq = false;
+        if self.read_mode() || !self.motor_on() {
+            self.update_patch();
+        }
+    }
+
+    fn read_status(&mut self) -> u8 {
+        let status: u8 = self.timer_irq as u8
+            | (self.transfer_complete as u8) << 1
+            | (self.end_of_head as u8) << 6;
+        self.timer_irq = false;
+        self.disk_irq = false;
+        self.transfer_complete = false;
+        status
+    }
+
+    fn read_drive_status(&self) -> u8 {
+        let inserted: bool = self.disk_inserted();
+        !inserted as u8 | ((!inserted || !self.scanning) as u8) << 1 | (!inserted as u8) << 2
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.timer_enabled {
+            return;
+        }
+        if self.timer_counter == 0 {
+            self.timer_irq = true;
+            self.timer_counter = self.timer_reload;
+            self.timer_enabled = self.timer_repeat;
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    fn clock_drive(&mut self) {
+        if self.insert_delay > 0 {
+            self.insert_delay -= 1;
+            return;
+        }
+        let Some(side) = self.side else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+        if !self.motor_on() {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        }
+        if self.transfer_reset() && !self.scanning {
+            return;
+        }
+        // the head goes back to the start of the side before the drive starts reading again
+        if self.end_of_head {
+            self.delay = HEAD_RETURN_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let mut irq: bool = self.disk_irq_enabled();
+        if self.read_mode() {
+            let value: u8 = self.sides[side][self.position];
+            if !self.transferring() {
+                self.gap_ended = false;
+            } else if value != 0 && !self.gap_ended {
+                // the start mark ending the gap isn't handed to the CPU
+                self.gap_ended = true;
+                irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = value;
+                self.disk_irq |= irq;
+            }
+        } else {
+            let mut value: u8 = 0;
+            if !self.crc_control() {
+                self.transfer_complete = true;
+                value = self.write_data;
+                self.disk_irq |= irq;
+            }
+            if !self.transferring() {
+                value = 0;
+            }
+            if !self.crc_control() {
+                self.crc = if self.transferring() { update_crc(self.crc, value) } else { 0 };
+            } else {
+                if !self.previous_crc_control {
+                    self.crc = update_crc(update_crc(self.crc, 0), 0);
+                }
+                value = self.crc as u8;
+                self.crc >>= 8;
+            }
+            self.sides[side][self.position] = value;
+            self.written = true;
+            self.gap_ended = false;
+        }
+        self.previous_crc_control = self.crc_control();
+
+        self.position += 1;
+        if self.position >= self.sides[side].len() {
+            self.control &= !0x01;
+            self.update_patch();
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x4030 if self.disk_registers_enabled => self.read_status(),
+            0x4031 if self.disk_registers_enabled => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+                self.read_data
+            }
+            0x4032 if self.disk_registers_enabled => self.read_drive_status(),
+            // external connector, bit 7 reports a good battery
+            0x4033 if self.disk_registers_enabled => 0x80,
+            0x4040..=0x4097 if self.sound_registers_enabled => self.audio.read(address).unwrap_or(0),
+            0x6000..=0xDFFF => self.prg_ram[address as usize - 0x6000],
+            0xE000..=0xFFFF => self.bios[address as usize - 0xE000],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | value as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | (value as u16) << 8,
+            0x4022 => {
+                self.timer_repeat = value & 0x01 != 0;
+                self.timer_enabled = value & 0x02 != 0 && self.disk_registers_enabled;
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_registers_enabled = value & 0x01 != 0;
+                self.sound_registers_enabled = value & 0x02 != 0;
+                if !self.disk_registers_enabled {
+                    self.timer_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if self.disk_registers_enabled => {
+                self.write_data = value;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_registers_enabled => self.write_control(value),
+            0x4040..=0x4097 if self.sound_registers_enabled => self.audio.write(address, value),
+            0x6000..=0xDFFF => self.prg_ram[address as usize - 0x6000] = value,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr_ram[address as usize & 0x1FFF]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr_ram[address as usize & 0x1FFF] = value;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.control & 0x08 != 0 { Mirroring::Horizontal } else { Mirroring::Vertical }
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.clock_timer();
+        self.clock_drive();
+        self.audio.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    // Writes to the disk are kept as an IPS patch against the original image
+    fn save_ram(&self) -> Option<&[u8]> {
+        self.patch.as_deref()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        let mut image: Vec<u8> = self.original.clone();
+        if ips::apply(&mut image, data) {
+            self.sides = image.chunks(SIDE_SIZE).map(raw_side).collect();
+            self.patch = Some(data.to_vec());
+        }
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.sides.len()
+    }
+
+    fn inserted_disk(&self) -> Option<usize> {
+        self.side
+    }
+
+    fn insert_disk(&mut self, side: Option<usize>) {
+        self.update_patch();
+        self.side = side.filter(|&side| side < self.sides.len());
+        self.insert_delay = if self.side.is_some() { INSERT_DELAY_CYCLES } else { 0 };
+    }
+
+    fn disk_side(&self) -> Option<Vec<u8>> {
+        let side: usize = self.side.filter(|_| self.disk_inserted())?;
+        Some(side_from_raw(&self.sides[side]))
+    }
+
+    fn write_disk_side(&mut self, data: &[u8]) {
+        if let Some(side) = self.side {
+            let mut data: Vec<u8> = data.to_vec();
+            data.resize(SIDE_SIZE, 0);
+            self.sides[side] = raw_side(&data);
+            self.written = true;
+            self.update_patch();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sides holding just a disk info block, side number at $15, and an empty file amount block
+    fn mapper(sides: u8) -> Fds {
+        let sides: Vec<Vec<u8>> = (0..sides).map(|number| {
+            let mut side: Vec<u8> = b"\x01*NINTENDO-HVC*".to_vec();
+            side.resize(56, 0);
+            side[0x15] = number;
+            side.extend([2, 0]);
+            side.resize(SIDE_SIZE, 0);
+            side
+        }).collect();
+        let mut fds = Fds::new(Disk { sides }, vec![0; 0x2000]);
+        fds.cpu_write(0x4023, 0x01);
+        fds
+    }
+
+    fn cycles_until_irq(fds: &mut Fds, limit: u32) -> Option<u32> {
+        (1..=limit).find(|_| {
+            fds.cpu_cycle();
+            fds.irq()
+        })
+    }
+
+    #[test]
+    fn drive_reads_a_byte_at_a_time() {
+        let mut fds = mapper(1);
+        assert_eq!(fds.cpu_read(0x4030), 0x40);
+        assert_eq!(fds.cpu_read(0x4032), 0b010);
+
+        // motor on, read mode, transfer and disk IRQ enabled
+        fds.cpu_write(0x4025, 0xC5);
+        // the head returns to the start, passes the lead-in, and the start mark isn't handed over
+        let first_byte: u32 = 2 + HEAD_RETURN_CYCLES + (BYTE_CYCLES + 1) * (LEAD_IN_BYTES as u32 + 1);
+        assert_eq!(cycles_until_irq(&mut fds, first_byte + 1), Some(first_byte));
+        assert_eq!(fds.cpu_read(0x4032), 0b000);
+        assert_eq!(fds.cpu_read(0x4031), 0x01);
+        assert!(!fds.irq());
+
+        assert_eq!(cycles_until_irq(&mut fds, 1000), Some(BYTE_CYCLES + 1));
+        assert_eq!(fds.cpu_read(0x4030), 0x02);
+        assert!(!fds.irq());
+        assert_eq!(fds.cpu_read(0x4031), b'*');
+    }
+
+    #[test]
+    fn drive_transfers_without_irq_when_disabled() {
+        let mut fds = mapper(1);
+        fds.cpu_write(0x4025, 0x45);
+        let first_byte: u32 = 2 + HEAD_RETURN_CYCLES + (BYTE_CYCLES + 1) * (LEAD_IN_BYTES as u32 + 1);
+        assert_eq!(cycles_until_irq(&mut fds, first_byte + 1000), None);
+        assert_eq!(fds.cpu_read(0x4030) & 0x02, 0x02);
+
+        // turning the motor off stops the drive at the end of the head
+        fds.cpu_write(0x4025, 0x44);
+        fds.cpu_cycle();
+        assert_eq!(fds.cpu_read(0x4030), 0x40);
+        assert_eq!(fds.cpu_read(0x4032), 0b010);
+    }
+
+    #[test]
+    fn timer_irq() {
+        let mut fds = mapper(1);
+        fds.cpu_write(0x4020, 10);
+        fds.cpu_write(0x4021, 0);
+        fds.cpu_write(0x4022, 0x02);
+        assert_eq!(cycles_until_irq(&mut fds, 100), Some(11));
+        assert_eq!(fds.cpu_read(0x4030), 0x41);
+        assert!(!fds.irq());
+        // one shot
+        assert_eq!(cycles_until_irq(&mut fds, 100), None);
+
+        fds.cpu_write(0x4022, 0x03);
+        assert_eq!(cycles_until_irq(&mut fds, 100), Some(11));
+        fds.cpu_read(0x4030);
+        assert_eq!(cycles_until_irq(&mut fds, 100), Some(11));
+
+        // disabling the timer or the disk registers acknowledges it
+        fds.cpu_write(0x4022, 0x00);
+        assert!(!fds.irq());
+        fds.cpu_write(0x4022, 0x03);
+        assert_eq!(cycles_until_irq(&mut fds, 100), Some(11));
+        fds.cpu_write(0x4023, 0x00);
+        assert!(!fds.irq());
+        fds.cpu_write(0x4022, 0x03);
+        assert_eq!(cycles_until_irq(&mut fds, 100), None);
+    }
+
+    #[test]
+    fn inserting_disks() {
+        let mut fds = mapper(2);
+        assert_eq!(fds.disk_sides(), 2);
+        assert_eq!(fds.disk_side().map(|side| side[0x15]), Some(0));
+
+        fds.insert_disk(None);
+        assert_eq!(fds.inserted_disk(), None);
+        assert_eq!(fds.cpu_read(0x4032), 0b111);
+        assert_eq!(fds.disk_side(), None);
+
+        // a new side reads as missing until the game has had time to notice the swap
+        fds.insert_disk(Some(1));
+        assert_eq!(fds.inserted_disk(), Some(1));
+        assert_eq!(fds.cpu_read(0x4032), 0b111);
+        for _ in 0..INSERT_DELAY_CYCLES {
+            fds.cpu_cycle();
+        }
+        assert_eq!(fds.cpu_read(0x4032), 0b010);
+        assert_eq!(fds.disk_side().map(|side| side[0x15]), Some(1));
+
+        fds.insert_disk(Some(2));
+        assert_eq!(fds.inserted_disk(), None);
+    }
+
+    #[test]
+    fn saves_only_once_the_disk_changes() {
+        let mut fds = mapper(2);
+        assert_eq!(fds.save_ram(), None);
+
+        let unchanged: Vec<u8> = fds.disk_side().unwrap();
+        fds.write_disk_side(&unchanged);
+        assert_eq!(fds.save_ram(), None);
+
+        let mut changed: Vec<u8> = unchanged.clone();
+        changed[0x20] = 0x42;
+        fds.write_disk_side(&changed);
+        let patch: Vec<u8> = fds.save_ram().unwrap().to_vec();
+        let mut image: Vec<u8> = fds.original.clone();
+        assert!(ips::apply(&mut image, &patch));
+        assert_eq!(image[0x20], 0x42);
+
+        // changing it back writes an empty patch over the old save
+        fds.write_disk_side(&unchanged);
+        assert_eq!(fds.save_ram(), Some(ips::diff(&[], &[]).as_slice()));
+
+        let mut restored = mapper(2);
+        restored.load_save_ram(&patch);
+        assert_eq!(restored.disk_side().map(|side| side[0x20]), Some(0x42));
+        assert_eq!(restored.save_ram(), Some(patch.as_slice()));
+    }
+}
@@ -0,0 +1,380 @@
+// Modulation table steps: how much each 3 bit entry adds to the counter, `None` resets it
+const MODULATION_STEPS: [Option<i8>; 8] = [Some(0), Some(1), Some(2), Some(4), None, Some(-4), Some(-2), Some(-1)];
+// $4089 master volume: 2/2, 2/3, 2/4 and 2/5
+const MASTER_VOLUMES: [f32; 4] = [1.0, 2.0 / 3.0, 0.5, 0.4];
+const MAX_GAIN: u8 = 32;
+// At full volume the channel is about 2.4 times as loud as a 2A03 pulse channel at full volume
+const OUTPUT_SCALE: f32 = 2.4 * 0.1494 / (63.0 * MAX_GAIN as f32);
+
+/// Gain envelope shared by the volume ($4080) and modulation ($4084) units. Disabled
+/// envelopes hold the gain written to them, enabled ones step it up or down to 0-32.
+struct Envelope {
+    speed: u8,
+    increase: bool,
+    disabled: bool,
+    gain: u8,
+    timer: u32
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Self {
+            speed: 0,
+            increase: false,
+            disabled: true,
+            gain: 0,
+            timer: 0
+        }
+    }
+
+    fn write(&mut self, value: u8, master_speed: u8) {
+        self.speed = value & 0x3F;
+        self.increase = value & 0x40 != 0;
+        self.disabled = value & 0x80 != 0;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.reset_timer(master_speed);
+    }
+
+    fn reset_timer(&mut self, master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.reset_timer(master_speed);
+            if self.increase && self.gain < MAX_GAIN {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+}
+
+/// The disk system's sound channel at $4040-$4097: a 64 step, 6 bit wavetable played back at
+/// a 12 bit pitch, bent by a modulation unit that walks a 64 step table of counter adjustments
+/// for vibrato and similar effects.
+pub struct FdsAudio {
+    wave: [u8; 64],
+    wave_write_enabled: bool,
+    wave_halted: bool,
+    wave_frequency: u16,
+    wave_accumulator: u32,
+    wave_position: usize,
+    master_volume: u8,
+    envelopes_halted: bool,
+    envelope_speed: u8, // $408A
+
+    volume: Envelope,
+    modulation: Envelope,
+    modulation_table: [u8; 64],
+    modulation_halted: bool,
+    modulation_frequency: u16,
+    modulation_accumulator: u32,
+    modulation_position: usize,
+    modulation_counter: i8 // 7 bit signed
+}
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        Self {
+            wave: [0; 64],
+            wave_write_enabled: false,
+            wave_halted: true,
+            wave_frequency: 0,
+            wave_accumulator: 0,
+            wave_position: 0,
+            master_volume: 0,
+            envelopes_halted: false,
+            envelope_speed: 0xE8,
+            volume: Envelope::new(),
+            modulation: Envelope::new(),
+            modulation_table: [0; 64],
+            modulation_halted: true,
+            modulation_frequency: 0,
+            modulation_accumulator: 0,
+            modulation_position: 0,
+            modulation_counter: 0
+        }
+    }
+
+    pub fn read(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4040..=0x407F => Some(self.wave[address as usize - 0x4040] | 0x40),
+            0x4090 => Some(self.volume.gain | 0x40),
+            0x4092 => Some(self.modulation.gain | 0x40),
+            _ => None
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x4040..=0x407F if self.wave_write_enabled => self.wave[address as usize - 0x4040] = value & 0x3F,
+            0x4080 => self.volume.write(value, self.envelope_speed),
+            0x4082 => self.wave_frequency = (self.wave_frequency & 0x0F00) | value as u16,
+            0x4083 => {
+                self.wave_frequency = (self.wave_frequency & 0x00FF) | (value as u16 & 0x0F) << 8;
+                self.wave_halted = value & 0x80 != 0;
+                self.envelopes_halted = value & 0x40 != 0;
+                if self.wave_halted {
+                    self.wave_accumulator = 0;
+                    self.wave_position = 0;
+                }
+                if self.envelopes_halted {
+                    self.volume.reset_timer(self.envelope_speed);
+                    self.modulation.reset_timer(self.envelope_speed);
+                }
+            }
+            0x4084 => self.modulation.write(value, self.envelope_speed),
+            0x4085 => self.modulation_counter = ((value & 0x7F) << 1) as i8 >> 1,
+            0x4086 => self.modulation_frequency = (self.modulation_frequency & 0x0F00) | value as u16,
+            0x4087 => {
+                self.modulation_frequency = (self.modulation_frequency & 0x00FF) | (value as u16 & 0x0F) << 8;
+                self.modulation_halted = value & 0x80 != 0;
+                if self.modulation_halted {
+                    self.modulation_accumulator = 0;
+                }
+            }
+            // each write fills two steps of the table, only while the modulator is halted
+            0x4088 if self.modulation_halted => {
+                self.modulation_table[self.modulation_position] = value & 0b111;
+                self.modulation_table[(self.modulation_position + 1) & 0x3F] = value & 0b111;
+                self.modulation_position = (self.modulation_position + 2) & 0x3F;
+            }
+            0x4089 => {
+                self.wave_write_enabled = value & 0x80 != 0;
+                self.master_volume = value & 0b11;
+            }
+            0x408A => self.envelope_speed = value,
+            _ => {}
+        }
+    }
+
+    fn step_modulation(&mut self) {
+        let step: u8 = self.modulation_table[self.modulation_position];
+        self.modulation_position = (self.modulation_position + 1) & 0x3F;
+        self.modulation_counter = match MODULATION_STEPS[step as usize] {
+            // wraps around inside 7 bits
+            Some(adjust) => ((self.modulation_counter.wrapping_add(adjust) as u8) << 1) as i8 >> 1,
+            None => 0
+        };
+    }
+
+    // Wave pitch bent by the modulation counter scaled by the modulation gain, with the
+    // rounding the hardware does
+    fn modulated_frequency(&self) -> u32 {
+        let mut offset: i32 = self.modulation_counter as i32 * self.modulation.gain as i32;
+        let remainder: i32 = offset & 0x0F;
+        offset >>= 4;
+        if remainder > 0 && offset & 0x80 == 0 {
+            offset += if self.modulation_counter < 0 { -1 } else { 2 };
+        }
+        if offset >= 192 {
+            offset -= 256;
+        } else if offset < -64 {
+            offset += 256;
+        }
+
+        offset *= self.wave_frequency as i32;
+        let remainder: i32 = offset & 0x3F;
+        offset >>= 6;
+        if remainder >= 32 {
+            offset += 1;
+        }
+        (self.wave_frequency as i32 + offset).max(0) as u32
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        if !self.wave_halted && !self.envelopes_halted {
+            self.volume.clock(self.envelope_speed);
+            self.modulation.clock(self.envelope_speed);
+        }
+
+        if !self.modulation_halted && self.modulation_frequency > 0 {
+            self.modulation_accumulator += self.modulation_frequency as u32;
+            if self.modulation_accumulator > 0xFFFF {
+                self.modulation_accumulator &= 0xFFFF;
+                self.step_modulation();
+            }
+        }
+
+        // the wave stops while the CPU has it open for writing
+        if !self.wave_halted && !self.wave_write_enabled {
+            let frequency: u32 = if self.modulation_halted { self.wave_frequency as u32 } else { self.modulated_frequency() };
+            self.wave_accumulator += frequency;
+            if self.wave_accumulator > 0xFFFF {
+                self.wave_accumulator &= 0xFFFF;
+                self.wave_position = (self.wave_position + 1) & 0x3F;
+            }
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        let sample: f32 = self.wave[self.wave_position] as f32;
+        let gain: f32 = self.volume.gain.min(MAX_GAIN) as f32;
+        sample * gain * MASTER_VOLUMES[self.master_volume as usize] * OUTPUT_SCALE
+    }
+}
+
+impl Default for FdsAudio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(audio: &mut FdsAudio, cycles: u32) {
+        for _ in 0..cycles {
+            audio.clock();
+        }
+    }
+
+    fn modulated(counter: i8, gain: u8, frequency: u16) -> u32 {
+        let mut audio = FdsAudio::new();
+        audio.modulation_counter = counter;
+        audio.modulation.gain = gain;
+        audio.wave_frequency = frequency;
+        audio.modulated_frequency()
+    }
+
+    #[test]
+    fn envelope_timing() {
+        let mut audio = FdsAudio::new();
+        audio.write(0x4083, 0x00);
+
+        // 8 * (speed + 1) * $408A cycles per step
+        audio.write(0x4080, 0x40 | 2);
+        clock(&mut audio, 8 * 3 * 0xE8 - 1);
+        assert_eq!(audio.read(0x4090), Some(0x40));
+        clock(&mut audio, 1);
+        assert_eq!(audio.read(0x4090), Some(0x41));
+
+        audio.write(0x408A, 1);
+        audio.write(0x4080, 0x40 | 2);
+        clock(&mut audio, 24 * 40);
+        assert_eq!(audio.read(0x4090), Some(0x40 | 32));
+
+        audio.write(0x4080, 0x00);
+        clock(&mut audio, 8 * 5);
+        assert_eq!(audio.read(0x4090), Some(0x40 | 27));
+
+        // $4083 bit 6 and a zero $408A both stop the envelopes
+        audio.write(0x4083, 0x40);
+        clock(&mut audio, 80);
+        assert_eq!(audio.read(0x4090), Some(0x40 | 27));
+        audio.write(0x4083, 0x00);
+        audio.write(0x408A, 0);
+        clock(&mut audio, 80);
+        assert_eq!(audio.read(0x4090), Some(0x40 | 27));
+
+        // a disabled envelope holds the gain written to it
+        audio.write(0x4084, 0x80 | 45);
+        assert_eq!(audio.read(0x4092), Some(0x40 | 45));
+    }
+
+    #[test]
+    fn modulation_table() {
+        let mut audio = FdsAudio::new();
+        audio.write(0x4087, 0x80);
+        for value in 0..32 {
+            audio.write(0x4088, value);
+        }
+        assert_eq!(audio.modulation_table[..8], [0, 0, 1, 1, 2, 2, 3, 3]);
+        assert_eq!(audio.modulation_table[62..], [7, 7]);
+
+        // writes only land while the modulator is halted
+        audio.write(0x4087, 0x00);
+        audio.write(0x4088, 5);
+        assert_eq!(audio.modulation_table[..2], [0, 0]);
+    }
+
+    #[test]
+    fn modulation_counter_wraps() {
+        let mut audio = FdsAudio::new();
+        audio.write(0x4085, 0x7F);
+        assert_eq!(audio.modulation_counter, -1);
+
+        audio.modulation_table = [1; 64];
+        audio.write(0x4085, 63);
+        audio.step_modulation();
+        assert_eq!(audio.modulation_counter, -64);
+
+        audio.modulation_table = [5; 64];
+        audio.write(0x4085, 0x41);
+        audio.step_modulation();
+        assert_eq!(audio.modulation_counter, 61);
+
+        audio.modulation_table = [4; 64];
+        audio.step_modulation();
+        assert_eq!(audio.modulation_counter, 0);
+
+        // a frequency of $800 overflows the accumulator every 32 cycles
+        audio.modulation_table = [3; 64];
+        audio.write(0x4086, 0x00);
+        audio.write(0x4087, 0x08);
+        clock(&mut audio, 31);
+        assert_eq!(audio.modulation_counter, 0);
+        clock(&mut audio, 1);
+        assert_eq!(audio.modulation_counter, 4);
+        assert_eq!(audio.modulation_position, 4);
+    }
+
+    #[test]
+    fn modulated_frequency_rounding() {
+        // worked through the hardware's formula by hand
+        assert_eq!(modulated(0, 32, 0x100), 0x100);
+        assert_eq!(modulated(1, 1, 0x100), 264);
+        assert_eq!(modulated(-1, 1, 0x100), 252);
+        assert_eq!(modulated(16, 1, 0x100), 260);
+        assert_eq!(modulated(3, 5, 100), 103);
+        assert_eq!(modulated(5, 1, 112), 116);
+        assert_eq!(modulated(-5, 3, 100), 98);
+        // the scaled counter wraps into -64..191
+        assert_eq!(modulated(-64, 32, 0x100), 768);
+        assert_eq!(modulated(63, 63, 0x100), 224);
+    }
+
+    #[test]
+    fn wave_halt_and_write_enable() {
+        let mut audio = FdsAudio::new();
+        audio.write(0x4040, 0x3F);
+        assert_eq!(audio.read(0x4040), Some(0x40));
+
+        audio.write(0x4089, 0x80);
+        for step in 0..64 {
+            audio.write(0x4040 + step, 0xC0 | step as u8);
+        }
+        assert_eq!(audio.read(0x4041), Some(0x41));
+
+        audio.write(0x4080, 0x80 | 32);
+        audio.write(0x4082, 0x00);
+        audio.write(0x4083, 0x08);
+        // the wave holds still while it is open for writing
+        clock(&mut audio, 64);
+        assert_eq!(audio.wave_position, 0);
+
+        audio.write(0x4089, 0x00);
+        clock(&mut audio, 32);
+        assert_eq!(audio.wave_position, 1);
+        assert_eq!(audio.output(), 32.0 * OUTPUT_SCALE);
+        clock(&mut audio, 32 * 2);
+        assert_eq!(audio.wave_position, 3);
+
+        // halting goes back to the first step
+        audio.write(0x4083, 0x88);
+        assert_eq!(audio.wave_position, 0);
+        clock(&mut audio, 64);
+        assert_eq!(audio.wave_position, 0);
+        assert_eq!(audio.output(), 0.0);
+    }
+}
@@ -0,0 +1,156 @@
+// SST39SF040 command addresses and the IDs it answers with in software ID mode
+const UNLOCK_ADDRESS_1: usize = 0x5555;
+const UNLOCK_ADDRESS_2: usize = 0x2AAA;
+const MANUFACTURER_ID: u8 = 0xBF;
+const DEVICE_ID: u8 = 0xB7;
+const SECTOR_SIZE: usize = 0x1000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ready,
+    Unlocked1,
+    Unlocked2,
+    Program,
+    Erase,
+    EraseUnlocked1,
+    EraseUnlocked2
+}
+
+/// Command state machine of an SST39SF040 flash chip, used as PRG ROM by homebrew boards that
+/// save by reprogramming their own ROM. Addresses are offsets into the chip.
+pub struct Flash {
+    state: State,
+    id_mode: bool
+}
+
+impl Flash {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ready,
+            id_mode: false
+        }
+    }
+
+    pub fn read(&self, data: &[u8], offset: usize) -> u8 {
+        if self.id_mode {
+            match offset & 1 {
+                0 => MANUFACTURER_ID,
+                _ => DEVICE_ID
+            }
+        } else {
+            data[offset % data.len()]
+        }
+    }
+
+    pub fn write(&mut self, data: &mut [u8], offset: usize, value: u8) {
+        let command: usize = offset & 0x7FFF;
+        self.state = match (self.state, command, value) {
+            // programming can only clear bits, erasing sets them back to 1. The byte being
+            // programmed can be anything, $F0 included.
+            (State::Program, _, _) => {
+                let offset: usize = offset % data.len();
+                data[offset] &= value;
+                State::Ready
+            }
+            (_, _, 0xF0) => {
+                self.id_mode = false;
+                State::Ready
+            }
+            (State::Ready, UNLOCK_ADDRESS_1, 0xAA) => State::Unlocked1,
+            (State::Unlocked1, UNLOCK_ADDRESS_2, 0x55) => State::Unlocked2,
+            (State::Unlocked2, UNLOCK_ADDRESS_1, 0xA0) => State::Program,
+            (State::Unlocked2, UNLOCK_ADDRESS_1, 0x80) => State::Erase,
+            (State::Unlocked2, UNLOCK_ADDRESS_1, 0x90) => {
+                self.id_mode = true;
+                State::Ready
+            }
+            (State::Erase, UNLOCK_ADDRESS_1, 0xAA) => State::EraseUnlocked1,
+            (State::EraseUnlocked1, UNLOCK_ADDRESS_2, 0x55) => State::EraseUnlocked2,
+            (State::EraseUnlocked2, _, 0x30) => {
+                let sector: usize = (offset % data.len()) & !(SECTOR_SIZE - 1);
+                data[sector..sector + SECTOR_SIZE].fill(0xFF);
+                State::Ready
+            }
+            (State::EraseUnlocked2, UNLOCK_ADDRESS_1, 0x10) => {
+                data.fill(0xFF);
+                State::Ready
+            }
+            _ => State::Ready
+        };
+    }
+}
+
+impl Default for Flash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 0x80000;
+
+    fn command(flash: &mut Flash, data: &mut [u8], command: u8) {
+        flash.write(data, UNLOCK_ADDRESS_1, 0xAA);
+        flash.write(data, UNLOCK_ADDRESS_2, 0x55);
+        flash.write(data, UNLOCK_ADDRESS_1, command);
+    }
+
+    fn program(flash: &mut Flash, data: &mut [u8], offset: usize, value: u8) {
+        command(flash, data, 0xA0);
+        flash.write(data, offset, value);
+    }
+
+    fn erase(flash: &mut Flash, data: &mut [u8], offset: usize, kind: u8) {
+        command(flash, data, 0x80);
+        flash.write(data, UNLOCK_ADDRESS_1, 0xAA);
+        flash.write(data, UNLOCK_ADDRESS_2, 0x55);
+        flash.write(data, offset, kind);
+    }
+
+    #[test]
+    fn program_clears_bits() {
+        let (mut flash, mut data): (Flash, Vec<u8>) = (Flash::new(), vec![0xFF; SIZE]);
+        program(&mut flash, &mut data, 0x12345, 0x5A);
+        assert_eq!(flash.read(&data, 0x12345), 0x5A);
+        program(&mut flash, &mut data, 0x12345, 0xF3);
+        assert_eq!(flash.read(&data, 0x12345), 0x52);
+        // without the unlock sequence writes change nothing
+        flash.write(&mut data, 0x12346, 0x00);
+        assert_eq!(flash.read(&data, 0x12346), 0xFF);
+    }
+
+    #[test]
+    fn program_writes_f0() {
+        let (mut flash, mut data): (Flash, Vec<u8>) = (Flash::new(), vec![0xFF; SIZE]);
+        program(&mut flash, &mut data, 0x4000, 0xF0);
+        assert_eq!(flash.read(&data, 0x4000), 0xF0);
+    }
+
+    #[test]
+    fn sector_erase_sets_one_sector() {
+        let (mut flash, mut data): (Flash, Vec<u8>) = (Flash::new(), vec![0x00; SIZE]);
+        erase(&mut flash, &mut data, 0x23456, 0x30);
+        assert!(data[0x23000..0x24000].iter().all(|&byte| byte == 0xFF));
+        assert_eq!(data[0x22FFF], 0x00);
+        assert_eq!(data[0x24000], 0x00);
+    }
+
+    #[test]
+    fn chip_erase_sets_everything() {
+        let (mut flash, mut data): (Flash, Vec<u8>) = (Flash::new(), vec![0x00; SIZE]);
+        erase(&mut flash, &mut data, UNLOCK_ADDRESS_1, 0x10);
+        assert!(data.iter().all(|&byte| byte == 0xFF));
+    }
+
+    #[test]
+    fn software_id_until_reset() {
+        let (mut flash, mut data): (Flash, Vec<u8>) = (Flash::new(), vec![0x00; SIZE]);
+        command(&mut flash, &mut data, 0x90);
+        assert_eq!((flash.read(&data, 0), flash.read(&data, 1)), (MANUFACTURER_ID, DEVICE_ID));
+        flash.write(&mut data, 0, 0xF0);
+        assert_eq!((flash.read(&data, 0), flash.read(&data, 1)), (0x00, 0x00));
+    }
+}
@@ -0,0 +1,272 @@
+use crate::nes::mapper::{
+    ay8910::Ay8910,
+    bank_from_end,
+    bank_offset,
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 69, the Sunsoft FME-7 and the 5B which adds an AY-3-8910 style PSG. Registers are
+/// selected through a command port at $8000 and written through a parameter port at $A000.
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+
+    command: u8,
+    chr_banks: [u8; 8],
+    prg_banks: [u8; 4], // $6000, $8000, $A000, $C000
+    mirroring: u8,
+
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+
+    psg: Ay8910
+}
+
+impl Fme7 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            prg_ram: prg_ram(&cartridge),
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            mirroring: 0,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+            psg: Ay8910::new(),
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    // $6000 can map ROM, RAM, or nothing: bit 6 selects RAM, bit 7 enables it
+    fn low_bank_ram(&self) -> bool {
+        self.prg_banks[0] & 0x40 != 0
+    }
+
+    fn low_bank_ram_enabled(&self) -> bool {
+        self.low_bank_ram() && self.prg_banks[0] & 0x80 != 0 && !self.prg_ram.is_empty()
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank: usize = match address {
+            0x6000..=0xDFFF => (self.prg_banks[(address as usize - 0x6000) >> 13] & 0x3F) as usize,
+            _ => bank_from_end(self.prg_rom.len(), 0x2000, 1)
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x2000, address)
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = value,
+            0x8..=0xB => self.prg_banks[self.command as usize - 0x8] = value,
+            0xC => self.mirroring = value & 0b11,
+            0xD => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.irq_counter_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | value as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16) << 8
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.low_bank_ram_enabled() => {
+                let bank: usize = (self.prg_banks[0] & 0x3F) as usize;
+                self.prg_ram[bank_offset(self.prg_ram.len(), bank, 0x2000, address)]
+            }
+            0x6000..=0x7FFF if self.low_bank_ram() => 0,
+            0x6000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.low_bank_ram_enabled() => {
+                let bank: usize = (self.prg_banks[0] & 0x3F) as usize;
+                let offset: usize = bank_offset(self.prg_ram.len(), bank, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+            0x8000..=0x9FFF => self.command = value & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.psg.write_address(value),
+            0xE000..=0xFFFF => self.psg.write_data(value),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_cycle(&mut self) {
+        // the counter decrements every cycle and fires when it wraps from 0 to $FFFF
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.psg.clock();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.psg.output()
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 128 KB of PRG in 8 KB banks and 64 KB of CHR in 1 KB banks, each filled with its own
+    // number, and 8 KB of PRG RAM
+    fn mapper() -> Fme7 {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([8, 8, 0x50, 0x40, 0, 0, 0, 0, 0, 0, 0, 0]);
+        for bank in 0..16 {
+            rom.extend(vec![bank; 0x2000]);
+        }
+        for bank in 0..64 {
+            rom.extend(vec![bank; 0x400]);
+        }
+        Fme7::new(Cartridge::from_bytes(&rom).unwrap())
+    }
+
+    fn command(fme7: &mut Fme7, command: u8, value: u8) {
+        fme7.cpu_write(0x8000, command);
+        fme7.cpu_write(0xA000, value);
+    }
+
+    fn cycles_until_irq(fme7: &mut Fme7, limit: u32) -> Option<u32> {
+        (1..=limit).find(|_| {
+            fme7.cpu_cycle();
+            fme7.irq()
+        })
+    }
+
+    #[test]
+    fn banks() {
+        let mut fme7 = mapper();
+        for (register, bank) in [(0x9, 3), (0xA, 4), (0xB, 0x45)] {
+            command(&mut fme7, register, bank);
+        }
+        let banks = [0x8000, 0xA000, 0xC000, 0xE000].map(|address| fme7.cpu_read(address));
+        assert_eq!(banks, [3, 4, 5, 15]);
+
+        for slot in 0..8 {
+            command(&mut fme7, slot, 20 + slot);
+        }
+        assert_eq!(fme7.ppu_read(0x0000), 20);
+        assert_eq!(fme7.ppu_read(0x1C00), 27);
+
+        command(&mut fme7, 0xC, 3);
+        assert!(matches!(fme7.mirroring(), Mirroring::SingleScreenUpper));
+    }
+
+    #[test]
+    fn ram_rom_window() {
+        let mut fme7 = mapper();
+        // ROM bank
+        command(&mut fme7, 0x8, 0x05);
+        assert_eq!(fme7.cpu_read(0x6000), 5);
+        fme7.cpu_write(0x6000, 0x42);
+        assert_eq!(fme7.cpu_read(0x6000), 5);
+
+        // RAM selected but not enabled reads as nothing and ignores writes
+        command(&mut fme7, 0x8, 0x40);
+        assert_eq!(fme7.cpu_read(0x6000), 0);
+        fme7.cpu_write(0x6000, 0x42);
+
+        command(&mut fme7, 0x8, 0xC0);
+        assert_eq!(fme7.cpu_read(0x6000), 0);
+        fme7.cpu_write(0x6000, 0x42);
+        assert_eq!(fme7.cpu_read(0x6000), 0x42);
+
+        // bit 7 alone doesn't switch from ROM
+        command(&mut fme7, 0x8, 0x85);
+        assert_eq!(fme7.cpu_read(0x6000), 5);
+    }
+
+    #[test]
+    fn irq_counter() {
+        let mut fme7 = mapper();
+        command(&mut fme7, 0xE, 0x02);
+        command(&mut fme7, 0xF, 0x00);
+        command(&mut fme7, 0xD, 0x81);
+        // fires on the wrap from 0 to $FFFF
+        assert_eq!(cycles_until_irq(&mut fme7, 10), Some(3));
+        command(&mut fme7, 0xD, 0x81);
+        assert!(!fme7.irq());
+        assert_eq!(cycles_until_irq(&mut fme7, 0x20000), Some(0x10000));
+
+        // both bytes of the counter count
+        command(&mut fme7, 0xD, 0x00);
+        command(&mut fme7, 0xE, 0x00);
+        command(&mut fme7, 0xF, 0x01);
+        command(&mut fme7, 0xD, 0x81);
+        assert_eq!(cycles_until_irq(&mut fme7, 0x1000), Some(0x101));
+
+        // counting without the IRQ enabled, then stopped altogether
+        command(&mut fme7, 0xD, 0x80);
+        assert_eq!(cycles_until_irq(&mut fme7, 0x20000), None);
+        command(&mut fme7, 0xE, 0x00);
+        command(&mut fme7, 0xF, 0x00);
+        command(&mut fme7, 0xD, 0x01);
+        assert_eq!(cycles_until_irq(&mut fme7, 0x20000), None);
+        assert_eq!(fme7.irq_counter, 0);
+    }
+
+    #[test]
+    fn psg_ports() {
+        let mut fme7 = mapper();
+        fme7.cpu_write(0xC000, 0x07);
+        fme7.cpu_write(0xE000, 0x3F);
+        fme7.cpu_write(0xC000, 0x08);
+        fme7.cpu_write(0xE000, 0x0F);
+        assert!(fme7.audio_output() > 0.0);
+    }
+}
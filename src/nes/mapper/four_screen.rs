@@ -0,0 +1,121 @@
+use crate::nes::mapper::Mapper;
+use crate::nes::rom::Mirroring;
+
+const NAMETABLE_RAM_SIZE: usize = 0x1000;
+
+/// 4 KB of nametable RAM on the cartridge for boards wired for four screen mirroring
+/// (Gauntlet, Rad Racer II), giving all four nametables their own memory. Wraps the board
+/// and answers nametable accesses the board itself doesn't while it reports `FourScreen`.
+pub struct FourScreen {
+    board: Box<dyn Mapper>,
+    ram: [u8; NAMETABLE_RAM_SIZE]
+}
+
+impl FourScreen {
+    pub fn new(board: Box<dyn Mapper>) -> Self {
+        Self {
+            board,
+            ram: [0; NAMETABLE_RAM_SIZE]
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.board.mirroring() == Mirroring::FourScreen
+    }
+}
+
+impl Mapper for FourScreen {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        self.board.cpu_read(address)
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        self.board.cpu_write(address, value);
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.board.ppu_read(address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.board.ppu_write(address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.board.mirroring()
+    }
+
+    fn irq(&self) -> bool {
+        self.board.irq()
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        match self.board.nametable_read(address) {
+            None if self.enabled() => Some(self.ram[address as usize & 0x0FFF]),
+            value => value
+        }
+    }
+
+    fn nametable_write(&mut self, address: u16, value: u8) -> bool {
+        if self.board.nametable_write(address, value) {
+            return true;
+        }
+        if !self.enabled() {
+            return false;
+        }
+        self.ram[address as usize & 0x0FFF] = value;
+        true
+    }
+
+    fn chr_ciram_page(&self, address: u16) -> Option<u8> {
+        self.board.chr_ciram_page(address)
+    }
+
+    fn ppu_register_write(&mut self, address: u16, value: u8) {
+        self.board.ppu_register_write(address, value);
+    }
+
+    fn ppu_address(&mut self, address: u16) {
+        self.board.ppu_address(address);
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.board.cpu_cycle();
+    }
+
+    fn scanline(&mut self) {
+        self.board.scanline();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.board.audio_output()
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        self.board.save_ram()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        self.board.load_save_ram(data);
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.board.disk_sides()
+    }
+
+    fn inserted_disk(&self) -> Option<usize> {
+        self.board.inserted_disk()
+    }
+
+    fn insert_disk(&mut self, side: Option<usize>) {
+        self.board.insert_disk(side);
+    }
+
+    fn disk_side(&self) -> Option<Vec<u8>> {
+        self.board.disk_side()
+    }
+
+    fn write_disk_side(&mut self, data: &[u8]) {
+        self.board.write_disk_side(data);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::nes::mapper::{
+    bank_offset,
+    flash::Flash,
+    restore,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+// Two 8 KB pattern table banks followed by two 8 KB sets of four screen nametables
+const CHR_RAM_SIZE: usize = 0x8000;
+const NAMETABLE_RAM: usize = 0x4000;
+
+/// Mapper 111, Membler Industries' GTROM (Cheapocabra). 32 KB PRG banks out of an SST39SF040
+/// that games can reprogram, 32 KB of CHR RAM split into two pattern table banks and two sets
+/// of four screen nametables, all selected by one register at $5000-$5FFF and $7000-$7FFF.
+pub struct Gtrom {
+    prg_rom: Vec<u8>,
+    chr_ram: Vec<u8>,
+    battery: bool,
+    flash: Flash,
+
+    register: u8 // PRG bank (bits 0-3), CHR bank (bit 4), nametable bank (bit 5), LEDs (bits 6-7)
+}
+
+impl Gtrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            chr_ram: vec![0; CHR_RAM_SIZE],
+            battery: cartridge.header.battery,
+            flash: Flash::new(),
+            register: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        bank_offset(self.prg_rom.len(), (self.register & 0x0F) as usize, 0x8000, address)
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        (((self.register as usize >> 4) & 1) * 0x2000) | (address as usize & 0x1FFF)
+    }
+
+    fn nametable_offset(&self, address: u16) -> usize {
+        NAMETABLE_RAM | (((self.register as usize >> 5) & 1) * 0x2000) | (address as usize & 0x0FFF)
+    }
+}
+
+impl Mapper for Gtrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => self.flash.read(&self.prg_rom, self.prg_offset(address)),
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5FFF | 0x7000..=0x7FFF => self.register = value,
+            // the flash's $5555/$2AAA command addresses are $D555/$AAAA in bank 0
+            0x8000..=0xFFFF => {
+                let offset: usize = self.prg_offset(address);
+                self.flash.write(&mut self.prg_rom, offset, value);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr_ram[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let offset: usize = self.chr_offset(address);
+        self.chr_ram[offset] = value;
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        Some(self.chr_ram[self.nametable_offset(address)])
+    }
+
+    fn nametable_write(&mut self, address: u16, value: u8) -> bool {
+        let offset: usize = self.nametable_offset(address);
+        self.chr_ram[offset] = value;
+        true
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::FourScreen
+    }
+
+    // The whole flash is the save
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_rom) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_rom, data);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 32, Irem G-101. Two 8 KB PRG banks that swap places like the MMC3's, eight 1 KB
+/// CHR banks and mirroring control. Major League (submapper 1) is wired for single screen
+/// mirroring and ignores the PRG mode.
+pub struct IremG101 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+    major_league: bool,
+
+    prg_banks: [u8; 2],
+    chr_banks: [u8; 8],
+    control: u8 // $9000, bit 0 mirroring, bit 1 PRG mode
+}
+
+impl IremG101 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            prg_ram: prg_ram(&cartridge),
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            major_league: cartridge.header.submapper == 1,
+            prg_banks: [0; 2],
+            chr_banks: [0; 8],
+            control: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let second_last: usize = bank_from_end(self.prg_rom.len(), 0x2000, 2);
+        let swap: bool = !self.major_league && self.control & 0b10 != 0;
+        let bank: usize = match (address >> 13) & 0b11 {
+            0 => if swap { second_last } else { (self.prg_banks[0] & 0x1F) as usize },
+            1 => (self.prg_banks[1] & 0x1F) as usize,
+            2 => if swap { (self.prg_banks[0] & 0x1F) as usize } else { second_last },
+            _ => bank_from_end(self.prg_rom.len(), 0x2000, 1)
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x2000, address)
+    }
+}
+
+impl Mapper for IremG101 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => self.prg_ram[bank_offset(self.prg_ram.len(), 0, 0x2000, address)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let offset: usize = bank_offset(self.prg_ram.len(), 0, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+            0x8000..=0x8FFF => self.prg_banks[0] = value,
+            0x9000..=0x9FFF => self.control = value,
+            0xA000..=0xAFFF => self.prg_banks[1] = value,
+            0xB000..=0xBFFF => self.chr_banks[address as usize & 0b111] = value,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.major_league {
+            Mirroring::SingleScreenLower
+        } else if self.control & 1 != 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 65, Irem H3001. Three 8 KB PRG banks with the last fixed, eight 1 KB CHR banks and
+/// a 16 bit CPU cycle down counter that raises an IRQ when it reaches 0.
+pub struct IremH3001 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    horizontal: bool,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool
+}
+
+impl IremH3001 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            chr: Chr::new(&cartridge),
+            prg_banks: [0, 1, 0xFE],
+            chr_banks: [0; 8],
+            horizontal: false,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank: usize = match address {
+            0x8000..=0xDFFF => self.prg_banks[(address as usize - 0x8000) >> 13] as usize,
+            _ => bank_from_end(self.prg_rom.len(), 0x2000, 1)
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x2000, address)
+    }
+}
+
+impl Mapper for IremH3001 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x8FFF => self.prg_banks[0] = value,
+            0x9001 => self.horizontal = value & 0x80 != 0,
+            0x9003 => {
+                self.irq_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x9004 => {
+                self.irq_counter = self.irq_reload;
+                self.irq_pending = false;
+            }
+            0x9005 => self.irq_reload = (self.irq_reload & 0x00FF) | (value as u16) << 8,
+            0x9006 => self.irq_reload = (self.irq_reload & 0xFF00) | value as u16,
+            0xA000..=0xAFFF => self.prg_banks[1] = value,
+            0xB000..=0xB007 => self.chr_banks[address as usize & 0b111] = value,
+            0xC000..=0xCFFF => self.prg_banks[2] = value,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.horizontal { Mirroring::Horizontal } else { Mirroring::Vertical }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    // The counter stops at 0 rather than wrapping
+    fn cpu_cycle(&mut self) {
+        if self.irq_enabled && self.irq_counter > 0 {
+            self.irq_counter -= 1;
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper() -> IremH3001 {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([8, 8, 0x10, 0x40, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom.extend(vec![0; 8 * 0x4000 + 8 * 0x2000]);
+        IremH3001::new(Cartridge::from_bytes(&rom).unwrap())
+    }
+
+    fn cycles_until_irq(h3001: &mut IremH3001, limit: u32) -> Option<u32> {
+        (1..=limit).find(|_| {
+            h3001.cpu_cycle();
+            h3001.irq()
+        })
+    }
+
+    #[test]
+    fn irq_counter() {
+        let mut h3001 = mapper();
+        h3001.cpu_write(0x9005, 0x01);
+        h3001.cpu_write(0x9006, 0x02);
+        h3001.cpu_write(0x9004, 0);
+        assert_eq!(h3001.irq_counter, 0x0102);
+
+        // counts only while enabled
+        for _ in 0..10 {
+            h3001.cpu_cycle();
+        }
+        assert_eq!(h3001.irq_counter, 0x0102);
+        h3001.cpu_write(0x9003, 0x80);
+        assert_eq!(cycles_until_irq(&mut h3001, 0x1000), Some(0x0102));
+
+        // acknowledged by $9003 or $9004, and stopped at 0 until reloaded
+        h3001.cpu_write(0x9003, 0x80);
+        assert!(!h3001.irq());
+        assert_eq!(cycles_until_irq(&mut h3001, 0x10000), None);
+        h3001.cpu_write(0x9004, 0);
+        assert_eq!(cycles_until_irq(&mut h3001, 0x1000), Some(0x0102));
+        h3001.cpu_write(0x9004, 0);
+        assert!(!h3001.irq());
+
+        h3001.cpu_write(0x9003, 0x00);
+        assert_eq!(cycles_until_irq(&mut h3001, 0x1000), None);
+    }
+}
@@ -0,0 +1,382 @@
+use crate::nes::mapper::{
+    bank_offset,
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+// SxROM boards reuse the upper CHR bank bits for wider PRG ROM/RAM when they only carry 8 KB of CHR
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Board {
+    Standard,
+    Snrom, // CHR bit 4 disables PRG RAM
+    Sorom, // CHR bit 3 selects one of two 8 KB PRG RAM banks
+    Surom, // CHR bit 4 selects the 256 KB PRG half
+    Sxrom  // CHR bit 4 selects the 256 KB PRG half, bits 2-3 one of four 8 KB PRG RAM banks
+}
+
+/// Mapper 1 (and 155, the MMC1A which can't disable PRG RAM). Registers are loaded
+/// one bit at a time through a 5 bit serial shift register at $8000-$FFFF.
+pub struct Mmc1 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+    board: Board,
+    revision_a: bool,
+
+    shift: u8,
+    shift_count: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+
+    // the last pattern fetch was from $1000-$1FFF, decides which CHR register drives the SxROM lines
+    chr_a12: bool,
+    cycle: u64,
+    last_write: u64
+}
+
+impl Mmc1 {
+    pub fn new(cartridge: Cartridge, revision_a: bool) -> Self {
+        let prg_ram: Vec<u8> = prg_ram(&cartridge);
+        let chr_ram: bool = cartridge.chr_rom.is_empty();
+
+        let board: Board = if !chr_ram {
+            Board::Standard
+        } else if prg_ram.len() > 0x4000 {
+            Board::Sxrom
+        } else if cartridge.prg_rom.len() > 0x40000 {
+            Board::Surom
+        } else if prg_ram.len() > 0x2000 {
+            Board::Sorom
+        } else {
+            Board::Snrom
+        };
+
+        Self {
+            prg_ram,
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            board,
+            revision_a,
+            shift: 0,
+            shift_count: 0,
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            chr_a12: false,
+            cycle: 0,
+            last_write: u64::MAX - 1,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn outer_chr_bank(&self) -> u8 {
+        if self.control & 0b1_0000 != 0 && self.chr_a12 { self.chr_bank_1 } else { self.chr_bank_0 }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        if self.prg_ram.is_empty() {
+            return false;
+        }
+        let chip_enabled: bool = self.revision_a || self.prg_bank & 0b1_0000 == 0;
+        match self.board {
+            Board::Snrom => chip_enabled && self.outer_chr_bank() & 0b1_0000 == 0,
+            _ => chip_enabled
+        }
+    }
+
+    fn prg_ram_offset(&self, address: u16) -> usize {
+        let bank: usize = match self.board {
+            Board::Sorom => (self.outer_chr_bank() as usize >> 3) & 0b01,
+            Board::Sxrom => (self.outer_chr_bank() as usize >> 2) & 0b11,
+            _ => 0
+        };
+        bank_offset(self.prg_ram.len(), bank, 0x2000, address)
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let outer: usize = match self.board {
+            Board::Surom | Board::Sxrom => self.outer_chr_bank() as usize & 0b1_0000,
+            _ => 0
+        };
+        let bank: usize = (self.prg_bank & 0x0F) as usize;
+        let last: usize = 0x0F;
+
+        let bank: usize = match (self.control >> 2) & 0b11 {
+            // 32 KB mode ignores the low bank bit
+            0 | 1 => (bank & !1) | ((address as usize >> 14) & 1),
+            2 => if address < 0xC000 { 0 } else { bank },
+            _ => if address < 0xC000 { bank } else { last }
+        };
+        bank_offset(self.prg_rom.len(), outer | bank, 0x4000, address)
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x8000..=0x9FFF => self.control = value,
+            0xA000..=0xBFFF => self.chr_bank_0 = value,
+            0xC000..=0xDFFF => self.chr_bank_1 = value,
+            _ => self.prg_bank = value
+        }
+    }
+
+    fn chr_bank(&self, address: u16) -> (usize, usize) {
+        if self.control & 0b1_0000 != 0 {
+            let bank: u8 = if address < 0x1000 { self.chr_bank_0 } else { self.chr_bank_1 };
+            (bank as usize, 0x1000)
+        } else {
+            (self.chr_bank_0 as usize >> 1, 0x2000)
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[self.prg_ram_offset(address)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let offset: usize = self.prg_ram_offset(address);
+                self.prg_ram[offset] = value;
+            }
+            0x8000..=0xFFFF => {
+                // the serial port ignores writes on back to back cycles, like the two writes of INC/ROR
+                let consecutive: bool = self.cycle.wrapping_sub(self.last_write) <= 1;
+                self.last_write = self.cycle;
+                if consecutive {
+                    return;
+                }
+
+                if value & 0x80 != 0 {
+                    self.shift = 0;
+                    self.shift_count = 0;
+                    self.control |= 0x0C;
+                    return;
+                }
+
+                self.shift |= (value & 1) << self.shift_count;
+                self.shift_count += 1;
+                if self.shift_count == 5 {
+                    self.write_register(address, self.shift);
+                    self.shift = 0;
+                    self.shift_count = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr_a12 = address & 0x1000 != 0;
+        let (bank, size) = self.chr_bank(address);
+        self.chr.read(bank, size, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let (bank, size) = self.chr_bank(address);
+        self.chr.write(bank, size, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal
+        }
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NES 2.0 image with 8 KB of CHR RAM, `prg_banks` 16 KB PRG banks filled with their own
+    // number and PRG RAM of 64 << `ram_shift` bytes
+    fn mapper(mapper: u8, prg_banks: u8, ram_shift: u8) -> Mmc1 {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([prg_banks, 0, (mapper & 0x0F) << 4, (mapper & 0xF0) | 0x08, 0, 0, ram_shift, 0x07, 0, 0, 0, 0]);
+        for bank in 0..prg_banks {
+            rom.extend(vec![bank; 0x4000]);
+        }
+        let cartridge: Cartridge = Cartridge::from_bytes(&rom).unwrap();
+        Mmc1::new(cartridge, mapper == 155)
+    }
+
+    // Five writes of one bit each, lowest first, spaced out so none look back to back
+    fn write_serial(mmc1: &mut Mmc1, address: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.cpu_write(address, value >> bit & 1);
+            mmc1.cpu_cycle();
+            mmc1.cpu_cycle();
+        }
+    }
+
+    fn ram_works(mmc1: &mut Mmc1) -> bool {
+        mmc1.cpu_write(0x6000, 0x5A);
+        let works: bool = mmc1.cpu_read(0x6000) == 0x5A;
+        mmc1.cpu_write(0x6000, 0);
+        works
+    }
+
+    #[test]
+    fn serial_writes() {
+        let mut mmc1 = mapper(1, 16, 0x07);
+        assert_eq!(mmc1.cpu_read(0xC000), 15);
+
+        write_serial(&mut mmc1, 0xE000, 5);
+        assert_eq!(mmc1.cpu_read(0x8000), 5);
+
+        // the fifth write's address picks the register, here vertical mirroring and $8000 fixed
+        for bit in [0, 1, 0, 1] {
+            mmc1.cpu_write(0xE000, bit);
+            mmc1.cpu_cycle();
+            mmc1.cpu_cycle();
+        }
+        mmc1.cpu_write(0x8000, 0);
+        mmc1.cpu_cycle();
+        mmc1.cpu_cycle();
+        assert_eq!(mmc1.cpu_read(0x8000), 0);
+        assert_eq!(mmc1.cpu_read(0xC000), 5);
+        assert!(matches!(mmc1.mirroring(), Mirroring::Vertical));
+
+        // bit 7 throws away the bits so far and goes back to fixing the last bank at $C000
+        write_serial(&mut mmc1, 0x8000, 0x0B);
+        assert!(matches!(mmc1.mirroring(), Mirroring::Horizontal));
+        mmc1.cpu_write(0xE000, 1);
+        mmc1.cpu_cycle();
+        mmc1.cpu_cycle();
+        mmc1.cpu_write(0xE000, 0x80);
+        mmc1.cpu_cycle();
+        mmc1.cpu_cycle();
+        assert_eq!(mmc1.cpu_read(0xC000), 15);
+        write_serial(&mut mmc1, 0xE000, 2);
+        assert_eq!(mmc1.cpu_read(0x8000), 2);
+    }
+
+    #[test]
+    fn back_to_back_writes_are_ignored() {
+        let mut mmc1 = mapper(1, 16, 0x07);
+        mmc1.cpu_write(0xE000, 0);
+        mmc1.cpu_write(0xE000, 1);
+        mmc1.cpu_cycle();
+        mmc1.cpu_write(0xE000, 1);
+        for bit in [1, 0, 0, 0] {
+            mmc1.cpu_cycle();
+            mmc1.cpu_cycle();
+            mmc1.cpu_write(0xE000, bit);
+        }
+        assert_eq!(mmc1.cpu_read(0x8000), 2);
+
+        // so is the reset half of a read-modify-write
+        mmc1.cpu_cycle();
+        mmc1.cpu_write(0x8000, 0x80);
+        assert_eq!(mmc1.shift_count, 0);
+        mmc1.cpu_cycle();
+        mmc1.cpu_cycle();
+        mmc1.cpu_write(0x8000, 1);
+        mmc1.cpu_write(0x8000, 0x80);
+        assert_eq!(mmc1.shift_count, 1);
+    }
+
+    #[test]
+    fn mmc1a_cannot_disable_ram() {
+        let mut mmc1 = mapper(1, 16, 0x07);
+        assert!(ram_works(&mut mmc1));
+        write_serial(&mut mmc1, 0xE000, 0x10);
+        assert!(!ram_works(&mut mmc1));
+
+        let mut mmc1a = mapper(155, 16, 0x07);
+        write_serial(&mut mmc1a, 0xE000, 0x10);
+        assert!(ram_works(&mut mmc1a));
+    }
+
+    #[test]
+    fn snrom_chr_bit_4_disables_ram() {
+        let mut mmc1 = mapper(1, 16, 0x07);
+        assert!(mmc1.board == Board::Snrom);
+        write_serial(&mut mmc1, 0xA000, 0x10);
+        assert!(!ram_works(&mut mmc1));
+        write_serial(&mut mmc1, 0xA000, 0x00);
+        assert!(ram_works(&mut mmc1));
+    }
+
+    #[test]
+    fn sorom_chr_bit_3_selects_ram_bank() {
+        let mut mmc1 = mapper(1, 16, 0x08);
+        assert!(mmc1.board == Board::Sorom);
+        mmc1.cpu_write(0x6000, 0x11);
+        write_serial(&mut mmc1, 0xA000, 0x08);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+        mmc1.cpu_write(0x6000, 0x22);
+        write_serial(&mut mmc1, 0xA000, 0x00);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x11);
+        // bit 4 leaves the RAM on
+        write_serial(&mut mmc1, 0xA000, 0x18);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x22);
+    }
+
+    #[test]
+    fn surom_chr_bit_4_selects_prg_half() {
+        let mut mmc1 = mapper(1, 32, 0x07);
+        assert!(mmc1.board == Board::Surom);
+        write_serial(&mut mmc1, 0xE000, 2);
+        assert_eq!(mmc1.cpu_read(0x8000), 2);
+        assert_eq!(mmc1.cpu_read(0xC000), 15);
+
+        write_serial(&mut mmc1, 0xA000, 0x10);
+        assert_eq!(mmc1.cpu_read(0x8000), 18);
+        assert_eq!(mmc1.cpu_read(0xC000), 31);
+        assert!(ram_works(&mut mmc1));
+    }
+
+    #[test]
+    fn sxrom_ram_banks_and_prg_half() {
+        let mut mmc1 = mapper(1, 32, 0x09);
+        assert!(mmc1.board == Board::Sxrom);
+        write_serial(&mut mmc1, 0xA000, 0x1C);
+        assert_eq!(mmc1.cpu_read(0xC000), 31);
+        mmc1.cpu_write(0x6000, 0x33);
+        write_serial(&mut mmc1, 0xA000, 0x04);
+        assert_eq!(mmc1.cpu_read(0xC000), 15);
+        assert_eq!(mmc1.cpu_read(0x6000), 0);
+        mmc1.cpu_write(0x6000, 0x44);
+
+        // in 4 KB CHR mode the register for the last pattern table fetched drives the lines
+        write_serial(&mut mmc1, 0x8000, 0x1C);
+        write_serial(&mut mmc1, 0xC000, 0x1C);
+        mmc1.ppu_read(0x0000);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x44);
+        assert_eq!(mmc1.cpu_read(0xC000), 15);
+        mmc1.ppu_read(0x1000);
+        assert_eq!(mmc1.cpu_read(0x6000), 0x33);
+        assert_eq!(mmc1.cpu_read(0xC000), 31);
+    }
+}
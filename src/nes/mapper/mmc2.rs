@@ -0,0 +1,235 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+const LATCH_FD: u8 = 0xFD;
+const LATCH_FE: u8 = 0xFE;
+
+/// Mappers 9 (MMC2) and 10 (MMC4). Each 4 KB pattern table has an FD and an FE bank
+/// register, and a latch that flips between them when the PPU fetches tile $FD or $FE.
+pub struct Mmc2 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+    mmc4: bool,
+
+    prg_bank: u8,
+    chr_banks: [[u8; 2]; 2], // [pattern table][FD, FE]
+    latches: [u8; 2],
+    horizontal: bool
+}
+
+impl Mmc2 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            prg_ram: prg_ram(&cartridge),
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            mmc4: cartridge.header.mapper == 10,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [LATCH_FE; 2],
+            horizontal: false,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        if self.mmc4 {
+            // 16 KB switchable at $8000, last 16 KB fixed
+            let bank: usize = if address < 0xC000 { self.prg_bank as usize } else { bank_from_end(self.prg_rom.len(), 0x4000, 1) };
+            bank_offset(self.prg_rom.len(), bank, 0x4000, address)
+        } else {
+            // 8 KB switchable at $8000, last three 8 KB banks fixed
+            let bank: usize = match address {
+                0x8000..=0x9FFF => self.prg_bank as usize,
+                _ => bank_from_end(self.prg_rom.len(), 0x2000, 4) + ((address as usize - 0x8000) >> 13)
+            };
+            bank_offset(self.prg_rom.len(), bank, 0x2000, address)
+        }
+    }
+
+    fn chr_bank(&self, address: u16) -> usize {
+        let table: usize = (address >> 12) as usize & 1;
+        let register: usize = if self.latches[table] == LATCH_FD { 0 } else { 1 };
+        self.chr_banks[table][register] as usize
+    }
+
+    // The latch flips after the fetch that hit the trigger address, so the trigger tile itself
+    // is still drawn from the old bank. MMC2 only watches $0FD8/$0FE8 in the first pattern table,
+    // the MMC4 and the second table match the whole $xFD8-$xFDF/$xFE8-$xFEF range.
+    fn update_latch(&mut self, address: u16) {
+        let table: usize = (address >> 12) as usize & 1;
+        let matches = |tile: u16| {
+            if table == 0 && !self.mmc4 {
+                address == tile << 4 | 0x08
+            } else {
+                address & 0x0FF8 == tile << 4 | 0x08
+            }
+        };
+        if matches(LATCH_FD as u16) {
+            self.latches[table] = LATCH_FD;
+        } else if matches(LATCH_FE as u16) {
+            self.latches[table] = LATCH_FE;
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => self.prg_ram[bank_offset(self.prg_ram.len(), 0, 0x2000, address)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let offset: usize = bank_offset(self.prg_ram.len(), 0, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+            0xA000..=0xAFFF => self.prg_bank = value & 0x0F,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = value & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = value & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = value & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = value & 0x1F,
+            0xF000..=0xFFFF => self.horizontal = value & 1 != 0,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        let value: u8 = self.chr.read(self.chr_bank(address), 0x1000, address);
+        self.update_latch(address);
+        value
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_bank(address), 0x1000, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.horizontal { Mirroring::Horizontal } else { Mirroring::Vertical }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::ppu::Ppu;
+
+    // 128 KB of PRG and eight 4 KB CHR banks, each filled with its own number so any
+    // pattern fetch shows which bank is mapped
+    fn cartridge(mapper: u8) -> Cartridge {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([8, 4, (mapper & 0x0F) << 4, mapper & 0xF0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom.extend(vec![0; 8 * 0x4000]);
+        for bank in 0..8 {
+            rom.extend(vec![bank; 0x1000]);
+        }
+        Cartridge::from_bytes(&rom).unwrap()
+    }
+
+    fn mapper(mapper: u8) -> Mmc2 {
+        let mut mmc2 = Mmc2::new(cartridge(mapper));
+        mmc2.cpu_write(0xB000, 1); // $0000 FD
+        mmc2.cpu_write(0xC000, 2); // $0000 FE
+        mmc2.cpu_write(0xD000, 3); // $1000 FD
+        mmc2.cpu_write(0xE000, 4); // $1000 FE
+        mmc2
+    }
+
+    // Fills both nametables with one tile and turns background rendering on from `table`
+    fn draw_tile(ppu: &mut Ppu, mmc2: &mut Mmc2, tile: u8, table: u8) {
+        ppu.write_register(0x2001, 0x00, mmc2);
+        ppu.write_register(0x2006, 0x20, mmc2);
+        ppu.write_register(0x2006, 0x00, mmc2);
+        for _ in 0..0x800 {
+            ppu.write_register(0x2007, tile, mmc2);
+        }
+        ppu.write_register(0x2000, table, mmc2);
+        ppu.write_register(0x2001, 0x08, mmc2);
+    }
+
+    fn render_frame(ppu: &mut Ppu, mmc2: &mut Mmc2) {
+        ppu.frame_complete = false;
+        while !ppu.frame_complete {
+            ppu.step(mmc2);
+        }
+    }
+
+    #[test]
+    fn background_fetches_flip_latch_0() {
+        let mut ppu = Ppu::new();
+        let mut mmc2 = mapper(9);
+        assert_eq!(ppu.read(0x0000, &mut mmc2), 2);
+
+        draw_tile(&mut ppu, &mut mmc2, 0xFD, 0x00);
+        render_frame(&mut ppu, &mut mmc2);
+        assert_eq!(ppu.read(0x0000, &mut mmc2), 1);
+        // the other pattern table's latch is untouched
+        assert_eq!(ppu.read(0x1000, &mut mmc2), 4);
+
+        draw_tile(&mut ppu, &mut mmc2, 0xFE, 0x00);
+        render_frame(&mut ppu, &mut mmc2);
+        assert_eq!(ppu.read(0x0000, &mut mmc2), 2);
+    }
+
+    #[test]
+    fn background_fetches_flip_latch_1() {
+        let mut ppu = Ppu::new();
+        let mut mmc2 = mapper(9);
+
+        draw_tile(&mut ppu, &mut mmc2, 0xFD, 0x10);
+        render_frame(&mut ppu, &mut mmc2);
+        assert_eq!(ppu.read(0x1000, &mut mmc2), 3);
+        assert_eq!(ppu.read(0x0000, &mut mmc2), 2);
+
+        draw_tile(&mut ppu, &mut mmc2, 0xFE, 0x10);
+        render_frame(&mut ppu, &mut mmc2);
+        assert_eq!(ppu.read(0x1000, &mut mmc2), 4);
+    }
+
+    #[test]
+    fn trigger_tile_uses_the_old_bank() {
+        let mut ppu = Ppu::new();
+        let mut mmc2 = mapper(9);
+
+        assert_eq!(ppu.read(0x0FD8, &mut mmc2), 2);
+        assert_eq!(ppu.read(0x0FD8, &mut mmc2), 1);
+    }
+
+    #[test]
+    fn mmc2_latch_0_only_matches_the_exact_address() {
+        let mut ppu = Ppu::new();
+        let mut mmc2 = mapper(9);
+        ppu.read(0x0FDA, &mut mmc2);
+        assert_eq!(ppu.read(0x0000, &mut mmc2), 2);
+        ppu.read(0x1FDA, &mut mmc2);
+        assert_eq!(ppu.read(0x1000, &mut mmc2), 3);
+
+        let mut mmc4 = mapper(10);
+        ppu.read(0x0FDA, &mut mmc4);
+        assert_eq!(ppu.read(0x0000, &mut mmc4), 1);
+    }
+}
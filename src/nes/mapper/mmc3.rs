@@ -0,0 +1,468 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+// PPU A12 has to stay low for this many CPU cycles before a rise clocks the IRQ counter,
+// which filters out the short dips between sprite pattern fetches
+const A12_FILTER_CYCLES: u32 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Board {
+    Mmc3,
+    Mmc6,   // mapper 4 submapper 1, 1 KB of on-chip RAM split into two protectable halves
+    Txsrom, // mapper 118, CHR bank bit 7 drives CIRAM A10
+    Tqrom   // mapper 119, CHR bank bit 6 selects 8 KB of CHR RAM next to the CHR ROM
+}
+
+/// Mapper 4 and its relatives 118/119. Eight bank registers behind a select/data pair
+/// at $8000/$8001 and a scanline counter clocked by rising edges of PPU A12.
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    chr_ram: Vec<u8>,
+    battery: bool,
+    board: Board,
+    header_mirroring: Mirroring,
+    old_irq: bool, // MMC3A and Sharp parts only fire when the counter decrements or is reloaded to 0
+
+    bank_select: u8,
+    banks: [u8; 8],
+    horizontal: bool,
+    ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    a12: bool,
+    a12_low_cycles: u32
+}
+
+impl Mmc3 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let board: Board = match (cartridge.header.mapper, cartridge.header.submapper) {
+            (118, _) => Board::Txsrom,
+            (119, _) => Board::Tqrom,
+            (_, 1) => Board::Mmc6,
+            _ => Board::Mmc3
+        };
+        let prg_ram: Vec<u8> = if board == Board::Mmc6 { vec![0; 0x400] } else { prg_ram(&cartridge) };
+
+        Self {
+            prg_ram,
+            chr: Chr::new(&cartridge),
+            chr_ram: if board == Board::Tqrom { vec![0; 0x2000] } else { Vec::new() },
+            battery: cartridge.header.battery,
+            board,
+            header_mirroring: cartridge.header.mirroring,
+            old_irq: cartridge.header.submapper == 4,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            horizontal: false,
+            ram_protect: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+            a12_low_cycles: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let second_last: usize = bank_from_end(self.prg_rom.len(), 0x2000, 2);
+        let last: usize = bank_from_end(self.prg_rom.len(), 0x2000, 1);
+        let swap: bool = self.bank_select & 0b0100_0000 != 0;
+        let bank: usize = match (address >> 13) & 0b11 {
+            0 => if swap { second_last } else { self.banks[6] as usize },
+            1 => self.banks[7] as usize,
+            2 => if swap { self.banks[6] as usize } else { second_last },
+            _ => last
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x2000, address)
+    }
+
+    // Bank register value (in 1 KB units) mapped at a pattern table address
+    fn chr_bank(&self, address: u16) -> u8 {
+        let mut slot: u16 = (address >> 10) & 0b111;
+        if self.bank_select & 0b1000_0000 != 0 {
+            slot ^= 0b100;
+        }
+        match slot {
+            0 => self.banks[0] & 0xFE,
+            1 => self.banks[0] | 0x01,
+            2 => self.banks[1] & 0xFE,
+            3 => self.banks[1] | 0x01,
+            slot => self.banks[slot as usize - 2]
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let previous: u8 = self.irq_counter;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        let fire: bool = if self.old_irq { (previous > 0 || self.irq_reload) && self.irq_counter == 0 } else { self.irq_counter == 0 };
+        if fire && self.irq_enabled {
+            self.irq_pending = true;
+        }
+        self.irq_reload = false;
+    }
+
+    // MMC6 RAM: $A001 bits 5/7 enable reads and 4/6 writes of the lower/upper 512 bytes
+    fn mmc6_access(&self, address: u16, write: bool) -> Option<bool> {
+        if self.bank_select & 0b0010_0000 == 0 || address < 0x7000 {
+            return None;
+        }
+        let upper: bool = address & 0x0200 != 0;
+        let (read_bit, write_bit) = if upper { (0b1000_0000, 0b0100_0000) } else { (0b0010_0000, 0b0001_0000) };
+        if write {
+            Some(self.ram_protect & read_bit != 0 && self.ram_protect & write_bit != 0)
+        } else {
+            Some(self.ram_protect & read_bit != 0)
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match (address & 0xE001, self.board) {
+            (0x8000, _) => self.bank_select = value,
+            (0x8001, _) => self.banks[(self.bank_select & 0b111) as usize] = value,
+            (0xA000, _) => self.horizontal = value & 1 != 0,
+            // MMC6 only accepts protection changes while its RAM is enabled through $8000
+            (0xA001, Board::Mmc6) => if self.bank_select & 0b0010_0000 != 0 { self.ram_protect = value },
+            (0xA001, _) => self.ram_protect = value,
+            (0xC000, _) => self.irq_latch = value,
+            (0xC001, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000, _) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            // a disabled half reads back 0 when the other one is enabled and is open bus otherwise,
+            // which this bus doesn't model, so both come out as 0
+            0x6000..=0x7FFF if self.board == Board::Mmc6 => match self.mmc6_access(address, false) {
+                Some(true) => self.prg_ram[address as usize & 0x03FF],
+                _ => 0
+            },
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() && self.ram_protect & 0b1000_0000 != 0 => {
+                self.prg_ram[bank_offset(self.prg_ram.len(), 0, 0x2000, address)]
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.board == Board::Mmc6 && self.mmc6_access(address, true) == Some(true) => {
+                self.prg_ram[address as usize & 0x03FF] = value;
+            }
+            // MMC3 RAM needs the enable bit set and the write protect bit clear
+            0x6000..=0x7FFF if self.board != Board::Mmc6 && !self.prg_ram.is_empty() && self.ram_protect & 0b1100_0000 == 0b1000_0000 => {
+                let offset: usize = bank_offset(self.prg_ram.len(), 0, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+            0x8000..=0xFFFF => self.write_register(address, value),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        let bank: u8 = self.chr_bank(address);
+        if self.board == Board::Tqrom && bank & 0b0100_0000 != 0 {
+            return self.chr_ram[bank_offset(self.chr_ram.len(), bank as usize, 0x400, address)];
+        }
+        self.chr.read(bank as usize, 0x400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let bank: u8 = self.chr_bank(address);
+        if self.board == Board::Tqrom && bank & 0b0100_0000 != 0 {
+            let offset: usize = bank_offset(self.chr_ram.len(), bank as usize, 0x400, address);
+            self.chr_ram[offset] = value;
+        } else {
+            self.chr.write(bank as usize, 0x400, address, value);
+        }
+    }
+
+    fn ppu_address(&mut self, address: u16) {
+        let a12: bool = address & 0x1000 != 0;
+        if a12 && !self.a12 && self.a12_low_cycles >= A12_FILTER_CYCLES {
+            self.clock_irq_counter();
+        }
+        if a12 {
+            self.a12_low_cycles = 0;
+        }
+        self.a12 = a12;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.board {
+            Board::Txsrom => {
+                let page = |nametable: u16| self.chr_bank(nametable * 0x400) >> 7;
+                Mirroring::Pages([page(0), page(1), page(2), page(3)])
+            }
+            _ if self.header_mirroring == Mirroring::FourScreen => Mirroring::FourScreen,
+            _ => if self.horizontal { Mirroring::Horizontal } else { Mirroring::Vertical }
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_cycle(&mut self) {
+        if !self.a12 {
+            self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+        }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::ppu::Ppu;
+
+    // NES 2.0 image with 128 KB of PRG, 64 KB of CHR in 1 KB banks filled with their own
+    // number and 8 KB of PRG RAM
+    fn mapper(mapper: u8, submapper: u8) -> Mmc3 {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([8, 8, (mapper & 0x0F) << 4, (mapper & 0xF0) | 0x08, submapper << 4, 0, 0x07, 0, 0, 0, 0, 0]);
+        rom.extend(vec![0; 8 * 0x4000]);
+        for bank in 0..64 {
+            rom.extend(vec![bank; 0x400]);
+        }
+        Mmc3::new(Cartridge::from_bytes(&rom).unwrap())
+    }
+
+    fn set_irq(mmc3: &mut Mmc3, latch: u8) {
+        mmc3.cpu_write(0xC000, latch);
+        mmc3.cpu_write(0xC001, 0);
+        mmc3.cpu_write(0xE001, 0);
+    }
+
+    // Background from $0000 and sprites from $1000, so A12 rises once a line at the sprite fetches
+    fn start_rendering(ppu: &mut Ppu, mmc3: &mut Mmc3) {
+        ppu.write_register(0x2000, 0x08, mmc3);
+        ppu.write_register(0x2001, 0x18, mmc3);
+    }
+
+    // Three dots to a CPU cycle, the filter counts the cycles A12 stays low
+    fn step_until_irq(ppu: &mut Ppu, mmc3: &mut Mmc3) {
+        while !mmc3.irq() {
+            for _ in 0..3 {
+                ppu.step(mmc3);
+            }
+            mmc3.cpu_cycle();
+        }
+    }
+
+    // A12 held low long enough to pass the filter, then raised
+    fn clock_scanline(mmc3: &mut Mmc3) {
+        mmc3.ppu_address(0x0000);
+        for _ in 0..A12_FILTER_CYCLES {
+            mmc3.cpu_cycle();
+        }
+        mmc3.ppu_address(0x1000);
+    }
+
+    #[test]
+    fn a12_clocks_the_scanline_irq() {
+        let mut ppu = Ppu::new();
+        let mut mmc3 = mapper(4, 0);
+        set_irq(&mut mmc3, 10);
+        start_rendering(&mut ppu, &mut mmc3);
+
+        // the first rise reloads the counter, the tenth after it takes it to 0
+        step_until_irq(&mut ppu, &mut mmc3);
+        assert_eq!(ppu.scanline, 10);
+        assert!((257..=320).contains(&ppu.dot));
+
+        mmc3.cpu_write(0xE000, 0);
+        assert!(!mmc3.irq());
+        mmc3.cpu_write(0xE001, 0);
+        step_until_irq(&mut ppu, &mut mmc3);
+        assert_eq!(ppu.scanline, 21);
+
+        // with rendering off A12 never rises
+        mmc3.cpu_write(0xE000, 0);
+        mmc3.cpu_write(0xE001, 0);
+        ppu.write_register(0x2001, 0x00, &mut mmc3);
+        for _ in 0..341 * 262 {
+            ppu.step(&mut mmc3);
+        }
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    fn a12_filter() {
+        let mut mmc3 = mapper(4, 0);
+        set_irq(&mut mmc3, 2);
+        clock_scanline(&mut mmc3);
+        assert_eq!(mmc3.irq_counter, 2);
+
+        // short dips, like the ones between sprite fetches, don't count
+        for _ in 0..4 {
+            mmc3.ppu_address(0x0000);
+            mmc3.cpu_cycle();
+            mmc3.ppu_address(0x1000);
+        }
+        assert_eq!(mmc3.irq_counter, 2);
+
+        clock_scanline(&mut mmc3);
+        clock_scanline(&mut mmc3);
+        assert!(mmc3.irq());
+    }
+
+    #[test]
+    fn new_irq_fires_every_line_with_a_zero_latch() {
+        let mut mmc3 = mapper(4, 0);
+        set_irq(&mut mmc3, 0);
+        for _ in 0..3 {
+            clock_scanline(&mut mmc3);
+            assert!(mmc3.irq());
+            mmc3.cpu_write(0xE000, 0);
+            mmc3.cpu_write(0xE001, 0);
+        }
+    }
+
+    #[test]
+    fn old_irq_needs_a_decrement_or_a_reload() {
+        let mut mmc3 = mapper(4, 4);
+        set_irq(&mut mmc3, 0);
+        clock_scanline(&mut mmc3);
+        assert!(mmc3.irq());
+        mmc3.cpu_write(0xE000, 0);
+        mmc3.cpu_write(0xE001, 0);
+
+        // reloading 0 from 0 again stays quiet
+        clock_scanline(&mut mmc3);
+        assert!(!mmc3.irq());
+
+        // counting down from 1 to 0 still fires
+        set_irq(&mut mmc3, 1);
+        clock_scanline(&mut mmc3);
+        assert!(!mmc3.irq());
+        clock_scanline(&mut mmc3);
+        assert!(mmc3.irq());
+    }
+
+    #[test]
+    fn mmc6_ram_protection() {
+        let mut mmc3 = mapper(4, 1);
+        assert_eq!(mmc3.prg_ram.len(), 0x400);
+
+        // nothing gets through until $8000 bit 5 enables the RAM
+        mmc3.cpu_write(0xA001, 0xF0);
+        mmc3.cpu_write(0x7000, 0x11);
+        mmc3.cpu_write(0x8000, 0x20);
+        assert_eq!(mmc3.cpu_read(0x7000), 0);
+
+        // lower half readable and writable, upper half disabled
+        mmc3.cpu_write(0xA001, 0x30);
+        mmc3.cpu_write(0x7000, 0x11);
+        mmc3.cpu_write(0x7200, 0x22);
+        assert_eq!(mmc3.cpu_read(0x7000), 0x11);
+        assert_eq!(mmc3.cpu_read(0x7200), 0);
+        // mirrored through $7000-$7FFF, nothing below
+        assert_eq!(mmc3.cpu_read(0x7C00), 0x11);
+        assert_eq!(mmc3.cpu_read(0x6000), 0);
+
+        // both halves read only
+        mmc3.cpu_write(0xA001, 0xA0);
+        mmc3.cpu_write(0x7000, 0x33);
+        mmc3.cpu_write(0x7200, 0x44);
+        assert_eq!(mmc3.cpu_read(0x7000), 0x11);
+        assert_eq!(mmc3.cpu_read(0x7200), 0);
+
+        mmc3.cpu_write(0xA001, 0xC0);
+        mmc3.cpu_write(0x7200, 0x44);
+        assert_eq!(mmc3.cpu_read(0x7200), 0x44);
+        assert_eq!(mmc3.cpu_read(0x7000), 0);
+
+        // $A001 is locked while the RAM is disabled
+        mmc3.cpu_write(0x8000, 0x00);
+        mmc3.cpu_write(0xA001, 0x30);
+        mmc3.cpu_write(0x8000, 0x20);
+        assert_eq!(mmc3.cpu_read(0x7200), 0x44);
+    }
+
+    #[test]
+    fn txsrom_chr_banks_drive_the_nametables() {
+        let mut ppu = Ppu::new();
+        let mut mmc3 = mapper(118, 0);
+        for (register, bank) in [0x80, 0x00, 0x01, 0x81, 0x82, 0x03].into_iter().enumerate() {
+            mmc3.cpu_write(0x8000, register as u8);
+            mmc3.cpu_write(0x8001, bank);
+        }
+        assert!(matches!(mmc3.mirroring(), Mirroring::Pages([1, 1, 0, 0])));
+        assert_eq!(ppu.read(0x0400, &mut mmc3), 0x81 & 0x7F);
+
+        ppu.write(0x2000, 0x55, &mut mmc3);
+        ppu.write(0x2800, 0x66, &mut mmc3);
+        assert_eq!(ppu.read(0x2400, &mut mmc3), 0x55);
+        assert_eq!(ppu.read(0x2C00, &mut mmc3), 0x66);
+
+        // with the CHR halves swapped the 1 KB registers pick the pages
+        mmc3.cpu_write(0x8000, 0x80);
+        assert!(matches!(mmc3.mirroring(), Mirroring::Pages([0, 1, 1, 0])));
+        // $A000 has no effect
+        mmc3.cpu_write(0xA000, 1);
+        assert!(matches!(mmc3.mirroring(), Mirroring::Pages([0, 1, 1, 0])));
+    }
+
+    #[test]
+    fn tqrom_chr_ram_banks() {
+        let mut ppu = Ppu::new();
+        let mut mmc3 = mapper(119, 0);
+        mmc3.cpu_write(0x8000, 2);
+        mmc3.cpu_write(0x8001, 0x41);
+        mmc3.cpu_write(0x8000, 3);
+        mmc3.cpu_write(0x8001, 0x03);
+
+        ppu.write(0x1000, 0x99, &mut mmc3);
+        assert_eq!(ppu.read(0x1000, &mut mmc3), 0x99);
+        ppu.write(0x1400, 0x99, &mut mmc3);
+        assert_eq!(ppu.read(0x1400, &mut mmc3), 3);
+
+        // CHR RAM is 8 KB, bank $49 is bank $41 again
+        mmc3.cpu_write(0x8000, 3);
+        mmc3.cpu_write(0x8001, 0x49);
+        assert_eq!(ppu.read(0x1400, &mut mmc3), 0x99);
+    }
+}
@@ -0,0 +1,844 @@
+use crate::nes::apu::Pulse;
+use crate::nes::mapper::{
+    bank_offset,
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+// Position of a PPU read within a scanline, counted from the first background nametable
+// fetch at dot 1: 32 tiles of background (4 reads each), 8 sprites (4 reads each), then the
+// two tiles prefetched for the next line
+const SPRITE_FETCHES_START: u32 = 128;
+const PREFETCH_START: u32 = 160;
+const PREFETCH_END: u32 = 168;
+
+// The pulses' envelopes and length counters run off a fixed 240 Hz timer instead of the APU frame counter
+const QUARTER_FRAME_CYCLES: u32 = 7457;
+
+/// Mapper 5. Besides banking it watches the PPU bus to find scanlines (three reads of the
+/// same nametable address in a row start a line), which drives the scanline IRQ, the
+/// sprite/background CHR sets in 8x16 mode, extended attributes and the vertical split.
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+    exram: [u8; 0x400],
+
+    prg_mode: u8,
+    chr_mode: u8,
+    ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametables: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    prg_banks: [u8; 5], // $5113-$5117
+    chr_banks: [u16; 12], // $5120-$5127 sprite (A) set, $5128-$512B background (B) set
+    chr_upper: u8,
+    background_set_written: bool,
+
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    // snooped from $2000/$2001
+    sprite_8x16: bool,
+    rendering: bool,
+
+    last_nametable_address: u16,
+    nametable_matches: u8,
+    idle_cycles: u8,
+    in_frame: bool,
+    scanline: u8,
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    fetch_index: u32,
+    fetches: u32,
+
+    // state of the background tile being fetched
+    tile_exram: u8,
+    tile_split: bool,
+    tile_split_y: u16,
+    tile_split_x: u16,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    pulses: [Pulse; 2],
+    pcm: u8,
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq: bool,
+    audio_cycles: u32
+}
+
+impl Mmc5 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            prg_ram: prg_ram(&cartridge),
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            exram: [0; 0x400],
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            background_set_written: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            sprite_8x16: false,
+            rendering: false,
+            last_nametable_address: 0,
+            nametable_matches: 0,
+            idle_cycles: 0,
+            in_frame: false,
+            scanline: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            fetch_index: 0,
+            fetches: 0,
+            tile_exram: 0,
+            tile_split: false,
+            tile_split_y: 0,
+            tile_split_x: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            pulses: [Pulse::default(), Pulse::default()],
+            pcm: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq: false,
+            audio_cycles: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    /*-------------------------------PRG-------------------------------------*/
+
+    // Maps $6000-$FFFF to (RAM?, offset)
+    fn prg_map(&self, address: u16) -> (bool, usize) {
+        let (register, size): (u8, usize) = match (address, self.prg_mode) {
+            (0x6000..=0x7FFF, _) => (self.prg_banks[0] & 0x7F, 0x2000),
+            (_, 0) => (self.prg_banks[4] | 0x80, 0x8000),
+            (0x8000..=0xBFFF, 1) => (self.prg_banks[2], 0x4000),
+            (_, 1) => (self.prg_banks[4] | 0x80, 0x4000),
+            (0x8000..=0xBFFF, 2) => (self.prg_banks[2], 0x4000),
+            (0xC000..=0xDFFF, 2) => (self.prg_banks[3], 0x2000),
+            (0xE000..=0xFFFF, _) => (self.prg_banks[4] | 0x80, 0x2000),
+            _ => (self.prg_banks[1 + ((address as usize - 0x8000) >> 13)], 0x2000)
+        };
+
+        // registers always count 8 KB banks, bigger windows drop the low bits
+        let span: usize = size / 0x2000 - 1;
+        let bank: usize = (register as usize & 0x7F & !span) | ((address as usize >> 13) & span);
+        if register & 0x80 != 0 {
+            (false, bank_offset(self.prg_rom.len(), bank, 0x2000, address))
+        } else {
+            (true, bank_offset(self.prg_ram.len().max(1), bank & 0x07, 0x2000, address))
+        }
+    }
+
+    /*-------------------------------CHR-------------------------------------*/
+
+    fn sprite_fetch(&self) -> bool {
+        self.in_frame && (SPRITE_FETCHES_START..PREFETCH_START).contains(&self.fetch_index)
+    }
+
+    fn background_fetch(&self) -> bool {
+        self.in_frame && (self.fetch_index < SPRITE_FETCHES_START || (PREFETCH_START..PREFETCH_END).contains(&self.fetch_index))
+    }
+
+    fn chr_offset(&self, address: u16) -> usize {
+        if self.background_fetch() {
+            if self.tile_split {
+                let address: u16 = (address & 0x0FF8) | (self.tile_split_y & 0b111);
+                return bank_offset(self.chr.data.len(), self.split_bank as usize, 0x1000, address);
+            }
+            if self.exram_mode == 1 {
+                let bank: usize = (self.tile_exram as usize & 0x3F) | (self.chr_upper as usize & 0b11) << 6;
+                return bank_offset(self.chr.data.len(), bank, 0x1000, address);
+            }
+        }
+
+        // in 8x16 mode sprites and background each get their own set, otherwise whichever set was written last is used
+        let background_set: bool = if self.sprite_8x16 && self.in_frame { !self.sprite_fetch() } else { self.background_set_written };
+        let slot: usize = address as usize >> 10;
+        let (bank, size): (u16, usize) = if background_set {
+            match self.chr_mode {
+                0 => (self.chr_banks[11], 0x2000),
+                1 => (self.chr_banks[11], 0x1000),
+                2 => (self.chr_banks[9 + (slot & 0b10)], 0x800),
+                _ => (self.chr_banks[8 + (slot & 0b11)], 0x400)
+            }
+        } else {
+            match self.chr_mode {
+                0 => (self.chr_banks[7], 0x2000),
+                1 => (self.chr_banks[3 + (slot & 0b100)], 0x1000),
+                2 => (self.chr_banks[1 + (slot & 0b110)], 0x800),
+                _ => (self.chr_banks[slot], 0x400)
+            }
+        };
+        bank_offset(self.chr.data.len(), bank as usize, size, address)
+    }
+
+    /*-------------------------------Scanlines-------------------------------*/
+
+    fn detect_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+        self.fetch_index = 0;
+        self.fetches = 1;
+    }
+
+    fn leave_frame(&mut self) {
+        self.in_frame = false;
+        self.last_nametable_address = 0;
+        self.nametable_matches = 0;
+    }
+
+    // Works out where the background tile whose nametable byte is being fetched sits
+    fn start_tile(&mut self, offset: u16) {
+        let (tile_x, line): (u16, u16) = if self.fetch_index < SPRITE_FETCHES_START {
+            (self.fetch_index as u16 / 4 + 2, self.scanline as u16)
+        } else {
+            ((self.fetch_index - PREFETCH_START) as u16 / 4, self.scanline as u16 + 1)
+        };
+
+        let threshold: u16 = (self.split_control & 0x1F) as u16;
+        let right_side: bool = self.split_control & 0b0100_0000 != 0;
+        self.tile_split = self.split_control & 0x80 != 0 && self.exram_mode <= 1 &&
+            if right_side { tile_x >= threshold } else { tile_x < threshold };
+        if self.tile_split {
+            let y: u16 = self.split_scroll as u16 + line;
+            self.tile_split_y = if y >= 240 { y - 240 } else { y };
+            self.tile_split_x = tile_x & 0x1F;
+        }
+        self.tile_exram = self.exram[offset as usize & 0x3FF];
+    }
+
+    fn split_nametable(&self) -> u8 {
+        self.exram[(self.tile_split_y as usize / 8) * 32 + self.tile_split_x as usize]
+    }
+
+    fn split_attribute(&self) -> u8 {
+        let attribute: u8 = self.exram[0x3C0 + (self.tile_split_y as usize / 32) * 8 + self.tile_split_x as usize / 4];
+        let shift: u16 = ((self.tile_split_y / 16) & 1) * 4 + ((self.tile_split_x / 2) & 1) * 2;
+        replicate((attribute >> shift) & 0b11)
+    }
+
+    /*-------------------------------Audio-----------------------------------*/
+
+    fn status(&self) -> u8 {
+        (self.pulses[0].length > 0) as u8 | ((self.pulses[1].length > 0) as u8) << 1
+    }
+}
+
+// Attribute byte that gives every quadrant the same palette
+fn replicate(palette: u8) -> u8 {
+    palette * 0b0101_0101
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x5010 => {
+                let value: u8 = (self.pcm_irq as u8) << 7 | self.pcm_read_mode as u8;
+                self.pcm_irq = false;
+                value
+            }
+            0x5015 => self.status(),
+            0x5204 => {
+                let value: u8 = (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irq_pending = false;
+                value
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[address as usize - 0x5C00],
+            0x6000..=0xFFFF => {
+                // the CPU fetching the NMI vector means the PPU left the visible frame
+                if address == 0xFFFA || address == 0xFFFB {
+                    self.leave_frame();
+                }
+                let (ram, offset) = self.prg_map(address);
+                let value: u8 = if ram {
+                    self.prg_ram.get(offset).copied().unwrap_or(0)
+                } else {
+                    self.prg_rom[offset]
+                };
+                if self.pcm_read_mode && (0x8000..=0xBFFF).contains(&address) {
+                    if value == 0 {
+                        self.pcm_irq = true;
+                    } else {
+                        self.pcm = value;
+                    }
+                }
+                value
+            }
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5007 => self.pulses[(address as usize >> 2) & 1].write(address & 0b11, value),
+            0x5010 => {
+                self.pcm_read_mode = value & 1 != 0;
+                self.pcm_irq_enabled = value & 0x80 != 0;
+            }
+            0x5011 if !self.pcm_read_mode && value != 0 => self.pcm = value,
+            0x5015 => {
+                self.pulses[0].set_enabled(value & 0b01 != 0);
+                self.pulses[1].set_enabled(value & 0b10 != 0);
+            }
+            0x5100 => self.prg_mode = value & 0b11,
+            0x5101 => self.chr_mode = value & 0b11,
+            0x5102 => self.ram_protect[0] = value & 0b11,
+            0x5103 => self.ram_protect[1] = value & 0b11,
+            0x5104 => self.exram_mode = value & 0b11,
+            0x5105 => self.nametables = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0b11,
+            0x5113..=0x5117 => self.prg_banks[address as usize - 0x5113] = value,
+            0x5120..=0x512B => {
+                let index: usize = address as usize - 0x5120;
+                self.chr_banks[index] = value as u16 | (self.chr_upper as u16 & 0b11) << 8;
+                self.background_set_written = index >= 8;
+            }
+            0x5130 => self.chr_upper = value & 0b11,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_compare = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FFF => {
+                let offset: usize = address as usize - 0x5C00;
+                match self.exram_mode {
+                    // as nametable/attribute memory it only takes writes while the PPU is rendering
+                    0 | 1 => self.exram[offset] = if self.in_frame { value } else { 0 },
+                    2 => self.exram[offset] = value,
+                    _ => {}
+                }
+            }
+            0x6000..=0xFFFF => {
+                let (ram, offset) = self.prg_map(address);
+                if ram && self.ram_protect == [0b10, 0b01] && offset < self.prg_ram.len() {
+                    self.prg_ram[offset] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.data[self.chr_offset(address)]
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        if self.chr.writable {
+            let offset: usize = self.chr_offset(address);
+            self.chr.data[offset] = value;
+        }
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        let offset: u16 = address & 0x3FF;
+
+        if self.background_fetch() {
+            match self.fetch_index % 4 {
+                0 => {
+                    self.start_tile(offset);
+                    if self.tile_split {
+                        return Some(self.split_nametable());
+                    }
+                }
+                1 if self.tile_split => return Some(self.split_attribute()),
+                1 if self.exram_mode == 1 => return Some(replicate(self.tile_exram >> 6)),
+                _ => {}
+            }
+        }
+
+        let quadrant: u16 = (address >> 10) & 0b11;
+        match (self.nametables >> (quadrant * 2)) & 0b11 {
+            0 | 1 => None,
+            2 => Some(if self.exram_mode <= 1 { self.exram[offset as usize] } else { 0 }),
+            _ => Some(if offset < 0x3C0 { self.fill_tile } else { replicate(self.fill_attribute) })
+        }
+    }
+
+    fn nametable_write(&mut self, address: u16, value: u8) -> bool {
+        let quadrant: u16 = (address >> 10) & 0b11;
+        match (self.nametables >> (quadrant * 2)) & 0b11 {
+            0 | 1 => false,
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[address as usize & 0x3FF] = value;
+                }
+                true
+            }
+            _ => true
+        }
+    }
+
+    fn ppu_register_write(&mut self, address: u16, value: u8) {
+        match address & 0x2007 {
+            0x2000 => self.sprite_8x16 = value & 0b0010_0000 != 0,
+            0x2001 => {
+                self.rendering = value & 0b0001_1000 != 0;
+                if !self.rendering {
+                    self.leave_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_address(&mut self, address: u16) {
+        self.idle_cycles = 0;
+        self.fetch_index = self.fetches;
+        self.fetches += 1;
+
+        if (0x2000..=0x2FFF).contains(&address) && address == self.last_nametable_address {
+            self.nametable_matches += 1;
+            if self.nametable_matches == 2 {
+                self.detect_scanline();
+            }
+        } else {
+            self.nametable_matches = 0;
+        }
+        self.last_nametable_address = address;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        let page = |quadrant: u8| (self.nametables >> (quadrant * 2)) & 1;
+        Mirroring::Pages([page(0), page(1), page(2), page(3)])
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || (self.pcm_irq && self.pcm_irq_enabled)
+    }
+
+    fn cpu_cycle(&mut self) {
+        // the PPU stops reading once rendering ends, after a few idle cycles MMC5 drops out of the frame
+        self.idle_cycles = self.idle_cycles.saturating_add(1);
+        if self.idle_cycles >= 3 && self.in_frame {
+            self.leave_frame();
+        }
+
+        self.audio_cycles += 1;
+        if self.audio_cycles.is_multiple_of(2) {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_timer();
+            }
+        }
+        if self.audio_cycles >= QUARTER_FRAME_CYCLES {
+            self.audio_cycles = 0;
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_envelope();
+                pulse.clock_length();
+            }
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        // same non-linear mix as the 2A03 pulses, PCM mixed in like the DMC
+        let pulses: f32 = (self.pulses[0].output() + self.pulses[1].output()) as f32;
+        let pulse_out: f32 = if pulses == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulses + 100.0) };
+        let pcm: f32 = self.pcm as f32 / 2.0;
+        let pcm_out: f32 = if pcm == 0.0 { 0.0 } else { 159.79 / (22638.0 / pcm + 100.0) };
+        pulse_out + pcm_out
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::ppu::Ppu;
+
+    // NES 2.0 image with 128 KB of PRG (sixteen 8 KB banks), 64 KB of CHR (sixty four 1 KB
+    // banks) and 64 KB of PRG RAM, every bank filled with its own number
+    fn mapper() -> Mmc5 {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([8, 8, 0x50, 0x08, 0, 0, 0x0A, 0, 0, 0, 0, 0]);
+        for bank in 0..16 {
+            rom.extend(vec![bank; 0x2000]);
+        }
+        for bank in 0..64 {
+            rom.extend(vec![bank; 0x400]);
+        }
+        Mmc5::new(Cartridge::from_bytes(&rom).unwrap())
+    }
+
+    // The PPU reads the same nametable byte three times in a row at the start of each line
+    fn start_scanline(mmc5: &mut Mmc5, address: u16) {
+        mmc5.ppu_address(0x0000);
+        for _ in 0..3 {
+            mmc5.ppu_address(address);
+        }
+    }
+
+    fn fetch(mmc5: &mut Mmc5, address: u16) -> u8 {
+        mmc5.ppu_address(address);
+        mmc5.ppu_read(address)
+    }
+
+    fn fetch_nametable(mmc5: &mut Mmc5, address: u16) -> Option<u8> {
+        mmc5.ppu_address(address);
+        mmc5.nametable_read(address)
+    }
+
+    fn step_to(ppu: &mut Ppu, mmc5: &mut Mmc5, scanline: u16) {
+        while ppu.scanline != scanline {
+            ppu.step(mmc5);
+        }
+    }
+
+    fn unlock_ram(mmc5: &mut Mmc5) {
+        mmc5.cpu_write(0x5102, 0b10);
+        mmc5.cpu_write(0x5103, 0b01);
+    }
+
+    #[test]
+    fn multiplier() {
+        let mut mmc5 = mapper();
+        assert_eq!(mmc5.cpu_read(0x5205), 0x01);
+        assert_eq!(mmc5.cpu_read(0x5206), 0xFE);
+
+        mmc5.cpu_write(0x5205, 0x12);
+        mmc5.cpu_write(0x5206, 0x34);
+        assert_eq!(mmc5.cpu_read(0x5205), 0xA8);
+        assert_eq!(mmc5.cpu_read(0x5206), 0x03);
+    }
+
+    #[test]
+    fn prg_modes() {
+        let mut mmc5 = mapper();
+        let banks = |mmc5: &mut Mmc5| [0x8000, 0xA000, 0xC000, 0xE000].map(|address| mmc5.cpu_read(address));
+        assert_eq!(mmc5.cpu_read(0xE000), 15);
+
+        // 32 KB, the low two bits of $5117 are ignored
+        mmc5.cpu_write(0x5100, 0);
+        mmc5.cpu_write(0x5117, 0x85);
+        assert_eq!(banks(&mut mmc5), [4, 5, 6, 7]);
+
+        // 16 KB + 16 KB
+        mmc5.cpu_write(0x5100, 1);
+        mmc5.cpu_write(0x5115, 0x87);
+        mmc5.cpu_write(0x5117, 0x8B);
+        assert_eq!(banks(&mut mmc5), [6, 7, 10, 11]);
+
+        // 16 KB + 8 KB + 8 KB
+        mmc5.cpu_write(0x5100, 2);
+        mmc5.cpu_write(0x5116, 0x89);
+        mmc5.cpu_write(0x5117, 0x8D);
+        assert_eq!(banks(&mut mmc5), [6, 7, 9, 13]);
+
+        // four 8 KB banks
+        mmc5.cpu_write(0x5100, 3);
+        mmc5.cpu_write(0x5114, 0x81);
+        mmc5.cpu_write(0x5115, 0x82);
+        mmc5.cpu_write(0x5116, 0x83);
+        mmc5.cpu_write(0x5117, 0x84);
+        assert_eq!(banks(&mut mmc5), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn prg_ram_banks_and_protection() {
+        let mut mmc5 = mapper();
+        mmc5.cpu_write(0x5113, 2);
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_read(0x6000), 0);
+
+        unlock_ram(&mut mmc5);
+        mmc5.cpu_write(0x6000, 0x42);
+        assert_eq!(mmc5.cpu_read(0x6000), 0x42);
+
+        // bit 7 clear maps the same RAM bank into the ROM area
+        mmc5.cpu_write(0x5114, 0x02);
+        assert_eq!(mmc5.cpu_read(0x8000), 0x42);
+        mmc5.cpu_write(0x8001, 0x43);
+        assert_eq!(mmc5.cpu_read(0x6001), 0x43);
+
+        mmc5.cpu_write(0x5113, 3);
+        assert_eq!(mmc5.cpu_read(0x6000), 0);
+
+        // any other protect value locks the RAM again
+        mmc5.cpu_write(0x5103, 0b11);
+        mmc5.cpu_write(0x8000, 0x99);
+        assert_eq!(mmc5.cpu_read(0x8000), 0x42);
+    }
+
+    #[test]
+    fn chr_modes() {
+        let mut mmc5 = mapper();
+        let banks = |mmc5: &mut Mmc5| [0x0000, 0x0400, 0x0800, 0x0C00, 0x1000, 0x1400, 0x1800, 0x1C00].map(|address| mmc5.ppu_read(address));
+
+        mmc5.cpu_write(0x5101, 0);
+        mmc5.cpu_write(0x5127, 1);
+        assert_eq!(banks(&mut mmc5), [8, 9, 10, 11, 12, 13, 14, 15]);
+
+        mmc5.cpu_write(0x5101, 1);
+        mmc5.cpu_write(0x5123, 2);
+        mmc5.cpu_write(0x5127, 5);
+        assert_eq!(banks(&mut mmc5), [8, 9, 10, 11, 20, 21, 22, 23]);
+
+        mmc5.cpu_write(0x5101, 2);
+        mmc5.cpu_write(0x5121, 3);
+        mmc5.cpu_write(0x5123, 4);
+        mmc5.cpu_write(0x5125, 6);
+        mmc5.cpu_write(0x5127, 9);
+        assert_eq!(banks(&mut mmc5), [6, 7, 8, 9, 12, 13, 18, 19]);
+
+        mmc5.cpu_write(0x5101, 3);
+        for slot in 0..8 {
+            mmc5.cpu_write(0x5120 + slot, 40 + slot as u8);
+        }
+        assert_eq!(banks(&mut mmc5), [40, 41, 42, 43, 44, 45, 46, 47]);
+
+        // outside 8x16 rendering the set written last wins, the B set repeats over both halves
+        for slot in 0..4 {
+            mmc5.cpu_write(0x5128 + slot, 50 + slot as u8);
+        }
+        assert_eq!(banks(&mut mmc5), [50, 51, 52, 53, 50, 51, 52, 53]);
+    }
+
+    #[test]
+    fn sprites_8x16_use_their_own_chr_set() {
+        let mut mmc5 = mapper();
+        mmc5.cpu_write(0x5101, 3);
+        for slot in 0..8 {
+            mmc5.cpu_write(0x5120 + slot, 16 + slot as u8);
+        }
+        for slot in 0..4 {
+            mmc5.cpu_write(0x5128 + slot, 32 + slot as u8);
+        }
+        mmc5.ppu_register_write(0x2000, 0x20);
+        mmc5.ppu_register_write(0x2001, 0x18);
+
+        start_scanline(&mut mmc5, 0x2000);
+        assert_eq!(fetch(&mut mmc5, 0x0000), 32);
+        assert_eq!(fetch(&mut mmc5, 0x1400), 33);
+        while mmc5.fetch_index < SPRITE_FETCHES_START {
+            mmc5.ppu_address(0x2000);
+        }
+        assert_eq!(fetch(&mut mmc5, 0x0000), 16);
+        assert_eq!(fetch(&mut mmc5, 0x1400), 21);
+        while mmc5.fetch_index < PREFETCH_START {
+            mmc5.ppu_address(0x2000);
+        }
+        assert_eq!(fetch(&mut mmc5, 0x1400), 33);
+
+        // 8x8 sprites use whichever set was written last for everything
+        mmc5.ppu_register_write(0x2000, 0x00);
+        assert_eq!(fetch(&mut mmc5, 0x1400), 33);
+    }
+
+    #[test]
+    fn scanline_irq_and_in_frame() {
+        let mut ppu = Ppu::new();
+        let mut mmc5 = mapper();
+        ppu.write_register(0x2001, 0x08, &mut mmc5);
+        mmc5.ppu_register_write(0x2001, 0x08);
+
+        // the power-on frame has no pre-render line, let it run out and idle through vblank
+        step_to(&mut ppu, &mut mmc5, 241);
+        for _ in 0..3 {
+            mmc5.cpu_cycle();
+        }
+        assert_eq!(mmc5.cpu_read(0x5204), 0x00);
+        mmc5.cpu_write(0x5203, 100);
+        mmc5.cpu_write(0x5204, 0x80);
+
+        step_to(&mut ppu, &mut mmc5, 50);
+        assert_eq!(mmc5.cpu_read(0x5204), 0x40);
+        step_to(&mut ppu, &mut mmc5, 100);
+        assert!(!mmc5.irq());
+        step_to(&mut ppu, &mut mmc5, 101);
+        assert!(mmc5.irq());
+        assert_eq!(mmc5.cpu_read(0x5204), 0xC0);
+        assert!(!mmc5.irq());
+
+        // the CPU fetching the NMI vector ends the frame
+        step_to(&mut ppu, &mut mmc5, 241);
+        assert_eq!(mmc5.cpu_read(0x5204), 0x40);
+        mmc5.cpu_read(0xFFFA);
+        assert_eq!(mmc5.cpu_read(0x5204), 0x00);
+
+        // as does turning rendering off
+        step_to(&mut ppu, &mut mmc5, 20);
+        assert_eq!(mmc5.cpu_read(0x5204), 0x40);
+        mmc5.ppu_register_write(0x2001, 0x00);
+        assert_eq!(mmc5.cpu_read(0x5204), 0x00);
+    }
+
+    #[test]
+    fn scanline_irq_can_be_masked() {
+        let mut mmc5 = mapper();
+        mmc5.cpu_write(0x5203, 2);
+        mmc5.ppu_register_write(0x2001, 0x08);
+        for _ in 0..3 {
+            start_scanline(&mut mmc5, 0x2000);
+        }
+        assert!(!mmc5.irq());
+        mmc5.cpu_write(0x5204, 0x80);
+        assert!(mmc5.irq());
+        assert_eq!(mmc5.cpu_read(0x5204), 0xC0);
+        assert!(!mmc5.irq());
+    }
+
+    #[test]
+    fn exram_modes() {
+        let mut mmc5 = mapper();
+        mmc5.cpu_write(0x5105, 0b10);
+
+        // mode 2 is plain CPU RAM
+        mmc5.cpu_write(0x5104, 2);
+        mmc5.cpu_write(0x5C00, 0x42);
+        assert_eq!(mmc5.cpu_read(0x5C00), 0x42);
+        assert_eq!(mmc5.nametable_read(0x2000), Some(0));
+
+        // mode 3 is read only
+        mmc5.cpu_write(0x5104, 3);
+        mmc5.cpu_write(0x5C00, 0x99);
+        assert_eq!(mmc5.cpu_read(0x5C00), 0x42);
+
+        // modes 0 and 1 hide it from the CPU and act as a nametable
+        mmc5.cpu_write(0x5104, 0);
+        assert_eq!(mmc5.cpu_read(0x5C00), 0);
+        assert_eq!(mmc5.nametable_read(0x2000), Some(0x42));
+        assert!(mmc5.nametable_write(0x2001, 0x24));
+        assert_eq!(mmc5.nametable_read(0x2001), Some(0x24));
+
+        // CPU writes outside rendering store zero
+        mmc5.cpu_write(0x5C00, 0x99);
+        assert_eq!(mmc5.nametable_read(0x2000), Some(0));
+        start_scanline(&mut mmc5, 0x2400);
+        mmc5.cpu_write(0x5C00, 0x99);
+        for _ in 0..3 {
+            mmc5.cpu_cycle();
+        }
+        assert_eq!(mmc5.nametable_read(0x2000), Some(0x99));
+    }
+
+    #[test]
+    fn extended_attributes() {
+        let mut mmc5 = mapper();
+        mmc5.cpu_write(0x5104, 2);
+        mmc5.cpu_write(0x5C05, 0b1000_0011);
+        mmc5.cpu_write(0x5104, 1);
+        mmc5.ppu_register_write(0x2001, 0x08);
+
+        start_scanline(&mut mmc5, 0x2005);
+        assert_eq!(mmc5.nametable_read(0x2005), None);
+        assert_eq!(fetch_nametable(&mut mmc5, 0x23C1), Some(0xAA));
+        // bits 0-5 pick a 4 KB CHR bank for the tile
+        assert_eq!(fetch(&mut mmc5, 0x0010), 12);
+        assert_eq!(fetch(&mut mmc5, 0x0418), 13);
+    }
+
+    #[test]
+    fn fill_mode() {
+        let mut mmc5 = mapper();
+        mmc5.cpu_write(0x5105, 0b11_10_01_00);
+        mmc5.cpu_write(0x5106, 0x33);
+        mmc5.cpu_write(0x5107, 0b10);
+        assert!(matches!(mmc5.mirroring(), Mirroring::Pages([0, 1, 0, 1])));
+
+        assert_eq!(mmc5.nametable_read(0x2000), None);
+        assert_eq!(mmc5.nametable_read(0x2400), None);
+        assert_eq!(mmc5.nametable_read(0x2C00), Some(0x33));
+        assert_eq!(mmc5.nametable_read(0x2FC0), Some(0xAA));
+        assert!(mmc5.nametable_write(0x2C00, 0x12));
+        assert_eq!(mmc5.nametable_read(0x2C00), Some(0x33));
+    }
+
+    #[test]
+    fn vertical_split() {
+        let mut mmc5 = mapper();
+        mmc5.cpu_write(0x5104, 2);
+        mmc5.cpu_write(0x5C00 + 32 + 2, 0x77);
+        mmc5.cpu_write(0x5C00 + 32 + 3, 0x78);
+        mmc5.cpu_write(0x5C00 + 0x3C0, 0b0000_1100);
+        mmc5.cpu_write(0x5104, 0);
+        // tiles left of column 4 come from ExRAM, scrolled down a row, with CHR from 4 KB bank 5
+        mmc5.cpu_write(0x5200, 0x80 | 4);
+        mmc5.cpu_write(0x5201, 8);
+        mmc5.cpu_write(0x5202, 5);
+        mmc5.ppu_register_write(0x2001, 0x08);
+
+        start_scanline(&mut mmc5, 0x2002);
+        assert_eq!(mmc5.nametable_read(0x2002), Some(0x77));
+        assert_eq!(fetch_nametable(&mut mmc5, 0x23C0), Some(0xFF));
+        assert_eq!(fetch(&mut mmc5, 0x0770), 21);
+        assert_eq!(fetch(&mut mmc5, 0x0778), 21);
+        assert_eq!(fetch_nametable(&mut mmc5, 0x2003), Some(0x78));
+        mmc5.ppu_address(0x23C0);
+        mmc5.ppu_address(0x0000);
+        mmc5.ppu_address(0x0008);
+        assert_eq!(fetch_nametable(&mut mmc5, 0x2004), None);
+        mmc5.ppu_address(0x23C1);
+        assert_eq!(fetch(&mut mmc5, 0x0000), 0);
+    }
+
+    #[test]
+    fn pcm_read_mode_irq() {
+        let mut mmc5 = mapper();
+        mmc5.cpu_write(0x5010, 0x81);
+        mmc5.cpu_write(0x5114, 0x83);
+        assert_eq!(mmc5.cpu_read(0x8000), 3);
+        assert!(!mmc5.irq());
+        assert!(mmc5.audio_output() > 0.0);
+
+        // a zero byte ends the sample
+        mmc5.cpu_write(0x5114, 0x80);
+        mmc5.cpu_read(0x8000);
+        assert!(mmc5.irq());
+        assert_eq!(mmc5.cpu_read(0x5010), 0x81);
+        assert!(!mmc5.irq());
+
+        // the flag still latches with the IRQ disabled
+        mmc5.cpu_write(0x5010, 0x01);
+        mmc5.cpu_read(0x8000);
+        assert!(!mmc5.irq());
+        assert_eq!(mmc5.cpu_read(0x5010), 0x81);
+        assert_eq!(mmc5.cpu_read(0x5010), 0x01);
+
+        // only $8000-$BFFF feeds the PCM channel
+        mmc5.cpu_write(0x5010, 0x81);
+        mmc5.cpu_write(0x5116, 0x80);
+        mmc5.cpu_read(0xC000);
+        assert!(!mmc5.irq());
+    }
+}
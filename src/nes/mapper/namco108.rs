@@ -0,0 +1,85 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 206, Namco 108 (DxROM). The MMC3's bank select/data pair without its PRG/CHR
+/// inversion, IRQ, work RAM or mirroring control: two 8 KB PRG banks with the last 16 KB
+/// fixed, two 2 KB CHR banks at $0000 and four 1 KB ones at $1000.
+pub struct Namco108 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+
+    bank_select: u8,
+    banks: [u8; 8]
+}
+
+impl Namco108 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            chr: Chr::new(&cartridge),
+            mirroring: cartridge.header.mirroring,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let second_last: usize = bank_from_end(self.prg_rom.len(), 0x2000, 2);
+        let last: usize = bank_from_end(self.prg_rom.len(), 0x2000, 1);
+        let bank: usize = match (address >> 13) & 0b11 {
+            0 => (self.banks[6] & 0x0F) as usize,
+            1 => (self.banks[7] & 0x0F) as usize,
+            2 => second_last,
+            _ => last
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x2000, address)
+    }
+
+    fn chr_bank(&self, address: u16) -> usize {
+        let slot: usize = (address >> 10) as usize & 0b111;
+        let bank: u8 = match slot {
+            0 | 1 => (self.banks[0] & 0x3E) | slot as u8,
+            2 | 3 => (self.banks[1] & 0x3E) | (slot as u8 - 2),
+            _ => self.banks[slot - 2] & 0x3F
+        };
+        bank as usize
+    }
+}
+
+impl Mapper for Namco108 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address & 0xE001 {
+            0x8000 => self.bank_select = value & 0b111,
+            0x8001 => self.banks[self.bank_select as usize] = value,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_bank(address), 0x400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_bank(address), 0x400, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
@@ -0,0 +1,412 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+const INTERNAL_RAM_SIZE: usize = 0x80;
+
+// Bank numbers from here up select a page of the console's nametable RAM instead of CHR ROM
+const CIRAM_BANKS: u8 = 0xE0;
+
+// One wavetable channel is updated every 15 CPU cycles, the enabled channels take turns
+const CHANNEL_UPDATE_CYCLES: u8 = 15;
+// Output of a channel at full volume and full sample swing
+const OUTPUT_SCALE: f32 = 0.002;
+
+/// Mapper 19. Three switchable 8 KB PRG banks, 1 KB CHR banks that can also point at the
+/// nametable RAM, nametables that can come from CHR ROM, a 15 bit IRQ counter and up to
+/// eight wavetable channels playing 4 bit samples out of 128 bytes of internal RAM.
+pub struct Namco163 {
+    prg_rom: Vec<u8>,
+    ram: Vec<u8>, // work RAM followed by the 128 bytes of internal RAM, both battery backed
+    work_ram: usize,
+    chr: Chr,
+    battery: bool,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    nametable_banks: [u8; 4],
+    ciram_disabled: [bool; 2], // per pattern table, banks $E0+ stay CHR ROM
+    sound_disabled: bool,
+    ram_address: u8,
+    auto_increment: bool,
+    write_protect: u8, // $F800
+
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    update_cycles: u8,
+    current_channel: u8,
+    channel_outputs: [f32; 8]
+}
+
+impl Namco163 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        // NES 2.0 headers give 128 bytes of NVRAM for boards that only save the internal RAM
+        let mut ram: Vec<u8> = prg_ram(&cartridge);
+        if ram.len() == INTERNAL_RAM_SIZE {
+            ram.clear();
+        }
+        let work_ram: usize = ram.len();
+        ram.resize(work_ram + INTERNAL_RAM_SIZE, 0);
+
+        Self {
+            ram,
+            work_ram,
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            nametable_banks: [CIRAM_BANKS; 4],
+            ciram_disabled: [false; 2],
+            sound_disabled: false,
+            ram_address: 0,
+            auto_increment: false,
+            write_protect: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            update_cycles: 0,
+            current_channel: 7,
+            channel_outputs: [0.0; 8],
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank: usize = match address {
+            0x8000..=0xDFFF => (self.prg_banks[(address as usize - 0x8000) >> 13] & 0x3F) as usize,
+            _ => bank_from_end(self.prg_rom.len(), 0x2000, 1)
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x2000, address)
+    }
+
+    // $F800 has to read $4x and the 2 KB block's bit be clear for work RAM writes to go through
+    fn prg_ram_writable(&self, address: u16) -> bool {
+        let block: u16 = (address - 0x6000) >> 11;
+        self.work_ram > 0 && self.write_protect & 0xF0 == 0x40 && self.write_protect & (1 << block) == 0
+    }
+
+    fn internal_ram(&self) -> &[u8] {
+        &self.ram[self.work_ram..]
+    }
+
+    fn internal_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram[self.work_ram..]
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let value: u8 = self.internal_ram()[self.ram_address as usize];
+        self.step_address();
+        value
+    }
+
+    fn write_data(&mut self, value: u8) {
+        let address: usize = self.ram_address as usize;
+        self.internal_ram_mut()[address] = value;
+        self.step_address();
+    }
+
+    fn step_address(&mut self) {
+        if self.auto_increment {
+            self.ram_address = (self.ram_address + 1) & 0x7F;
+        }
+    }
+
+    /*-------------------------------Audio-----------------------------------*/
+
+    // Channels 8 - count through 7 are enabled, count is set in the last channel's registers
+    fn enabled_channels(&self) -> u8 {
+        ((self.internal_ram()[0x7F] >> 4) & 0b111) + 1
+    }
+
+    fn update_channel(&mut self, channel: u8) {
+        let base: usize = 0x40 + channel as usize * 8;
+        let registers: &mut [u8] = &mut self.internal_ram_mut()[base..base + 8];
+
+        let frequency: u32 = registers[0] as u32 | (registers[2] as u32) << 8 | (registers[4] as u32 & 0b11) << 16;
+        let length: u32 = 256 - (registers[4] as u32 & 0xFC);
+        let mut phase: u32 = registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        phase = (phase + frequency) % (length << 16);
+        registers[1] = phase as u8;
+        registers[3] = (phase >> 8) as u8;
+        registers[5] = (phase >> 16) as u8;
+
+        let wave_address: u32 = registers[6] as u32;
+        let volume: u8 = registers[7] & 0x0F;
+        let nibble: u32 = ((phase >> 16) + wave_address) & 0xFF;
+        let byte: u8 = self.internal_ram()[nibble as usize >> 1];
+        let sample: u8 = if nibble & 1 == 0 { byte & 0x0F } else { byte >> 4 };
+
+        self.channel_outputs[channel as usize] = (sample as f32 - 8.0) * volume as f32;
+    }
+
+    fn step_audio(&mut self) {
+        self.update_cycles += 1;
+        if self.update_cycles < CHANNEL_UPDATE_CYCLES {
+            return;
+        }
+        self.update_cycles = 0;
+
+        let first: u8 = 8 - self.enabled_channels();
+        if self.current_channel < first {
+            self.current_channel = 7;
+        }
+        self.update_channel(self.current_channel);
+        self.current_channel = if self.current_channel <= first { 7 } else { self.current_channel - 1 };
+    }
+}
+
+impl Mapper for Namco163 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x4800..=0x4FFF => self.read_data(),
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_enabled as u8) << 7 | (self.irq_counter >> 8) as u8,
+            0x6000..=0x7FFF if self.work_ram > 0 => self.ram[bank_offset(self.work_ram, 0, 0x2000, address)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x4800..=0x4FFF => self.write_data(value),
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | value as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16 & 0x7F) << 8;
+                self.irq_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF if self.prg_ram_writable(address) => {
+                let offset: usize = bank_offset(self.work_ram, 0, 0x2000, address);
+                self.ram[offset] = value;
+            }
+            0x8000..=0xBFFF => self.chr_banks[(address as usize - 0x8000) >> 11] = value,
+            0xC000..=0xDFFF => self.nametable_banks[(address as usize - 0xC000) >> 11] = value,
+            0xE000..=0xE7FF => {
+                self.prg_banks[0] = value & 0x3F;
+                self.sound_disabled = value & 0x40 != 0;
+            }
+            0xE800..=0xEFFF => {
+                self.prg_banks[1] = value & 0x3F;
+                self.ciram_disabled = [value & 0x40 != 0, value & 0x80 != 0];
+            }
+            0xF000..=0xF7FF => self.prg_banks[2] = value & 0x3F,
+            0xF800..=0xFFFF => {
+                self.write_protect = value;
+                self.ram_address = value & 0x7F;
+                self.auto_increment = value & 0x80 != 0;
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address, value);
+    }
+
+    fn chr_ciram_page(&self, address: u16) -> Option<u8> {
+        let bank: u8 = self.chr_banks[(address >> 10) as usize & 0b111];
+        if bank >= CIRAM_BANKS && !self.ciram_disabled[(address >> 12) as usize & 1] { Some(bank & 1) } else { None }
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        let bank: u8 = self.nametable_banks[(address >> 10) as usize & 0b11];
+        if bank >= CIRAM_BANKS { None } else { Some(self.chr.read(bank as usize, 0x400, address)) }
+    }
+
+    fn nametable_write(&mut self, address: u16, value: u8) -> bool {
+        let bank: u8 = self.nametable_banks[(address >> 10) as usize & 0b11];
+        if bank >= CIRAM_BANKS {
+            return false;
+        }
+        self.chr.write(bank as usize, 0x400, address, value);
+        true
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        let page = |quadrant: usize| self.nametable_banks[quadrant] & 1;
+        Mirroring::Pages([page(0), page(1), page(2), page(3)])
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_cycle(&mut self) {
+        // counts up to $7FFF and stops there
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+        if !self.sound_disabled {
+            self.step_audio();
+        }
+    }
+
+    // The chip plays its channels one after another, what comes out is their average
+    fn audio_output(&self) -> f32 {
+        let enabled: u8 = self.enabled_channels();
+        let sum: f32 = self.channel_outputs[(8 - enabled) as usize..].iter().sum();
+        sum / enabled as f32 * OUTPUT_SCALE
+    }
+
+    // The internal RAM is saved after the work RAM
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper() -> Namco163 {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([8, 8, 0x30, 0x10, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom.extend(vec![0; 8 * 0x4000 + 8 * 0x2000]);
+        Namco163::new(Cartridge::from_bytes(&rom).unwrap())
+    }
+
+    // Writes internal RAM from `address` through the auto-incrementing data port
+    fn write_internal(namco: &mut Namco163, address: u8, values: &[u8]) {
+        namco.cpu_write(0xF800, 0x80 | address);
+        for &value in values {
+            namco.cpu_write(0x4800, value);
+        }
+    }
+
+    fn clock(namco: &mut Namco163, cycles: u32) {
+        for _ in 0..cycles {
+            namco.cpu_cycle();
+        }
+    }
+
+    // Phase of a channel in samples
+    fn position(namco: &Namco163, channel: usize) -> u8 {
+        namco.internal_ram()[0x40 + channel * 8 + 5]
+    }
+
+    #[test]
+    fn internal_ram_port() {
+        let mut namco = mapper();
+        write_internal(&mut namco, 0x10, &[1, 2, 3]);
+        assert_eq!(namco.internal_ram()[0x10..0x13], [1, 2, 3]);
+
+        // without auto-increment the port stays on one byte
+        namco.cpu_write(0xF800, 0x11);
+        assert_eq!(namco.cpu_read(0x4800), 2);
+        assert_eq!(namco.cpu_read(0x4800), 2);
+        namco.cpu_write(0x4800, 9);
+        assert_eq!(namco.cpu_read(0x4800), 9);
+
+        // reads increment too, and the address wraps within 128 bytes
+        namco.cpu_write(0xF800, 0x90);
+        assert_eq!([namco.cpu_read(0x4800), namco.cpu_read(0x4800), namco.cpu_read(0x4800)], [1, 9, 3]);
+        write_internal(&mut namco, 0x7F, &[0x44, 0x55]);
+        assert_eq!(namco.internal_ram()[0x7F], 0x44);
+        assert_eq!(namco.internal_ram()[0x00], 0x55);
+    }
+
+    #[test]
+    fn channel_timing() {
+        let mut namco = mapper();
+        // channel 7 only: frequency $10000 steps one sample per update over a 16 sample wave
+        write_internal(&mut namco, 0x78, &[0x00, 0x00, 0x00, 0x00, 0xF1, 0x00, 0x00, 0x0F]);
+        clock(&mut namco, 14);
+        assert_eq!(position(&namco, 7), 0);
+        clock(&mut namco, 1);
+        assert_eq!(position(&namco, 7), 1);
+        clock(&mut namco, 15 * 15);
+        assert_eq!(position(&namco, 7), 0);
+
+        // with two channels enabled each one gets every other update
+        write_internal(&mut namco, 0x70, &[0x00, 0x00, 0x00, 0x00, 0xF1]);
+        write_internal(&mut namco, 0x7F, &[0x1F]);
+        clock(&mut namco, 15);
+        assert_eq!([position(&namco, 6), position(&namco, 7)], [0, 1]);
+        clock(&mut namco, 15);
+        assert_eq!([position(&namco, 6), position(&namco, 7)], [1, 1]);
+        clock(&mut namco, 15 * 4);
+        assert_eq!([position(&namco, 6), position(&namco, 7)], [3, 3]);
+
+        // all eight: 120 cycles between updates of a channel
+        write_internal(&mut namco, 0x40, &[0x00, 0x00, 0x00, 0x00, 0xF1]);
+        write_internal(&mut namco, 0x7F, &[0x7F]);
+        clock(&mut namco, 120);
+        assert_eq!(position(&namco, 7), 4);
+        assert_eq!(position(&namco, 6), 4);
+        assert_eq!(position(&namco, 0), 1);
+
+        // $E000 bit 6 stops the sound
+        namco.cpu_write(0xE000, 0x40);
+        clock(&mut namco, 240);
+        assert_eq!(position(&namco, 0), 1);
+    }
+
+    #[test]
+    fn channel_output() {
+        let mut namco = mapper();
+        // samples 0, 15, 0, 15... from nibble 0 of the RAM, low nibble first
+        write_internal(&mut namco, 0x00, &[0xF0; 8]);
+        write_internal(&mut namco, 0x78, &[0x00, 0x00, 0x00, 0x00, 0xF1, 0x00, 0x00, 0x0F]);
+        clock(&mut namco, 15);
+        assert_eq!(namco.audio_output(), 7.0 * 15.0 * OUTPUT_SCALE);
+        clock(&mut namco, 15);
+        assert_eq!(namco.audio_output(), -8.0 * 15.0 * OUTPUT_SCALE);
+
+        // the wave address offsets the samples, enabled channels are averaged: channel 7 moves
+        // on to sample 3, the stopped channel 6 sits on sample 1
+        write_internal(&mut namco, 0x76, &[0x01, 0x08]);
+        write_internal(&mut namco, 0x7F, &[0x1F]);
+        clock(&mut namco, 30);
+        assert_eq!(namco.audio_output(), (7.0 * 15.0 + 7.0 * 8.0) / 2.0 * OUTPUT_SCALE);
+    }
+
+    #[test]
+    fn irq_counter() {
+        let mut namco = mapper();
+        namco.cpu_write(0x5000, 0xFD);
+        namco.cpu_write(0x5800, 0x7F);
+        clock(&mut namco, 10);
+        assert!(!namco.irq());
+        assert_eq!(namco.cpu_read(0x5000), 0xFD);
+
+        namco.cpu_write(0x5800, 0xFF);
+        clock(&mut namco, 1);
+        assert!(!namco.irq());
+        clock(&mut namco, 1);
+        assert!(namco.irq());
+        // stops at $7FFF
+        clock(&mut namco, 10);
+        assert_eq!([namco.cpu_read(0x5000), namco.cpu_read(0x5800)], [0xFF, 0xFF]);
+
+        namco.cpu_write(0x5000, 0x00);
+        assert!(!namco.irq());
+        namco.cpu_write(0x5800, 0x00);
+        assert_eq!(namco.cpu_read(0x5800), 0x00);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::nes::mapper::{
+    bank_offset,
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 0, no bank switching. NROM-128 carries 16 KB of PRG which shows up at both
+/// $8000 and $C000, NROM-256 fills the whole 32 KB window.
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    battery: bool
+}
+
+impl Nrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            prg_ram: prg_ram(&cartridge),
+            chr: Chr::new(&cartridge),
+            mirroring: cartridge.header.mirroring,
+            battery: cartridge.header.battery,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => self.prg_ram[bank_offset(self.prg_ram.len(), 0, 0x2000, address)],
+            0x8000..=0xFFFF => self.prg_rom[bank_offset(self.prg_rom.len(), 0, 0x8000, address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if let 0x6000..=0x7FFF = address {
+            if !self.prg_ram.is_empty() {
+                let offset: usize = bank_offset(self.prg_ram.len(), 0, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(0, 0x2000, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(0, 0x2000, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
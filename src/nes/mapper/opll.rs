@@ -0,0 +1,644 @@
+use std::f32::consts::PI;
+
+// The VRC7 runs its FM core off a 3.58 MHz clock, 72 clocks per output sample
+pub const CPU_CYCLES_PER_SAMPLE: u32 = 36;
+pub const SAMPLE_RATE: f32 = 49716.0;
+
+const CHANNELS: usize = 6;
+
+// Attenuations are in dB. The envelope reaches silence at 48 dB (7 bits of 0.375 dB).
+const MAX_ATTENUATION: f32 = 48.0;
+const TOTAL_LEVEL_STEP: f32 = 0.75;
+const VOLUME_STEP: f32 = 3.0;
+const SUSTAIN_LEVEL_STEP: f32 = 3.0;
+
+// Time to attack over / decay through 96 dB at rate 1, each further rate step (of 4 key scale steps) halves it
+const ATTACK_TIME: f32 = 2.82624;
+const DECAY_TIME: f32 = 39.28064;
+
+// Release rates forced by the channel's sustain bit and by key off on percussive patches
+const SUSTAIN_RELEASE_RATE: u8 = 5;
+const PERCUSSIVE_RELEASE_RATE: u8 = 7;
+
+// Phase is 19 bits, fnum << block counts in units of half a multiplier step
+const PHASE_BITS: u32 = 19;
+const PHASE_MASK: u32 = (1 << PHASE_BITS) - 1;
+// Phase shift of the carrier at full modulator output
+const MODULATION_DEPTH: f32 = 4.0 * PI;
+
+// Tremolo is a 3.7 Hz triangle 4.8 dB deep, vibrato an 8 step pattern advancing every 1024 samples
+const TREMOLO_PERIOD: u32 = 13436;
+const TREMOLO_DEPTH: f32 = 4.8;
+const VIBRATO_STEP_SAMPLES: u32 = 1024;
+const VIBRATO_TABLE: [i32; 8] = [0, 1, 2, 1, 0, -1, -2, -1];
+
+// Doubled, so index 0 is x0.5
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+// Key scale attenuation at block 7 by the top 4 bits of fnum, 6 dB less per block below
+const KEY_SCALE_TABLE: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25,
+    36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0
+];
+
+// Channel output at 0 dB, six channels at full level stay within the mix
+const OUTPUT_SCALE: f32 = 0.1;
+
+/// The VRC7's built-in instruments, patch 0 is the user defined one at registers $00-$07.
+pub const VRC7_PATCHES: [[u8; 8]; 16] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27], // buzzy bell
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12], // guitar
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12], // wurly
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27], // flute
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28], // clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4], // synth
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07], // trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17], // organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // bells
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02], // vibes
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // tutti
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02], // fretless
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6], // synth bass
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06]  // sweep
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Envelope {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off
+}
+
+// One operator's half of an instrument
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: u8,
+    key_scale_level: u8,
+    rectified: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8
+}
+
+impl OperatorPatch {
+    fn new(patch: &[u8; 8], carrier: bool) -> Self {
+        let index: usize = carrier as usize;
+        let flags: u8 = patch[index];
+        Self {
+            tremolo: flags & 0x80 != 0,
+            vibrato: flags & 0x40 != 0,
+            sustained: flags & 0x20 != 0,
+            key_scale_rate: flags & 0x10 != 0,
+            multiplier: flags & 0x0F,
+            key_scale_level: patch[2 + index] >> 6,
+            rectified: patch[3] & if carrier { 0x10 } else { 0x08 } != 0,
+            attack: patch[4 + index] >> 4,
+            decay: patch[4 + index] & 0x0F,
+            sustain_level: patch[6 + index] >> 4,
+            release: patch[6 + index] & 0x0F
+        }
+    }
+}
+
+struct Operator {
+    phase: u32,
+    attenuation: f32,
+    envelope: Envelope
+}
+
+impl Operator {
+    fn new() -> Self {
+        Self {
+            phase: 0,
+            attenuation: MAX_ATTENUATION,
+            envelope: Envelope::Off
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0;
+        self.envelope = Envelope::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.envelope != Envelope::Off {
+            self.envelope = Envelope::Release;
+        }
+    }
+
+    // Rate 0-15 raised by the key scale offset to the 0-63 range the timing tables use
+    fn effective_rate(rate: u8, patch: &OperatorPatch, block: u8, fnum: u16) -> u8 {
+        if rate == 0 {
+            return 0;
+        }
+        let key_scale: u8 = (block << 1) | (fnum >> 8) as u8;
+        let offset: u8 = if patch.key_scale_rate { key_scale } else { key_scale >> 2 };
+        (rate * 4 + offset).min(63)
+    }
+
+    fn decay_step(rate: u8) -> f32 {
+        if rate == 0 {
+            0.0
+        } else {
+            96.0 / (DECAY_TIME * SAMPLE_RATE) * 2f32.powf((rate as f32 - 4.0) / 4.0)
+        }
+    }
+
+    // Fraction of the remaining attenuation removed per sample, the attack curve is exponential
+    fn attack_factor(rate: u8) -> f32 {
+        if rate >= 60 {
+            1.0
+        } else if rate == 0 {
+            0.0
+        } else {
+            let samples: f32 = ATTACK_TIME * SAMPLE_RATE * 2f32.powf(-(rate as f32 - 4.0) / 4.0);
+            1.0 - 128f32.powf(-1.0 / samples)
+        }
+    }
+
+    fn step_envelope(&mut self, patch: &OperatorPatch, channel: &Channel) {
+        let rate = |rate: u8| Self::effective_rate(rate, patch, channel.block, channel.fnum);
+        match self.envelope {
+            Envelope::Attack => {
+                self.attenuation *= 1.0 - Self::attack_factor(rate(patch.attack));
+                if self.attenuation < 0.1 {
+                    self.attenuation = 0.0;
+                    self.envelope = Envelope::Decay;
+                }
+            }
+            Envelope::Decay => {
+                let sustain: f32 = patch.sustain_level as f32 * SUSTAIN_LEVEL_STEP;
+                self.attenuation += Self::decay_step(rate(patch.decay));
+                if self.attenuation >= sustain {
+                    self.attenuation = sustain;
+                    self.envelope = Envelope::Sustain;
+                }
+            }
+            // percussive patches keep fading at the release rate while the key is held
+            Envelope::Sustain => if !patch.sustained {
+                self.attenuation += Self::decay_step(rate(patch.release));
+            },
+            Envelope::Release => {
+                let release: u8 = if channel.sustain {
+                    SUSTAIN_RELEASE_RATE
+                } else if patch.sustained {
+                    patch.release
+                } else {
+                    PERCUSSIVE_RELEASE_RATE
+                };
+                self.attenuation += Self::decay_step(rate(release));
+            }
+            Envelope::Off => {}
+        }
+
+        if self.attenuation >= MAX_ATTENUATION {
+            self.attenuation = MAX_ATTENUATION;
+            if self.envelope != Envelope::Attack {
+                self.envelope = Envelope::Off;
+            }
+        }
+    }
+
+    // Advances the operator one sample and returns its output (-1.0 to 1.0)
+    fn step(&mut self, patch: &OperatorPatch, channel: &Channel, lfo: &Lfo, level: f32, modulation: f32) -> f32 {
+        self.step_envelope(patch, channel);
+
+        let vibrato: i32 = if patch.vibrato { ((channel.fnum as i32 >> 6) * lfo.vibrato()) >> 1 } else { 0 };
+        let fnum: u32 = (channel.fnum as i32 + vibrato).max(0) as u32;
+        let increment: u32 = ((fnum << channel.block) * MULTIPLIERS[patch.multiplier as usize]) >> 1;
+        let phase: u32 = self.phase;
+        self.phase = (self.phase + increment) & PHASE_MASK;
+
+        if self.envelope == Envelope::Off {
+            return 0.0;
+        }
+
+        let key_scale: f32 = match patch.key_scale_level {
+            0 => 0.0,
+            shift => {
+                let base: f32 = KEY_SCALE_TABLE[channel.fnum as usize >> 5] - 6.0 * (7 - channel.block) as f32;
+                base.max(0.0) / (1 << (3 - shift)) as f32
+            }
+        };
+        let tremolo: f32 = if patch.tremolo { lfo.tremolo() } else { 0.0 };
+        let attenuation: f32 = self.attenuation + level + key_scale + tremolo;
+        if attenuation >= MAX_ATTENUATION * 2.0 {
+            return 0.0;
+        }
+
+        let angle: f32 = phase as f32 / (1 << PHASE_BITS) as f32 * 2.0 * PI + modulation;
+        let wave: f32 = angle.sin();
+        let wave: f32 = if patch.rectified && wave < 0.0 { 0.0 } else { wave };
+        wave * 10f32.powf(-attenuation / 20.0)
+    }
+}
+
+struct Lfo {
+    tremolo_counter: u32,
+    vibrato_counter: u32
+}
+
+impl Lfo {
+    fn step(&mut self) {
+        self.tremolo_counter = (self.tremolo_counter + 1) % TREMOLO_PERIOD;
+        self.vibrato_counter = (self.vibrato_counter + 1) % (VIBRATO_STEP_SAMPLES * 8);
+    }
+
+    fn tremolo(&self) -> f32 {
+        let position: f32 = self.tremolo_counter as f32 / TREMOLO_PERIOD as f32;
+        let triangle: f32 = if position < 0.5 { position * 2.0 } else { 2.0 - position * 2.0 };
+        triangle * TREMOLO_DEPTH
+    }
+
+    fn vibrato(&self) -> i32 {
+        VIBRATO_TABLE[(self.vibrato_counter / VIBRATO_STEP_SAMPLES) as usize]
+    }
+}
+
+struct Channel {
+    fnum: u16,
+    block: u8,
+    key: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    modulator: Operator,
+    carrier: Operator,
+    feedback: [f32; 2] // last two modulator outputs
+}
+
+impl Channel {
+    fn new() -> Self {
+        Self {
+            fnum: 0,
+            block: 0,
+            key: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            modulator: Operator::new(),
+            carrier: Operator::new(),
+            feedback: [0.0; 2]
+        }
+    }
+}
+
+/// YM2413 (OPLL) style two operator FM synthesizer, as cut down for the VRC7: six melodic
+/// channels and no rhythm mode. Each channel plays one of 15 fixed instruments or the user patch.
+pub struct Opll {
+    patches: &'static [[u8; 8]; 16],
+    custom: [u8; 8],
+    address: u8,
+    channels: [Channel; CHANNELS],
+    lfo: Lfo
+}
+
+impl Opll {
+    pub fn new(patches: &'static [[u8; 8]; 16]) -> Self {
+        Self {
+            patches,
+            custom: [0; 8],
+            address: 0,
+            channels: std::array::from_fn(|_| Channel::new()),
+            lfo: Lfo { tremolo_counter: 0, vibrato_counter: 0 }
+        }
+    }
+
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        self.write_register(self.address, value);
+    }
+
+    pub fn write_register(&mut self, register: u8, value: u8) {
+        let index: usize = register as usize & 0x0F;
+        match register {
+            0x00..=0x07 => self.custom[index] = value,
+            0x10..=0x15 => self.channels[index].fnum = (self.channels[index].fnum & 0x100) | value as u16,
+            0x20..=0x25 => {
+                let channel = &mut self.channels[index];
+                channel.fnum = (channel.fnum & 0xFF) | (value as u16 & 1) << 8;
+                channel.block = (value >> 1) & 0b111;
+                channel.sustain = value & 0x20 != 0;
+                let key: bool = value & 0x10 != 0;
+                if key && !channel.key {
+                    channel.modulator.key_on();
+                    channel.carrier.key_on();
+                } else if !key && channel.key {
+                    channel.modulator.key_off();
+                    channel.carrier.key_off();
+                }
+                channel.key = key;
+            }
+            0x30..=0x35 => {
+                self.channels[index].instrument = value >> 4;
+                self.channels[index].volume = value & 0x0F;
+            }
+            _ => {}
+        }
+    }
+
+    fn patch(&self, instrument: u8) -> [u8; 8] {
+        if instrument == 0 { self.custom } else { self.patches[instrument as usize] }
+    }
+
+    // Generates one sample of the mixed channels
+    pub fn clock(&mut self) -> f32 {
+        self.lfo.step();
+
+        let mut output: f32 = 0.0;
+        for index in 0..CHANNELS {
+            let patch: [u8; 8] = self.patch(self.channels[index].instrument);
+            let modulator_patch: OperatorPatch = OperatorPatch::new(&patch, false);
+            let carrier_patch: OperatorPatch = OperatorPatch::new(&patch, true);
+            let feedback: u8 = patch[3] & 0b111;
+            let total_level: f32 = (patch[2] & 0x3F) as f32 * TOTAL_LEVEL_STEP;
+
+            let channel = &mut self.channels[index];
+            let self_modulation: f32 = if feedback == 0 {
+                0.0
+            } else {
+                (channel.feedback[0] + channel.feedback[1]) / 2.0 * PI * 2f32.powi(feedback as i32 - 5)
+            };
+
+            // the operators borrow the channel for its frequency, so step them on copies of their state
+            let mut modulator: Operator = std::mem::replace(&mut channel.modulator, Operator::new());
+            let mut carrier: Operator = std::mem::replace(&mut channel.carrier, Operator::new());
+            let modulation: f32 = modulator.step(&modulator_patch, channel, &self.lfo, total_level, self_modulation);
+            let volume: f32 = channel.volume as f32 * VOLUME_STEP;
+            output += carrier.step(&carrier_patch, channel, &self.lfo, volume, modulation * MODULATION_DEPTH);
+            channel.modulator = modulator;
+            channel.carrier = carrier;
+            channel.feedback = [channel.feedback[1], modulation];
+        }
+        output * OUTPUT_SCALE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keys one channel on and renders `samples` samples, scaled to 16 bits
+    fn render(opll: &mut Opll, instrument: u8, fnum: u16, block: u8, samples: usize) -> Vec<i16> {
+        opll.write_register(0x10, fnum as u8);
+        opll.write_register(0x30, instrument << 4);
+        opll.write_register(0x20, 0x10 | block << 1 | (fnum >> 8) as u8);
+        (0..samples).map(|_| (opll.clock() / OUTPUT_SCALE * i16::MAX as f32).round() as i16).collect()
+    }
+
+    // Keys channel 0 on with the user patch at full volume and returns its unscaled output
+    fn render_user_patch(patch: [u8; 8], fnum: u16, block: u8, samples: usize) -> Vec<f32> {
+        let mut opll = Opll::new(&VRC7_PATCHES);
+        for (register, value) in patch.into_iter().enumerate() {
+            opll.write_register(register as u8, value);
+        }
+        opll.write_register(0x10, fnum as u8);
+        opll.write_register(0x30, 0x00);
+        opll.write_register(0x20, 0x10 | block << 1 | (fnum >> 8) as u8);
+        (0..samples).map(|_| opll.clock() / OUTPUT_SCALE).collect()
+    }
+
+    // Bessel function of the first kind, from its power series
+    fn bessel(order: i32, x: f64) -> f64 {
+        let n: u32 = order.unsigned_abs();
+        let mut term: f64 = (1..=n).fold(1.0, |term, k| term * x / 2.0 / k as f64);
+        let mut sum: f64 = 0.0;
+        for k in 0..40 {
+            sum += term;
+            term *= -(x / 2.0) * (x / 2.0) / ((k + 1) as f64 * (k + 1 + n) as f64);
+        }
+        if order < 0 && n % 2 == 1 { -sum } else { sum }
+    }
+
+    // sin(t + b sin t) = sum of J_n(b) sin((1 + n) t), so harmonic m of a carrier and modulator
+    // at the same pitch has the amplitude J_(m-1)(b) + (-1)^m J_(m+1)(b)
+    fn fm_harmonic(harmonic: i32, index: f64) -> f64 {
+        let sign: f64 = if harmonic % 2 == 0 { 1.0 } else { -1.0 };
+        bessel(harmonic - 1, index) + sign * bessel(harmonic + 1, index)
+    }
+
+    // Sine amplitudes of the first `count` harmonics of a waveform `period` samples long
+    fn harmonics(samples: &[f32], period: usize, count: usize) -> Vec<f64> {
+        (1..=count).map(|harmonic| {
+            let sum: f64 = samples.iter().enumerate()
+                .map(|(n, sample)| *sample as f64 * (2.0 * std::f64::consts::PI * (harmonic * n) as f64 / period as f64).sin())
+                .sum();
+            2.0 * sum / samples.len() as f64
+        }).collect()
+    }
+
+    // Modulation index whose Bessel spectrum best fits the measured harmonics, and the worst error
+    fn fit_modulation_index(measured: &[f64]) -> (f64, f64) {
+        (0..8000).map(|step| step as f64 / 1000.0)
+            .map(|index| {
+                let error: f64 = measured.iter().enumerate()
+                    .map(|(harmonic, amplitude)| (amplitude - fm_harmonic(harmonic as i32 + 1, index)).abs())
+                    .fold(0.0, f64::max);
+                (index, error)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+    }
+
+    // Carrier and modulator at x1 (fnum 0x100, block 5 repeats every 64 samples), instant attack,
+    // no decay or feedback. The modulator's total level is the only difference between renders.
+    fn fm_spectrum(total_level: u8) -> Vec<f64> {
+        let samples: Vec<f32> = render_user_patch([0x21, 0x21, total_level, 0x00, 0xF0, 0xF0, 0x00, 0x00], 0x100, 5, 64 * 64);
+        harmonics(&samples, 64, 8)
+    }
+
+    // Two operator FM has the spectrum of sin(t + b sin t) (Chowning, "The Synthesis of Complex
+    // Audio Spectra by Means of Frequency Modulation", 1973), and the YM2413 application manual
+    // gives 0.75 dB per total level step, so 8 steps down halves the modulation index.
+    #[test]
+    fn modulation_follows_the_bessel_spectrum() {
+        let (loud, loud_error): (f64, f64) = fit_modulation_index(&fm_spectrum(16));
+        let (quiet, quiet_error): (f64, f64) = fit_modulation_index(&fm_spectrum(24));
+        assert!(loud_error < 0.01 && quiet_error < 0.01, "spectrum off by {} and {}", loud_error, quiet_error);
+        let ratio: f64 = loud / quiet;
+        assert!((ratio - 10f64.powf(6.0 / 20.0)).abs() < 0.02, "indexes {} and {}", loud, quiet);
+    }
+
+    // Carrier envelope in dB, from the samples where the sine is far enough from zero to divide
+    // by. fnum 0xFF at block 2 steps the phase by 1020 per sample.
+    fn envelope(samples: &[f32]) -> Vec<(usize, f32)> {
+        samples.iter().enumerate()
+            .filter_map(|(n, sample)| {
+                let phase: f32 = ((n as u32 * 1020) & PHASE_MASK) as f32 / (1 << PHASE_BITS) as f32;
+                let sine: f32 = (phase * 2.0 * PI).sin();
+                (sine.abs() > 0.7).then(|| (n, 20.0 * (sample / sine).abs().max(1e-6).log10()))
+            })
+            .collect()
+    }
+
+    // With key scaling on, block 2 and fnum bit 8 clear add 4 to the rate, the same as one step
+    // of the register at key scale 0, which the YM2413 application manual tabulates:
+    // attack 2826.24 ms and decay 39280.64 ms (over 96 dB) at rate 1, halving with every step.
+    const KEY_SCALED_FNUM: u16 = 0xFF;
+    const KEY_SCALED_BLOCK: u8 = 2;
+
+    #[test]
+    fn attack_takes_the_datasheet_time() {
+        // rate 5 + 1 for key scale, no decay: 2826.24 ms / 2^5
+        let samples: Vec<f32> = render_user_patch([0x01, 0x31, 0x3F, 0x00, 0x00, 0x50, 0x00, 0x00], KEY_SCALED_FNUM, KEY_SCALED_BLOCK, SAMPLE_RATE as usize / 4);
+        let expected: f32 = 2.82624 / 32.0 * SAMPLE_RATE;
+        let (full, _): (usize, f32) = *envelope(&samples).iter().find(|(_, level)| *level > -0.5).unwrap();
+        assert!((full as f32 - expected).abs() < expected * 0.1, "full volume after {} samples, expected {}", full, expected);
+    }
+
+    #[test]
+    fn decay_takes_the_datasheet_time() {
+        // instant attack, decay rate 6 + 1 for key scale to a 24 dB sustain level:
+        // 39280.64 ms / 2^6 over 96 dB, so 12 dB down takes an eighth of that
+        let samples: Vec<f32> = render_user_patch([0x01, 0x31, 0x3F, 0x00, 0x00, 0xF6, 0x00, 0x80], KEY_SCALED_FNUM, KEY_SCALED_BLOCK, SAMPLE_RATE as usize / 2);
+        let levels: Vec<(usize, f32)> = envelope(&samples);
+        let expected: f32 = 39.28064 / 64.0 / 8.0 * SAMPLE_RATE;
+        let (half, _): (usize, f32) = *levels.iter().find(|(_, level)| *level < -12.0).unwrap();
+        assert!((half as f32 - expected).abs() < expected * 0.05, "12 dB down after {} samples, expected {}", half, expected);
+
+        let (_, sustain): (usize, f32) = *levels.last().unwrap();
+        assert!((sustain + 24.0).abs() < 0.5, "sustained at {} dB", sustain);
+    }
+
+    // A user patch with a silenced modulator, an instant attack and no decay is a plain sine
+    // at 49716 * fnum * 2^(block - 19) Hz
+    #[test]
+    fn pure_carrier_is_a_sine_at_the_programmed_pitch() {
+        let mut opll = Opll::new(&VRC7_PATCHES);
+        for (register, value) in [0x01, 0x21, 0x3F, 0x00, 0x00, 0xF0, 0x00, 0x0F].into_iter().enumerate() {
+            opll.write_register(register as u8, value);
+        }
+        let samples: Vec<i16> = render(&mut opll, 0, 0x100, 5, SAMPLE_RATE as usize);
+
+        let crossings: usize = samples.windows(2).filter(|pair| pair[0] < 0 && pair[1] >= 0).count();
+        let expected: f32 = SAMPLE_RATE * 0x100 as f32 * 2f32.powi(5 - 19);
+        assert!((crossings as f32 - expected).abs() <= 1.0, "{} cycles, expected {}", crossings, expected);
+
+        let peak: i16 = samples.iter().copied().max().unwrap();
+        assert!(peak > 32000, "peak {}", peak);
+    }
+
+    #[test]
+    fn key_off_releases_to_silence() {
+        let mut opll = Opll::new(&VRC7_PATCHES);
+        render(&mut opll, 8, 0x122, 4, 2000);
+        opll.write_register(0x20, 0x08);
+        let tail: Vec<i16> = (0..SAMPLE_RATE as usize).map(|_| (opll.clock() / OUTPUT_SCALE * i16::MAX as f32) as i16).collect();
+        assert!(tail[tail.len() - 100..].iter().all(|sample| *sample == 0));
+    }
+
+    // Magnitudes of the first `count` harmonics of a waveform `period` samples long
+    fn harmonic_magnitudes(samples: &[f32], period: usize, count: usize) -> Vec<f64> {
+        (1..=count).map(|harmonic| {
+            let (sine, cosine): (f64, f64) = samples.iter().enumerate()
+                .map(|(n, sample)| {
+                    let angle: f64 = 2.0 * std::f64::consts::PI * (harmonic * n) as f64 / period as f64;
+                    (*sample as f64 * angle.sin(), *sample as f64 * angle.cos())
+                })
+                .fold((0.0, 0.0), |(a, b), (c, d)| (a + c, b + d));
+            2.0 * sine.hypot(cosine) / samples.len() as f64
+        }).collect()
+    }
+
+    // Steady state of a built-in instrument at fnum 0x100, block 5 (64 samples per period at
+    // x1), after the slowest of the tested attacks is over
+    fn built_in_spectrum(instrument: u8) -> Vec<f64> {
+        let mut opll = Opll::new(&VRC7_PATCHES);
+        let samples: Vec<i16> = render(&mut opll, instrument, 0x100, 5, SAMPLE_RATE as usize / 2 + 64 * 16);
+        let tail: Vec<f32> = samples[samples.len() - 64 * 16..].iter().map(|sample| *sample as f32 / i16::MAX as f32).collect();
+        harmonic_magnitudes(&tail, 64, 12)
+    }
+
+    // Built-in instruments without vibrato and with an unrectified carrier, with the doubled
+    // modulator and carrier multipliers of the patch set dumped from VRC7 hardware
+    const LATTICE_PATCHES: [(u8, i32, i32); 5] = [
+        (1, 6, 2),  // buzzy bell, x3 on x1
+        (5, 4, 2),  // clarinet, x2 on x1
+        (6, 4, 2),  // synth, x2 on x1
+        (9, 10, 2), // bells, x5 on x1
+        (10, 10, 2) // vibes, x5 on x1
+    ];
+
+    // The carrier's phase is moved by the modulator's output, which whatever its feedback and
+    // rectification repeats at the modulator's frequency, so an unrectified carrier only has
+    // lines at carrier + k * modulator: buzzy bell has no multiples of 3, clarinet and synth
+    // only odd harmonics, bells and vibes only 1, 4, 6, 9, 11...
+    #[test]
+    fn built_in_patches_keep_to_their_frequency_ratios() {
+        for (instrument, modulator, carrier) in LATTICE_PATCHES {
+            let spectrum: Vec<f64> = built_in_spectrum(instrument);
+            let strongest: f64 = spectrum.iter().copied().fold(0.0, f64::max);
+            assert!(strongest > 0.05, "instrument {} is silent", instrument);
+            for (harmonic, magnitude) in (1..).zip(&spectrum) {
+                let on_lattice: bool = (-12..=12).any(|k: i32| (carrier + k * modulator).abs() == 2 * harmonic);
+                if !on_lattice {
+                    assert!(*magnitude < strongest * 0.01, "instrument {} harmonic {}: {:?}", instrument, harmonic, spectrum);
+                }
+            }
+        }
+    }
+
+    // Without feedback sin(c t + b sin(m t)) has J_k(b) at c + k m, lines at negative
+    // frequencies folding back with their sign flipped
+    fn fm_lines(modulator: i32, carrier: i32, index: f64, count: usize) -> Vec<f64> {
+        let mut lines: Vec<f64> = vec![0.0; count];
+        for k in -24..=24 {
+            let frequency: i32 = carrier + k * modulator;
+            let harmonic: usize = frequency.unsigned_abs() as usize / 2;
+            if frequency % 2 == 0 && (1..=count).contains(&harmonic) {
+                lines[harmonic - 1] += frequency.signum() as f64 * bessel(k, index);
+            }
+        }
+        lines.iter().map(|line| line.abs()).collect()
+    }
+
+    #[test]
+    fn feedback_free_built_in_patches_follow_the_bessel_spectrum() {
+        // synth and bells have no feedback
+        for (instrument, modulator, carrier) in [LATTICE_PATCHES[2], LATTICE_PATCHES[3]] {
+            assert_eq!(VRC7_PATCHES[instrument as usize][3] & 0b111, 0);
+            let spectrum: Vec<f64> = built_in_spectrum(instrument);
+            // the envelope sets the overall level, fit it along with the modulation index
+            let (index, error): (f64, f64) = (0..6000).map(|step| step as f64 / 1000.0)
+                .map(|index| {
+                    let lines: Vec<f64> = fm_lines(modulator, carrier, index, spectrum.len());
+                    let level: f64 = lines.iter().zip(&spectrum).map(|(line, measured)| line * measured).sum::<f64>() / lines.iter().map(|line| line * line).sum::<f64>();
+                    let error: f64 = lines.iter().zip(&spectrum).map(|(line, measured)| (line * level - measured).abs()).fold(0.0, f64::max) / level;
+                    (index, error)
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+            assert!(error < 0.01, "instrument {}: best fit at index {} is off by {}", instrument, index, error);
+        }
+    }
+
+    // Level in dB of a quarter second of a built-in instrument held from key on, `seconds` in
+    fn held_level(instrument: u8, seconds: f32) -> f32 {
+        let mut opll = Opll::new(&VRC7_PATCHES);
+        let samples: Vec<i16> = render(&mut opll, instrument, 0x100, 5, (SAMPLE_RATE * (seconds + 0.25)) as usize);
+        let window: &[i16] = &samples[(SAMPLE_RATE * seconds) as usize..];
+        let power: f64 = window.iter().map(|sample| (*sample as f64).powi(2)).sum::<f64>() / window.len() as f64;
+        10.0 * power.log10() as f32
+    }
+
+    // The carrier's EG type bit in the hardware patch set: organ holds its sustain level while
+    // the key is down, guitar is percussive and keeps fading at its release rate
+    #[test]
+    fn built_in_envelope_types() {
+        let organ: f32 = held_level(8, 1.0) - held_level(8, 2.0);
+        assert!(organ.abs() < 0.5, "organ fell {} dB", organ);
+        let guitar: f32 = held_level(2, 1.0) - held_level(2, 2.0);
+        assert!(guitar > 3.0, "guitar fell {} dB", guitar);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 18, Jaleco SS88006. Every bank number is written a nibble at a time: three 8 KB
+/// PRG banks with the last fixed, eight 1 KB CHR banks, and a 16 bit down counter IRQ that
+/// can be cut down to its low 12, 8 or 4 bits.
+pub struct Ss88006 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    ram_control: u8,
+    mirroring: u8,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_mask: u16,
+    irq_enabled: bool,
+    irq_pending: bool
+}
+
+impl Ss88006 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            prg_ram: prg_ram(&cartridge),
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            ram_control: 0,
+            mirroring: 0,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_mask: 0xFFFF,
+            irq_enabled: false,
+            irq_pending: false,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank: usize = match address {
+            0x8000..=0xDFFF => self.prg_banks[(address as usize - 0x8000) >> 13] as usize,
+            _ => bank_from_end(self.prg_rom.len(), 0x2000, 1)
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x2000, address)
+    }
+
+    // Even registers take the low nibble, odd ones the high nibble
+    fn write_nibble(target: &mut u8, register: u16, value: u8) {
+        *target = if register & 1 == 0 {
+            (*target & 0xF0) | (value & 0x0F)
+        } else {
+            (*target & 0x0F) | (value & 0x0F) << 4
+        };
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0x8000..=0x8003 => Self::write_nibble(&mut self.prg_banks[(register as usize >> 1) & 1], register, value),
+            0x9000 | 0x9001 => Self::write_nibble(&mut self.prg_banks[2], register, value),
+            0x9002 => self.ram_control = value & 0b11,
+            0xA000..=0xDFFF => {
+                let index: usize = ((register as usize - 0xA000) >> 12) * 2 + ((register as usize >> 1) & 1);
+                Self::write_nibble(&mut self.chr_banks[index], register, value);
+            }
+            0xE000..=0xE003 => {
+                let shift: u16 = (register & 0b11) * 4;
+                self.irq_reload = (self.irq_reload & !(0x0F << shift)) | (value as u16 & 0x0F) << shift;
+            }
+            0xF000 => {
+                self.irq_counter = self.irq_reload;
+                self.irq_pending = false;
+            }
+            0xF001 => {
+                self.irq_enabled = value & 1 != 0;
+                self.irq_mask = if value & 0b1000 != 0 {
+                    0x000F
+                } else if value & 0b0100 != 0 {
+                    0x00FF
+                } else if value & 0b0010 != 0 {
+                    0x0FFF
+                } else {
+                    0xFFFF
+                };
+                self.irq_pending = false;
+            }
+            0xF002 => self.mirroring = value & 0b11,
+            // $F003 drives the uPD7755/7756 speech chip, whose samples aren't part of the dumps
+            _ => {}
+        }
+    }
+
+    fn prg_ram_readable(&self) -> bool {
+        !self.prg_ram.is_empty() && self.ram_control & 0b01 != 0
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_readable() && self.ram_control & 0b10 != 0
+    }
+}
+
+impl Mapper for Ss88006 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_readable() => self.prg_ram[bank_offset(self.prg_ram.len(), 0, 0x2000, address)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_writable() => {
+                let offset: usize = bank_offset(self.prg_ram.len(), 0, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+            0x8000..=0xFFFF => self.write_register(address & 0xF003, value),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_banks[(address >> 10) as usize & 0b111] as usize, 0x400, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    // Only the masked bits count, the rest of the counter holds still
+    fn cpu_cycle(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        let counter: u16 = self.irq_counter & self.irq_mask;
+        let counter: u16 = counter.wrapping_sub(1) & self.irq_mask;
+        self.irq_counter = (self.irq_counter & !self.irq_mask) | counter;
+        if counter == 0 {
+            self.irq_pending = true;
+        }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapper() -> Ss88006 {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([8, 8, 0x20, 0x10, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom.extend(vec![0; 8 * 0x4000 + 8 * 0x2000]);
+        Ss88006::new(Cartridge::from_bytes(&rom).unwrap())
+    }
+
+    // Loads $1234 a nibble at a time into the reload value and the counter
+    fn reload(ss88006: &mut Ss88006) {
+        for (register, nibble) in [4, 3, 2, 1].into_iter().enumerate() {
+            ss88006.cpu_write(0xE000 + register as u16, nibble);
+        }
+        ss88006.cpu_write(0xF000, 0);
+    }
+
+    fn cycles_until_irq(ss88006: &mut Ss88006, limit: u32) -> Option<u32> {
+        (1..=limit).find(|_| {
+            ss88006.cpu_cycle();
+            ss88006.irq()
+        })
+    }
+
+    #[test]
+    fn irq_counter_widths() {
+        let mut ss88006 = mapper();
+        reload(&mut ss88006);
+        assert_eq!(ss88006.irq_counter, 0x1234);
+
+        ss88006.cpu_write(0xF001, 0x01);
+        assert_eq!(cycles_until_irq(&mut ss88006, 0x10000), Some(0x1234));
+        // the counter wraps and keeps going
+        ss88006.cpu_write(0xF001, 0x01);
+        assert_eq!(cycles_until_irq(&mut ss88006, 0x20000), Some(0x10000));
+
+        // 12, 8 and 4 bit counters leave the upper bits alone
+        for (control, cycles, upper) in [(0x03, 0x234, 0x1000), (0x05, 0x34, 0x1200), (0x09, 0x4, 0x1230)] {
+            reload(&mut ss88006);
+            ss88006.cpu_write(0xF001, control);
+            assert_eq!(cycles_until_irq(&mut ss88006, 0x10000), Some(cycles));
+            assert_eq!(ss88006.irq_counter, upper);
+        }
+        // the narrowest width wins
+        reload(&mut ss88006);
+        ss88006.cpu_write(0xF001, 0x0F);
+        assert_eq!(cycles_until_irq(&mut ss88006, 0x10000), Some(4));
+        ss88006.cpu_write(0xF000, 0);
+        assert_eq!(cycles_until_irq(&mut ss88006, 0x10000), Some(4));
+    }
+
+    #[test]
+    fn irq_acknowledge_and_disable() {
+        let mut ss88006 = mapper();
+        reload(&mut ss88006);
+        ss88006.cpu_write(0xF001, 0x09);
+        assert_eq!(cycles_until_irq(&mut ss88006, 100), Some(4));
+        // stays pending until acknowledged
+        ss88006.cpu_cycle();
+        assert!(ss88006.irq());
+        ss88006.cpu_write(0xF001, 0x08);
+        assert!(!ss88006.irq());
+
+        // a disabled counter holds still
+        ss88006.cpu_write(0xF000, 0);
+        for _ in 0..100 {
+            ss88006.cpu_cycle();
+        }
+        assert_eq!(ss88006.irq_counter, 0x1234);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    prg_ram,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::ppu::nametable_index;
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 68, Sunsoft-4. A 16 KB PRG bank with the last one fixed, four 2 KB CHR banks, and
+/// two 1 KB banks of CHR ROM that can stand in for the nametables (After Burner).
+pub struct Sunsoft4 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+
+    chr_banks: [u8; 4],
+    nametable_banks: [u8; 2],
+    control: u8, // $E000, mirroring and CHR ROM nametables
+    prg_bank: u8
+}
+
+impl Sunsoft4 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            prg_ram: prg_ram(&cartridge),
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            chr_banks: [0; 4],
+            nametable_banks: [0; 2],
+            control: 0,
+            prg_bank: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.prg_bank & 0b1_0000 != 0
+    }
+
+    fn rom_nametables(&self) -> bool {
+        self.control & 0b1_0000 != 0
+    }
+
+    // CHR ROM offset of a nametable address, the 1 KB banks always come from the upper 128 KB
+    fn nametable_offset(&self, address: u16) -> usize {
+        let page: usize = nametable_index(address, self.mirroring()) >> 10;
+        bank_offset(self.chr.data.len(), (self.nametable_banks[page] | 0x80) as usize, 0x400, address)
+    }
+}
+
+impl Mapper for Sunsoft4 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[bank_offset(self.prg_ram.len(), 0, 0x2000, address)],
+            0x8000..=0xBFFF => self.prg_rom[bank_offset(self.prg_rom.len(), (self.prg_bank & 0x0F) as usize, 0x4000, address)],
+            0xC000..=0xFFFF => self.prg_rom[bank_offset(self.prg_rom.len(), bank_from_end(self.prg_rom.len(), 0x4000, 1), 0x4000, address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let offset: usize = bank_offset(self.prg_ram.len(), 0, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+            0x8000..=0xBFFF => self.chr_banks[(address as usize - 0x8000) >> 12] = value,
+            0xC000..=0xCFFF => self.nametable_banks[0] = value & 0x7F,
+            0xD000..=0xDFFF => self.nametable_banks[1] = value & 0x7F,
+            0xE000..=0xEFFF => self.control = value,
+            0xF000..=0xFFFF => self.prg_bank = value,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_banks[(address >> 11) as usize & 0b11] as usize, 0x800, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_banks[(address >> 11) as usize & 0b11] as usize, 0x800, address, value);
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        if self.rom_nametables() { Some(self.chr.data[self.nametable_offset(address)]) } else { None }
+    }
+
+    // CHR ROM nametables can't be written, but the write mustn't land in the console's RAM either
+    fn nametable_write(&mut self, _address: u16, _value: u8) -> bool {
+        self.rom_nametables()
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::ppu::Ppu;
+
+    // 256 KB of CHR ROM in 1 KB banks filled with their own number
+    fn mapper() -> Sunsoft4 {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([2, 32, 0x40, 0x40, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom.extend(vec![0; 2 * 0x4000]);
+        for bank in 0..=255 {
+            rom.extend(vec![bank; 0x400]);
+        }
+        Sunsoft4::new(Cartridge::from_bytes(&rom).unwrap())
+    }
+
+    #[test]
+    fn chr_rom_nametables() {
+        let mut ppu = Ppu::new();
+        let mut sunsoft4 = mapper();
+        // the banks always come from the upper 128 KB
+        sunsoft4.cpu_write(0xC000, 0x05);
+        sunsoft4.cpu_write(0xD000, 0xFF);
+        sunsoft4.cpu_write(0xE000, 0x10);
+        let pages = |ppu: &mut Ppu, sunsoft4: &mut Sunsoft4| [0x2000, 0x2400, 0x2800, 0x2C00].map(|address| ppu.read(address, sunsoft4));
+        assert_eq!(pages(&mut ppu, &mut sunsoft4), [0x85, 0xFF, 0x85, 0xFF]);
+
+        // they follow the mirroring
+        sunsoft4.cpu_write(0xE000, 0x11);
+        assert_eq!(pages(&mut ppu, &mut sunsoft4), [0x85, 0x85, 0xFF, 0xFF]);
+        sunsoft4.cpu_write(0xE000, 0x13);
+        assert_eq!(pages(&mut ppu, &mut sunsoft4), [0xFF; 4]);
+
+        // writes go nowhere
+        ppu.write(0x2000, 0x42, &mut sunsoft4);
+        sunsoft4.cpu_write(0xE000, 0x10);
+        assert_eq!(ppu.read(0x2000, &mut sunsoft4), 0x85);
+        sunsoft4.cpu_write(0xE000, 0x00);
+        assert_eq!(ppu.read(0x2000, &mut sunsoft4), 0x00);
+
+        // with bit 4 clear the console's RAM is back
+        ppu.write(0x2000, 0x42, &mut sunsoft4);
+        assert_eq!(pages(&mut ppu, &mut sunsoft4), [0x42, 0x00, 0x42, 0x00]);
+    }
+
+    #[test]
+    fn chr_banks() {
+        let mut sunsoft4 = mapper();
+        for (slot, bank) in [3, 4, 0x7F, 0x80].into_iter().enumerate() {
+            sunsoft4.cpu_write(0x8000 + slot as u16 * 0x1000, bank);
+        }
+        let banks = [0x0000, 0x0400, 0x0800, 0x1000, 0x1800, 0x1C00].map(|address| sunsoft4.ppu_read(address));
+        assert_eq!(banks, [6, 7, 8, 0xFE, 0x00, 0x01]);
+    }
+}
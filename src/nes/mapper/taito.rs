@@ -0,0 +1,138 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+// Value $7EF8/$7EF9 must hold for the X1-005's internal RAM to respond
+const X1005_RAM_ENABLE: u8 = 0xA3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Board {
+    Tc0190, // mapper 33, registers at $8000-$A003
+    X1005   // mapper 80, registers at $7EF0-$7EFF and 128 bytes of battery backed RAM
+}
+
+/// Mappers 33 (Taito TC0190) and 80 (Taito X1-005). Both have three 8 KB PRG banks with the
+/// last fixed, two 2 KB CHR banks at $0000 and four 1 KB ones at $1000, and mirroring control.
+pub struct Taito {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    board: Board,
+    battery: bool,
+    internal_ram: Vec<u8>,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 6],
+    horizontal: bool,
+    ram_enable: u8
+}
+
+impl Taito {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let board: Board = if cartridge.header.mapper == 80 { Board::X1005 } else { Board::Tc0190 };
+
+        Self {
+            chr: Chr::new(&cartridge),
+            board,
+            battery: cartridge.header.battery,
+            internal_ram: if board == Board::X1005 { vec![0; 0x80] } else { Vec::new() },
+            prg_banks: [0, 1, 0xFE],
+            chr_banks: [0; 6],
+            horizontal: cartridge.header.mirroring == Mirroring::Horizontal,
+            ram_enable: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank: usize = match address {
+            0x8000..=0xDFFF => self.prg_banks[(address as usize - 0x8000) >> 13] as usize,
+            _ => bank_from_end(self.prg_rom.len(), 0x2000, 1)
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x2000, address)
+    }
+
+    fn chr_bank(&self, address: u16) -> (usize, usize) {
+        match address {
+            0x0000..=0x0FFF => (self.chr_banks[(address >> 11) as usize & 1] as usize, 0x800),
+            _ => (self.chr_banks[2 + ((address >> 10) as usize & 0b11)] as usize, 0x400)
+        }
+    }
+
+    fn internal_ram_enabled(&self) -> bool {
+        self.board == Board::X1005 && self.ram_enable == X1005_RAM_ENABLE
+    }
+
+    fn write_tc0190(&mut self, address: u16, value: u8) {
+        match address & 0xA003 {
+            0x8000 => {
+                self.prg_banks[0] = value & 0x3F;
+                self.horizontal = value & 0x40 != 0;
+            }
+            0x8001 => self.prg_banks[1] = value & 0x3F,
+            0x8002 | 0x8003 => self.chr_banks[address as usize & 1] = value,
+            _ => self.chr_banks[2 + (address as usize & 0b11)] = value
+        }
+    }
+
+    fn write_x1005(&mut self, address: u16, value: u8) {
+        match address {
+            0x7EF0 | 0x7EF1 => self.chr_banks[address as usize & 1] = value >> 1,
+            0x7EF2..=0x7EF5 => self.chr_banks[address as usize - 0x7EF0] = value,
+            0x7EF6 | 0x7EF7 => self.horizontal = value & 1 == 0,
+            0x7EF8 | 0x7EF9 => self.ram_enable = value,
+            0x7EFA | 0x7EFB => self.prg_banks[0] = value,
+            0x7EFC | 0x7EFD => self.prg_banks[1] = value,
+            0x7EFE | 0x7EFF => self.prg_banks[2] = value,
+            0x7F00..=0x7FFF if self.internal_ram_enabled() => self.internal_ram[address as usize & 0x7F] = value,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Taito {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x7F00..=0x7FFF if self.internal_ram_enabled() => self.internal_ram[address as usize & 0x7F],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match (self.board, address) {
+            (Board::Tc0190, 0x8000..=0xBFFF) => self.write_tc0190(address, value),
+            (Board::X1005, 0x7EF0..=0x7FFF) => self.write_x1005(address, value),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        let (bank, size) = self.chr_bank(address);
+        self.chr.read(bank, size, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        let (bank, size) = self.chr_bank(address);
+        self.chr.write(bank, size, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.horizontal { Mirroring::Horizontal } else { Mirroring::Vertical }
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery && !self.internal_ram.is_empty() { Some(&self.internal_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.internal_ram, data);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::nes::mapper::Mapper;
+use crate::nes::rom::{
+    Mirroring,
+    TRAINER_SIZE
+};
+
+const TRAINER_START: u16 = 0x7000;
+const TRAINER_END: u16 = TRAINER_START + TRAINER_SIZE as u16 - 1;
+
+/// The 512 bytes of RAM at $7000-$71FF that the copier devices a trainer was dumped from loaded
+/// it into, whether the board itself has work RAM there or not. Wraps the board and answers
+/// reads in that range; writes go to the board as well, so its registers and battery RAM see them.
+pub struct Trainer {
+    board: Box<dyn Mapper>,
+    ram: Vec<u8>
+}
+
+impl Trainer {
+    pub fn new(board: Box<dyn Mapper>, trainer: Vec<u8>) -> Self {
+        Self {
+            board,
+            ram: trainer
+        }
+    }
+}
+
+impl Mapper for Trainer {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            TRAINER_START..=TRAINER_END => self.ram[(address - TRAINER_START) as usize],
+            _ => self.board.cpu_read(address)
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if let TRAINER_START..=TRAINER_END = address {
+            self.ram[(address - TRAINER_START) as usize] = value;
+        }
+        self.board.cpu_write(address, value);
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.board.ppu_read(address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.board.ppu_write(address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.board.mirroring()
+    }
+
+    fn irq(&self) -> bool {
+        self.board.irq()
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        self.board.nametable_read(address)
+    }
+
+    fn nametable_write(&mut self, address: u16, value: u8) -> bool {
+        self.board.nametable_write(address, value)
+    }
+
+    fn chr_ciram_page(&self, address: u16) -> Option<u8> {
+        self.board.chr_ciram_page(address)
+    }
+
+    fn ppu_register_write(&mut self, address: u16, value: u8) {
+        self.board.ppu_register_write(address, value);
+    }
+
+    fn ppu_address(&mut self, address: u16) {
+        self.board.ppu_address(address);
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.board.cpu_cycle();
+    }
+
+    fn scanline(&mut self) {
+        self.board.scanline();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.board.audio_output()
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        self.board.save_ram()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        self.board.load_save_ram(data);
+    }
+
+    fn disk_sides(&self) -> usize {
+        self.board.disk_sides()
+    }
+
+    fn inserted_disk(&self) -> Option<usize> {
+        self.board.inserted_disk()
+    }
+
+    fn insert_disk(&mut self, side: Option<usize>) {
+        self.board.insert_disk(side);
+    }
+
+    fn disk_side(&self) -> Option<Vec<u8>> {
+        self.board.disk_side()
+    }
+
+    fn write_disk_side(&mut self, data: &[u8]) {
+        self.board.write_disk_side(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nes::mapper::{
+        from_cartridge,
+        Mapper
+    };
+    use crate::nes::rom::Cartridge;
+
+    // UxROM has no work RAM of its own, the trainer still has to show up at $7000
+    #[test]
+    fn trainer_is_mapped_without_work_ram() {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([2, 0, 0x24, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom.extend((0..512).map(|byte| byte as u8));
+        rom.extend(vec![0xEA; 2 * 0x4000]);
+        let mut board: Box<dyn Mapper> = from_cartridge(Cartridge::from_bytes(&rom).unwrap()).unwrap();
+
+        assert_eq!((board.cpu_read(0x7000), board.cpu_read(0x71FF)), (0x00, 0xFF));
+        board.cpu_write(0x7010, 0x99);
+        assert_eq!(board.cpu_read(0x7010), 0x99);
+        assert_eq!(board.cpu_read(0x8000), 0xEA);
+    }
+}
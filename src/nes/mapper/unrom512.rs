@@ -0,0 +1,139 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    flash::Flash,
+    restore,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    HeaderFormat,
+    Mirroring
+};
+
+/// Mapper 30, RetroUSB's UNROM-512. UNROM style PRG banking over up to 512 KB, four 8 KB
+/// CHR RAM banks and, on the flashable version (battery bit set), an SST39SF040 as PRG ROM
+/// that games reprogram to save. Header flags 6 bits 3 and 0 select the nametable layout.
+pub struct Unrom512 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    nametable_flags: u8,
+    flashable: bool,
+    flash: Flash,
+
+    prg_bank: u8,
+    chr_bank: u8,
+    nametable_page: u8
+}
+
+impl Unrom512 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        // iNES headers can't size CHR RAM, those images get the full 32 KB the board can bank
+        let mut chr: Chr = Chr::new(&cartridge);
+        if chr.writable && cartridge.header.format == HeaderFormat::INes {
+            chr.data.resize(0x8000, 0);
+        }
+
+        Self {
+            chr,
+            nametable_flags: cartridge.header.nametable_flags,
+            flashable: cartridge.header.battery,
+            flash: Flash::new(),
+            prg_bank: 0,
+            chr_bank: 0,
+            nametable_page: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank: usize = match address {
+            0x8000..=0xBFFF => self.prg_bank as usize,
+            _ => bank_from_end(self.prg_rom.len(), 0x4000, 1)
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x4000, address)
+    }
+
+    fn four_screen(&self) -> bool {
+        self.nametable_flags == 0b1001
+    }
+
+    // Four screen boards keep their nametables in the last 8 KB of CHR RAM
+    fn nametable_offset(&self, address: u16) -> usize {
+        self.chr.data.len() - 0x2000 + (address as usize & 0x0FFF)
+    }
+
+    fn write_register(&mut self, value: u8) {
+        self.prg_bank = value & 0x1F;
+        self.chr_bank = (value >> 5) & 0b11;
+        self.nametable_page = value >> 7;
+    }
+}
+
+impl Mapper for Unrom512 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xFFFF => self.flash.read(&self.prg_rom, self.prg_offset(address)),
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            // the flash sees $8000-$BFFF through the switchable bank, so commands go out as
+            // bank 1 + $9555 and bank 0 + $AAAA
+            0x8000..=0xBFFF if self.flashable => {
+                let offset: usize = self.prg_offset(address);
+                self.flash.write(&mut self.prg_rom, offset, value);
+            }
+            0x8000..=0xFFFF if self.flashable => self.write_register(value),
+            // the non flashable board has bus conflicts
+            0x8000..=0xFFFF => {
+                let value: u8 = value & self.prg_rom[self.prg_offset(address)];
+                self.write_register(value);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_bank as usize, 0x2000, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_bank as usize, 0x2000, address, value);
+    }
+
+    fn nametable_read(&mut self, address: u16) -> Option<u8> {
+        if self.four_screen() && self.chr.writable { Some(self.chr.data[self.nametable_offset(address)]) } else { None }
+    }
+
+    fn nametable_write(&mut self, address: u16, value: u8) -> bool {
+        if !self.four_screen() || !self.chr.writable {
+            return false;
+        }
+        let offset: usize = self.nametable_offset(address);
+        self.chr.data[offset] = value;
+        true
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.nametable_flags {
+            0b0000 => Mirroring::Horizontal,
+            0b0001 => Mirroring::Vertical,
+            0b1000 if self.nametable_page == 0 => Mirroring::SingleScreenLower,
+            0b1000 => Mirroring::SingleScreenUpper,
+            _ => Mirroring::FourScreen
+        }
+    }
+
+    // The whole flash is the save
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.flashable { Some(&self.prg_rom) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_rom, data);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    bus_conflicts,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 2, a switchable 16 KB bank at $8000 and the last bank fixed at $C000.
+pub struct Uxrom {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    prg_bank: u8
+}
+
+impl Uxrom {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            chr: Chr::new(&cartridge),
+            mirroring: cartridge.header.mirroring,
+            bus_conflicts: bus_conflicts(&cartridge),
+            prg_bank: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x8000..=0xBFFF => self.prg_rom[bank_offset(self.prg_rom.len(), self.prg_bank as usize, 0x4000, address)],
+            0xC000..=0xFFFF => self.prg_rom[bank_offset(self.prg_rom.len(), bank_from_end(self.prg_rom.len(), 0x4000, 1), 0x4000, address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.prg_bank = if self.bus_conflicts { value & self.cpu_read(address) } else { value };
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(0, 0x2000, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(0, 0x2000, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
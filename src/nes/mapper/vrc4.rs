@@ -0,0 +1,305 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    prg_ram,
+    restore,
+    vrc_irq::VrcIrq,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+// CPU address lines wired to the chip's register select inputs (A0, A1). Submapper 0 is an
+// old iNES dump that could be either board, so both wirings are decoded at once.
+fn address_lines(mapper: u16, submapper: u8) -> (u16, u16) {
+    match (mapper, submapper) {
+        (21, 1) => (0x02, 0x04),          // VRC4a
+        (21, 2) => (0x40, 0x80),          // VRC4c
+        (21, _) => (0x42, 0x84),
+        (22, _) => (0x02, 0x01),          // VRC2a
+        (23, 1) | (23, 3) => (0x01, 0x02), // VRC4f, VRC2b
+        (23, 2) => (0x04, 0x08),          // VRC4e
+        (23, _) => (0x05, 0x0A),
+        (25, 1) | (25, 3) => (0x02, 0x01), // VRC4b, VRC2c
+        (25, 2) => (0x08, 0x04),          // VRC4d
+        _ => (0x0A, 0x05)
+    }
+}
+
+/// Mappers 21, 22, 23 and 25, the VRC2 and VRC4. Two switchable 8 KB PRG banks, eight 1 KB
+/// CHR banks written a nibble at a time, and on the VRC4 a PRG swap mode and an IRQ counter.
+pub struct Vrc4 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+    vrc2: bool,
+    microwire: bool, // $6000-$6FFF is the microwire latch rather than work RAM
+    lines: (u16, u16),
+    chr_shift: u8, // VRC2a ignores the low bit of its CHR banks
+
+    prg_banks: [u8; 2],
+    prg_swap: bool,
+    chr_banks: [u16; 8],
+    mirroring: u8,
+    irq: VrcIrq,
+
+    // VRC2 boards without work RAM have a one bit latch at $6000, used for the EEPROM microwire lines
+    microwire_latch: u8
+}
+
+impl Vrc4 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        let (mapper, submapper) = (cartridge.header.mapper, cartridge.header.submapper);
+        let vrc2: bool = mapper == 22 || submapper == 3;
+        // The only VRC2 boards with work RAM keep it battery backed, the rest wire the latch
+        // instead. iNES headers always claim 8 KB of RAM, so the RAM size can't tell them apart.
+        let microwire: bool = vrc2 && !cartridge.header.battery;
+
+        Self {
+            prg_ram: if microwire { Vec::new() } else { prg_ram(&cartridge) },
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            vrc2,
+            microwire,
+            lines: address_lines(mapper, submapper),
+            chr_shift: if mapper == 22 { 1 } else { 0 },
+            prg_banks: [0; 2],
+            prg_swap: false,
+            chr_banks: [0; 8],
+            mirroring: 0,
+            irq: VrcIrq::new(),
+            microwire_latch: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    // Folds whatever lines the board uses down to $x000-$x003
+    fn register(&self, address: u16) -> u16 {
+        let a0: u16 = (address & self.lines.0 != 0) as u16;
+        let a1: u16 = (address & self.lines.1 != 0) as u16;
+        (address & 0xF000) | a1 << 1 | a0
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let second_last: usize = bank_from_end(self.prg_rom.len(), 0x2000, 2);
+        let bank: usize = match (address >> 13) & 0b11 {
+            0 => if self.prg_swap { second_last } else { self.prg_banks[0] as usize },
+            1 => self.prg_banks[1] as usize,
+            2 => if self.prg_swap { self.prg_banks[0] as usize } else { second_last },
+            _ => bank_from_end(self.prg_rom.len(), 0x2000, 1)
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x2000, address)
+    }
+
+    fn chr_bank(&self, address: u16) -> usize {
+        (self.chr_banks[(address >> 10) as usize & 0b111] >> self.chr_shift) as usize
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0x8000..=0x8003 => self.prg_banks[0] = value & 0x1F,
+            0x9000..=0x9003 if self.vrc2 => self.mirroring = value & 0b01,
+            0x9000 => self.mirroring = value & 0b11,
+            0x9002 if !self.vrc2 => self.prg_swap = value & 0b10 != 0,
+            0xA000..=0xA003 => self.prg_banks[1] = value & 0x1F,
+            0xB000..=0xEFFF => {
+                let index: usize = ((register as usize - 0xB000) >> 12) * 2 + ((register as usize & 0b10) >> 1);
+                let bank: u16 = self.chr_banks[index];
+                self.chr_banks[index] = if register & 1 == 0 {
+                    (bank & 0x1F0) | (value & 0x0F) as u16
+                } else {
+                    let high: u16 = if self.vrc2 { 0x0F } else { 0x1F };
+                    (bank & 0x0F) | ((value as u16 & high) << 4)
+                };
+            }
+            0xF000 if !self.vrc2 => self.irq.write_latch_low(value),
+            0xF001 if !self.vrc2 => self.irq.write_latch_high(value),
+            0xF002 if !self.vrc2 => self.irq.write_control(value),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x6FFF if self.microwire => 0x60 | self.microwire_latch,
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => self.prg_ram[bank_offset(self.prg_ram.len(), 0, 0x2000, address)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x6FFF if self.microwire => self.microwire_latch = value & 1,
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                let offset: usize = bank_offset(self.prg_ram.len(), 0, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+            0x8000..=0xFFFF => self.write_register(self.register(address), value),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_bank(address), 0x400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_bank(address), 0x400, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.clock();
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NES 2.0 image with 128 KB of PRG in 8 KB banks and 64 KB of CHR in 1 KB banks, each
+    // filled with its own number
+    fn mapper(mapper: u8, submapper: u8, battery: bool) -> Vrc4 {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        let flags6: u8 = (mapper & 0x0F) << 4 | if battery { 0x02 } else { 0 };
+        let ram: u8 = if battery { 0x70 } else { 0x07 };
+        rom.extend([8, 8, flags6, (mapper & 0xF0) | 0x08, submapper << 4, 0, ram, 0, 0, 0, 0, 0]);
+        for bank in 0..16 {
+            rom.extend(vec![bank; 0x2000]);
+        }
+        for bank in 0..64 {
+            rom.extend(vec![bank; 0x400]);
+        }
+        Vrc4::new(Cartridge::from_bytes(&rom).unwrap())
+    }
+
+    // Writes both nibbles of CHR banks 0 and 1 through the given A0/A1 lines
+    fn chr_banks_through(vrc4: &mut Vrc4, a0: u16, a1: u16) -> [u8; 2] {
+        vrc4.cpu_write(0xB000, 0x05);
+        vrc4.cpu_write(0xB000 | a0, 0x01);
+        vrc4.cpu_write(0xB000 | a1, 0x03);
+        vrc4.cpu_write(0xB000 | a0 | a1, 0x02);
+        [vrc4.ppu_read(0x0000), vrc4.ppu_read(0x0400)]
+    }
+
+    #[test]
+    fn address_lines_per_submapper() {
+        let boards: [(u8, u8, u16, u16); 12] = [
+            (21, 1, 0x02, 0x04), // VRC4a
+            (21, 2, 0x40, 0x80), // VRC4c
+            (21, 0, 0x02, 0x04),
+            (21, 0, 0x40, 0x80),
+            (23, 1, 0x01, 0x02), // VRC4f
+            (23, 2, 0x04, 0x08), // VRC4e
+            (23, 0, 0x01, 0x02),
+            (23, 0, 0x04, 0x08),
+            (25, 1, 0x02, 0x01), // VRC4b
+            (25, 2, 0x08, 0x04), // VRC4d
+            (25, 0, 0x02, 0x01),
+            (25, 0, 0x08, 0x04)
+        ];
+        for (number, submapper, a0, a1) in boards {
+            let mut vrc4 = mapper(number, submapper, false);
+            assert_eq!(chr_banks_through(&mut vrc4, a0, a1), [0x15, 0x23], "mapper {} submapper {}", number, submapper);
+        }
+
+        // lines the board doesn't use are ignored, every write lands in $B000
+        let mut vrc4a = mapper(21, 1, false);
+        assert_eq!(chr_banks_through(&mut vrc4a, 0x40, 0x80), [0x02, 0x00]);
+    }
+
+    #[test]
+    fn vrc2_address_lines() {
+        // VRC2a drops the low bit of its CHR banks
+        let mut vrc2a = mapper(22, 0, false);
+        assert_eq!(chr_banks_through(&mut vrc2a, 0x02, 0x01), [0x0A, 0x11]);
+        let mut vrc2b = mapper(23, 3, false);
+        assert_eq!(chr_banks_through(&mut vrc2b, 0x01, 0x02), [0x15, 0x23]);
+        let mut vrc2c = mapper(25, 3, false);
+        assert_eq!(chr_banks_through(&mut vrc2c, 0x02, 0x01), [0x15, 0x23]);
+    }
+
+    #[test]
+    fn prg_swap_mode() {
+        let mut vrc4 = mapper(21, 1, false);
+        vrc4.cpu_write(0x8000, 3);
+        vrc4.cpu_write(0xA000, 4);
+        let banks = |vrc4: &mut Vrc4| [0x8000, 0xA000, 0xC000, 0xE000].map(|address| vrc4.cpu_read(address));
+        assert_eq!(banks(&mut vrc4), [3, 4, 14, 15]);
+        vrc4.cpu_write(0x9004, 0x02);
+        assert_eq!(banks(&mut vrc4), [14, 4, 3, 15]);
+
+        // the VRC2 has no swap mode, $9000-$9003 all set the mirroring
+        let mut vrc2 = mapper(23, 3, false);
+        vrc2.cpu_write(0x8000, 3);
+        vrc2.cpu_write(0x9002, 0x03);
+        assert_eq!(banks(&mut vrc2), [3, 0, 14, 15]);
+        assert!(matches!(vrc2.mirroring(), Mirroring::Horizontal));
+    }
+
+    #[test]
+    fn irq_registers() {
+        let mut vrc4 = mapper(25, 2, false);
+        // latch low and high nibbles, then cycle mode, enabled
+        vrc4.cpu_write(0xF000, 0x0E);
+        vrc4.cpu_write(0xF008, 0x0F);
+        vrc4.cpu_write(0xF004, 0x06);
+        vrc4.cpu_cycle();
+        assert!(!vrc4.irq());
+        vrc4.cpu_cycle();
+        assert!(vrc4.irq());
+        vrc4.cpu_write(0xF00C, 0);
+        assert!(!vrc4.irq());
+
+        // the VRC2 has no IRQ
+        let mut vrc2 = mapper(25, 3, false);
+        vrc2.cpu_write(0xF000, 0x0F);
+        vrc2.cpu_write(0xF002, 0x0F);
+        vrc2.cpu_write(0xF001, 0x06);
+        for _ in 0..1000 {
+            vrc2.cpu_cycle();
+        }
+        assert!(!vrc2.irq());
+    }
+
+    #[test]
+    fn vrc2_microwire_latch() {
+        let mut vrc2 = mapper(22, 0, false);
+        vrc2.cpu_write(0x6000, 0xFF);
+        assert_eq!(vrc2.cpu_read(0x6000), 0x61);
+        assert_eq!(vrc2.cpu_read(0x7000), 0);
+
+        // battery backed VRC2 boards have work RAM there instead
+        let mut vrc2 = mapper(23, 3, true);
+        vrc2.cpu_write(0x6000, 0xFF);
+        assert_eq!(vrc2.cpu_read(0x6000), 0xFF);
+    }
+}
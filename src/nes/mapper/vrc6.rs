@@ -0,0 +1,377 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    prg_ram,
+    restore,
+    vrc_irq::VrcIrq,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+// Linear approximation of one step of a 2A03 pulse, the VRC6 channels come out at about the same level
+const OUTPUT_SCALE: f32 = 0.00752;
+
+/// VRC6 pulse, 16 steps with a 3 bit duty or a constant level ("digitized" mode).
+#[derive(Default)]
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    constant: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8
+}
+
+impl Vrc6Pulse {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.constant = value & 0x80 != 0;
+                self.duty = (value >> 4) & 0b111;
+                self.volume = value & 0x0F;
+            }
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (value as u16 & 0x0F) << 8;
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) { self.volume } else { 0 }
+    }
+}
+
+/// VRC6 sawtooth, an accumulator that adds the rate every other step and resets after 14 steps.
+#[derive(Default)]
+struct Vrc6Saw {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8
+}
+
+impl Vrc6Saw {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.rate = value & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (value as u16 & 0x0F) << 8;
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer != 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled { self.accumulator >> 3 } else { 0 }
+    }
+}
+
+/// Mappers 24 and 26 (VRC6a/b, the latter with A0 and A1 swapped). 16 KB + 8 KB switchable
+/// PRG, eight 1 KB CHR banks, the VRC IRQ counter and three expansion sound channels.
+pub struct Vrc6 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+    swapped_lines: bool,
+
+    prg_banks: [u8; 2],
+    chr_banks: [u8; 8],
+    banking: u8, // $B003
+    irq: VrcIrq,
+
+    pulses: [Vrc6Pulse; 2],
+    saw: Vrc6Saw,
+    halt: bool,
+    frequency_shift: u8
+}
+
+impl Vrc6 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            prg_ram: prg_ram(&cartridge),
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            swapped_lines: cartridge.header.mapper == 26,
+            prg_banks: [0; 2],
+            chr_banks: [0; 8],
+            banking: 0,
+            irq: VrcIrq::new(),
+            pulses: [Vrc6Pulse::default(), Vrc6Pulse::default()],
+            saw: Vrc6Saw::default(),
+            halt: false,
+            frequency_shift: 0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        match address {
+            0x8000..=0xBFFF => bank_offset(self.prg_rom.len(), self.prg_banks[0] as usize, 0x4000, address),
+            0xC000..=0xDFFF => bank_offset(self.prg_rom.len(), self.prg_banks[1] as usize, 0x2000, address),
+            _ => bank_offset(self.prg_rom.len(), bank_from_end(self.prg_rom.len(), 0x2000, 1), 0x2000, address)
+        }
+    }
+
+    // 1 KB bank at `address`. $B003 bits 0-1: 1 KB banks, 2 KB banks, or 1 KB at $0000 and
+    // 2 KB at $1000. The registers always count 1 KB, a 2 KB slot takes A10 from the PPU when
+    // bit 5 is set and repeats the register's bank in both halves otherwise.
+    fn chr_bank(&self, address: u16) -> usize {
+        let slot: usize = (address >> 10) as usize & 0b111;
+        let (register, two_kb): (u8, bool) = match self.banking & 0b11 {
+            0 => (self.chr_banks[slot], false),
+            1 => (self.chr_banks[slot >> 1], true),
+            _ if slot < 4 => (self.chr_banks[slot], false),
+            _ => (self.chr_banks[4 + ((slot >> 1) & 1)], true)
+        };
+        if two_kb && self.banking & 0x20 != 0 {
+            (register & 0xFE) as usize | (slot & 1)
+        } else {
+            register as usize
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.banking & 0x80 != 0
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0x8000..=0x8003 => self.prg_banks[0] = value & 0x0F,
+            0x9003 => {
+                self.halt = value & 1 != 0;
+                self.frequency_shift = if value & 0b100 != 0 { 8 } else if value & 0b10 != 0 { 4 } else { 0 };
+            }
+            0x9000..=0x9002 => self.pulses[0].write(register & 0b11, value),
+            0xA000..=0xA002 => self.pulses[1].write(register & 0b11, value),
+            0xB000..=0xB002 => self.saw.write(register & 0b11, value),
+            0xB003 => self.banking = value,
+            0xC000..=0xC003 => self.prg_banks[1] = value & 0x1F,
+            0xD000..=0xD003 => self.chr_banks[register as usize & 0b11] = value,
+            0xE000..=0xE003 => self.chr_banks[4 + (register as usize & 0b11)] = value,
+            0xF000 => self.irq.write_latch(value),
+            0xF001 => self.irq.write_control(value),
+            0xF002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[bank_offset(self.prg_ram.len(), 0, 0x2000, address)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let offset: usize = bank_offset(self.prg_ram.len(), 0, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+            0x8000..=0xFFFF => {
+                let (a0, a1) = if self.swapped_lines { (address & 2 != 0, address & 1 != 0) } else { (address & 1 != 0, address & 2 != 0) };
+                self.write_register((address & 0xF000) | (a1 as u16) << 1 | a0 as u16, value);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_bank(address), 0x400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_bank(address), 0x400, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.banking >> 2) & 0b11 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.clock();
+        if !self.halt {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock(self.frequency_shift);
+            }
+            self.saw.clock(self.frequency_shift);
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        let level: u8 = self.pulses[0].output() + self.pulses[1].output() + self.saw.output();
+        level as f32 * OUTPUT_SCALE
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 32 KB of PRG and 256 1 KB CHR banks, each filled with its own number
+    fn board(mapper: u8) -> Vrc6 {
+        let mut rom: Vec<u8> = b"NES\x1A".to_vec();
+        rom.extend([2, 32, (mapper & 0x0F) << 4, mapper & 0xF0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom.extend(vec![0; 0x8000]);
+        for bank in 0..=255 {
+            rom.extend(vec![bank; 0x400]);
+        }
+        let mut vrc6: Vrc6 = Vrc6::new(Cartridge::from_bytes(&rom).unwrap());
+        for (register, bank) in [0xD000, 0xD001, 0xD002, 0xD003, 0xE000, 0xE001, 0xE002, 0xE003].into_iter().zip([0x10, 0x21, 0x32, 0x43, 0x54, 0x65, 0x76, 0x87]) {
+            vrc6.cpu_write(register, bank);
+        }
+        vrc6
+    }
+
+    // 1 KB bank showing in each of the eight slots
+    fn banks(vrc6: &mut Vrc6) -> Vec<u8> {
+        (0..8).map(|slot| vrc6.ppu_read(slot * 0x400)).collect()
+    }
+
+    #[test]
+    fn chr_mode_0_has_eight_1kb_banks() {
+        let mut vrc6: Vrc6 = board(24);
+        for banking in [0x00, 0x20] {
+            vrc6.cpu_write(0xB003, banking);
+            assert_eq!(banks(&mut vrc6), [0x10, 0x21, 0x32, 0x43, 0x54, 0x65, 0x76, 0x87]);
+        }
+    }
+
+    #[test]
+    fn chr_mode_1_has_four_2kb_banks() {
+        let mut vrc6: Vrc6 = board(24);
+        vrc6.cpu_write(0xB003, 0x21);
+        assert_eq!(banks(&mut vrc6), [0x10, 0x11, 0x20, 0x21, 0x32, 0x33, 0x42, 0x43]);
+        // without PPU A10 both halves show the register's bank
+        vrc6.cpu_write(0xB003, 0x01);
+        assert_eq!(banks(&mut vrc6), [0x10, 0x10, 0x21, 0x21, 0x32, 0x32, 0x43, 0x43]);
+    }
+
+    #[test]
+    fn chr_modes_2_and_3_mix_1kb_and_2kb_banks() {
+        let mut vrc6: Vrc6 = board(24);
+        for mode in [0x02, 0x03] {
+            vrc6.cpu_write(0xB003, 0x20 | mode);
+            assert_eq!(banks(&mut vrc6), [0x10, 0x21, 0x32, 0x43, 0x54, 0x55, 0x64, 0x65]);
+            vrc6.cpu_write(0xB003, mode);
+            assert_eq!(banks(&mut vrc6), [0x10, 0x21, 0x32, 0x43, 0x54, 0x54, 0x65, 0x65]);
+        }
+    }
+
+    #[test]
+    fn vrc6b_swaps_a0_and_a1() {
+        let mut vrc6: Vrc6 = board(26);
+        vrc6.cpu_write(0xD001, 0x99); // R2 on VRC6b
+        vrc6.cpu_write(0xB003, 0x00);
+        assert_eq!(banks(&mut vrc6)[..4], [0x10, 0x32, 0x99, 0x43]);
+    }
+
+    // Level of the expansion channels over `cycles` CPU cycles
+    fn levels(vrc6: &mut Vrc6, cycles: usize) -> Vec<u8> {
+        (0..cycles).map(|_| {
+            vrc6.cpu_cycle();
+            (vrc6.audio_output() / OUTPUT_SCALE).round() as u8
+        }).collect()
+    }
+
+    #[test]
+    fn pulse_duty() {
+        let mut vrc6: Vrc6 = board(24);
+        // duty 3 (4/16 high) at volume 15, stepping every cycle
+        vrc6.cpu_write(0x9000, 0x3F);
+        vrc6.cpu_write(0x9001, 0x00);
+        vrc6.cpu_write(0x9002, 0x80);
+        let output: Vec<u8> = levels(&mut vrc6, 32);
+        assert_eq!(output.iter().filter(|&&level| level == 15).count(), 8);
+        assert!(output.iter().all(|&level| level == 15 || level == 0));
+        assert_eq!(output[12..16], [15, 15, 15, 15]);
+
+        // the constant mode bit holds the volume
+        vrc6.cpu_write(0x9000, 0x87);
+        assert!(levels(&mut vrc6, 16).iter().all(|&level| level == 7));
+    }
+
+    #[test]
+    fn saw_ramp() {
+        let mut vrc6: Vrc6 = board(24);
+        // rate 16, clocked every other cycle
+        vrc6.cpu_write(0xB000, 0x10);
+        vrc6.cpu_write(0xB001, 0x01);
+        vrc6.cpu_write(0xB002, 0x80);
+        let output: Vec<u8> = levels(&mut vrc6, 28).into_iter().step_by(2).collect();
+        assert_eq!(output, [0, 2, 2, 4, 4, 6, 6, 8, 8, 10, 10, 12, 12, 0]);
+
+        // halting freezes the channels
+        vrc6.cpu_write(0x9003, 0x01);
+        let level: u8 = levels(&mut vrc6, 1)[0];
+        assert!(levels(&mut vrc6, 8).iter().all(|&halted| halted == level));
+    }
+}
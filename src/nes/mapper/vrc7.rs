@@ -0,0 +1,172 @@
+use crate::nes::mapper::{
+    bank_from_end,
+    bank_offset,
+    opll::{
+        Opll,
+        CPU_CYCLES_PER_SAMPLE,
+        VRC7_PATCHES
+    },
+    prg_ram,
+    restore,
+    vrc_irq::VrcIrq,
+    Chr,
+    Mapper
+};
+use crate::nes::rom::{
+    Cartridge,
+    Mirroring
+};
+
+/// Mapper 85. Three switchable 8 KB PRG banks, eight 1 KB CHR banks, the VRC IRQ counter and
+/// a six channel FM synthesizer. VRC7a (Lagrange Point) selects registers with A4, VRC7b with A3.
+pub struct Vrc7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    battery: bool,
+    register_line: u16,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    control: u8, // $E000
+    irq: VrcIrq,
+
+    opll: Opll,
+    audio_cycles: u32,
+    output: f32
+}
+
+impl Vrc7 {
+    pub fn new(cartridge: Cartridge) -> Self {
+        Self {
+            prg_ram: prg_ram(&cartridge),
+            chr: Chr::new(&cartridge),
+            battery: cartridge.header.battery,
+            register_line: match cartridge.header.submapper {
+                1 => 0x08,
+                2 => 0x10,
+                _ => 0x18
+            },
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            control: 0,
+            irq: VrcIrq::new(),
+            opll: Opll::new(&VRC7_PATCHES),
+            audio_cycles: 0,
+            output: 0.0,
+            prg_rom: cartridge.prg_rom
+        }
+    }
+
+    fn prg_offset(&self, address: u16) -> usize {
+        let bank: usize = match address {
+            0x8000..=0xDFFF => self.prg_banks[(address as usize - 0x8000) >> 13] as usize,
+            _ => bank_from_end(self.prg_rom.len(), 0x2000, 1)
+        };
+        bank_offset(self.prg_rom.len(), bank, 0x2000, address)
+    }
+
+    fn chr_bank(&self, address: u16) -> usize {
+        self.chr_banks[(address >> 10) as usize & 0b111] as usize
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        !self.prg_ram.is_empty() && self.control & 0x80 != 0
+    }
+
+    fn audio_silenced(&self) -> bool {
+        self.control & 0x40 != 0
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0x8000 => self.prg_banks[0] = value & 0x3F,
+            0x8010 => self.prg_banks[1] = value & 0x3F,
+            0x9000 => self.prg_banks[2] = value & 0x3F,
+            0xA000..=0xD010 => {
+                let index: usize = ((register as usize - 0xA000) >> 12) * 2 + ((register as usize >> 4) & 1);
+                self.chr_banks[index] = value;
+            }
+            0xE000 => {
+                self.control = value;
+                if self.audio_silenced() {
+                    self.opll = Opll::new(&VRC7_PATCHES);
+                }
+            }
+            0xE010 => self.irq.write_latch(value),
+            0xF000 => self.irq.write_control(value),
+            0xF010 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_read(&mut self, address: u16) -> u8 {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[bank_offset(self.prg_ram.len(), 0, 0x2000, address)],
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(address)],
+            _ => 0
+        }
+    }
+
+    fn cpu_write(&mut self, address: u16, value: u8) {
+        match address {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                let offset: usize = bank_offset(self.prg_ram.len(), 0, 0x2000, address);
+                self.prg_ram[offset] = value;
+            }
+            // the sound chip's ports sit at the same addresses on both boards
+            0x9010 if !self.audio_silenced() => self.opll.write_address(value),
+            0x9030 if !self.audio_silenced() => self.opll.write_data(value),
+            0x8000..=0xFFFF => {
+                let line: u16 = if address & self.register_line != 0 { 0x10 } else { 0 };
+                self.write_register((address & 0xF000) | line, value);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, address: u16) -> u8 {
+        self.chr.read(self.chr_bank(address), 0x400, address)
+    }
+
+    fn ppu_write(&mut self, address: u16, value: u8) {
+        self.chr.write(self.chr_bank(address), 0x400, address, value);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.clock();
+
+        self.audio_cycles += 1;
+        if self.audio_cycles == CPU_CYCLES_PER_SAMPLE {
+            self.audio_cycles = 0;
+            self.output = if self.audio_silenced() { 0.0 } else { self.opll.clock() };
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.output
+    }
+
+    fn save_ram(&self) -> Option<&[u8]> {
+        if self.battery { Some(&self.prg_ram) } else { None }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        restore(&mut self.prg_ram, data);
+    }
+}
@@ -0,0 +1,160 @@
+// The prescaler counts down in thirds of a PPU dot so three CPU cycles line up with 341 dots
+const PRESCALER_PERIOD: i16 = 341;
+
+/// IRQ counter shared by the VRC4, VRC6 and VRC7. An 8 bit up counter reloaded from a latch
+/// on overflow, clocked either every CPU cycle or roughly once per scanline by a prescaler.
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool
+}
+
+impl VrcIrq {
+    pub fn new() -> Self {
+        Self {
+            latch: 0,
+            counter: 0,
+            prescaler: PRESCALER_PERIOD,
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+            pending: false
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    // VRC4 takes the latch a nibble at a time
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = (self.latch & 0xF0) | (value & 0x0F);
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0x0F) | (value & 0x0F) << 4;
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 0b001 != 0;
+        self.enabled = value & 0b010 != 0;
+        self.cycle_mode = value & 0b100 != 0;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+        self.pending = false;
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    // Called once per CPU cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER_PERIOD;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl Default for VrcIrq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cycles_until_irq(irq: &mut VrcIrq, limit: u32) -> Option<u32> {
+        (1..=limit).find(|_| {
+            irq.clock();
+            irq.pending()
+        })
+    }
+
+    #[test]
+    fn cycle_mode() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xFD);
+        irq.write_control(0b110);
+        assert_eq!(cycles_until_irq(&mut irq, 10), Some(3));
+
+        // reloaded from the latch on overflow
+        irq.acknowledge();
+        assert!(!irq.pending());
+        assert_eq!(irq.counter, 0xFD);
+    }
+
+    #[test]
+    fn scanline_mode() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch(0xFF);
+        irq.write_control(0b010);
+        // the prescaler runs out after 114, 114 and 113 cycles, three lines in 341 cycles
+        assert_eq!(cycles_until_irq(&mut irq, 1000), Some(114));
+        irq.write_control(0b011);
+        irq.write_latch(0xFD);
+        irq.write_control(0b011);
+        assert_eq!(cycles_until_irq(&mut irq, 1000), Some(341));
+        irq.acknowledge();
+        assert_eq!(cycles_until_irq(&mut irq, 1000), Some(341));
+    }
+
+    #[test]
+    fn acknowledge_and_enable() {
+        let mut irq = VrcIrq::new();
+        irq.write_latch_low(0x0E);
+        irq.write_latch_high(0xFF);
+        assert_eq!(irq.latch, 0xFE);
+
+        // disabled counters don't count
+        assert_eq!(cycles_until_irq(&mut irq, 10), None);
+
+        irq.write_control(0b111);
+        assert_eq!(cycles_until_irq(&mut irq, 10), Some(2));
+        irq.acknowledge();
+        assert_eq!(cycles_until_irq(&mut irq, 10), Some(2));
+
+        // without the enable-after-acknowledge bit acknowledging stops the counter
+        irq.write_control(0b110);
+        assert_eq!(cycles_until_irq(&mut irq, 10), Some(2));
+        irq.acknowledge();
+        assert_eq!(cycles_until_irq(&mut irq, 1000), None);
+
+        // writing the control register acknowledges too
+        irq.write_control(0b110);
+        assert_eq!(cycles_until_irq(&mut irq, 10), Some(2));
+        irq.write_control(0b000);
+        assert!(!irq.pending());
+    }
+}
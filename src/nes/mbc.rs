@@ -0,0 +1,135 @@
+use std::cell::RefCell;
+
+use crate::nes::{
+    apu::Apu,
+    controller::Controller,
+    mapper::Mapper,
+    ppu::Ppu
+};
+
+const OAM_DMA_CYCLES: u32 = 513;
+
+/// The CPU's view of the console: 2 KB of RAM, the PPU and APU/IO registers and the
+/// cartridge from $4020 up. Devices sit in RefCells since reading their registers has
+/// side effects but the CPU reads through a shared borrow.
+pub struct Mbc {
+    pub ram: [u8; 0x800],
+    pub ppu: RefCell<Ppu>,
+    pub apu: RefCell<Apu>,
+    pub controllers: RefCell<[Controller; 2]>,
+    pub cartridge: RefCell<Box<dyn Mapper>>,
+    pub dma_cycles: u32 // CPU cycles stolen by OAM DMA, collected by Nes::step
+}
+
+impl Mbc {
+    pub fn load_cartridge(&mut self, cartridge: Box<dyn Mapper>) {
+        self.cartridge = RefCell::new(cartridge);
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x1FFF => self.ram[address as usize & 0x07FF],
+            0x2000..=0x3FFF => self.ppu.borrow_mut().read_register(address, self.cartridge.borrow_mut().as_mut()),
+            0x4015 => self.apu.borrow_mut().read_status(),
+            0x4016 => self.controllers.borrow_mut()[0].read(),
+            0x4017 => self.controllers.borrow_mut()[1].read(),
+            0x4000..=0x401F => 0, // write only APU registers and the disabled test mode registers
+            _ => self.cartridge.borrow_mut().cpu_read(address)
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1FFF => self.ram[address as usize & 0x07FF] = value,
+            0x2000..=0x3FFF => {
+                let cartridge = self.cartridge.get_mut();
+                cartridge.ppu_register_write(address, value);
+                self.ppu.get_mut().write_register(address, value, cartridge.as_mut());
+            }
+            0x4014 => {
+                let page: u16 = (value as u16) << 8;
+                let mut data: [u8; 0x100] = [0; 0x100];
+                for (offset, byte) in data.iter_mut().enumerate() {
+                    *byte = self.read(page | offset as u16);
+                }
+                self.ppu.get_mut().write_oam_dma(&data);
+                self.dma_cycles += OAM_DMA_CYCLES;
+            }
+            0x4016 => {
+                for controller in self.controllers.get_mut().iter_mut() {
+                    controller.write(value);
+                }
+            }
+            0x4000..=0x4017 => self.apu.get_mut().write_register(address, value),
+            0x4018..=0x401F => {}
+            _ => self.cartridge.get_mut().cpu_write(address, value)
+        }
+    }
+
+    // Runs everything clocked off the CPU for the cycles an instruction took, the PPU runs 3 dots per cycle
+    pub fn tick(&mut self, cycles: u32) {
+        let ppu = self.ppu.get_mut();
+        let apu = self.apu.get_mut();
+        let cartridge = self.cartridge.get_mut();
+        for _ in 0..cycles {
+            cartridge.cpu_cycle();
+            apu.step();
+            if apu.sample_due() {
+                apu.mix(cartridge.audio_output());
+            }
+            for _ in 0..3 {
+                ppu.step(cartridge.as_mut());
+            }
+        }
+    }
+
+    pub fn nmi(&mut self) -> bool {
+        self.ppu.get_mut().take_nmi()
+    }
+
+    // IRQ is level triggered, any source holding the line keeps it asserted
+    pub fn irq(&mut self) -> bool {
+        self.cartridge.get_mut().irq() || self.apu.get_mut().irq()
+    }
+
+    pub fn read_u16(&self, address: u16) -> u16 {
+        (self.read(address.wrapping_add(1)) as u16) << 8 | self.read(address) as u16
+    }
+
+    pub fn write_u16(&mut self, address: u16, value: u16) {
+        self.write(address, (value & 0x00FF) as u8);
+        self.write(address.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    // (zp,x): pointer is fetched from the zero page, wrapping inside it
+    fn pre_index_address(&self, address: u8, x: u8) -> u16 {
+        let pointer: u8 = address.wrapping_add(x);
+        (self.read(pointer.wrapping_add(1) as u16) as u16) << 8 | self.read(pointer as u16) as u16
+    }
+
+    // (zp),y: returns the final address and whether adding y crossed a page
+    fn post_index_address(&self, address: u8, y: u8) -> (u16, bool) {
+        let base: u16 = (self.read(address.wrapping_add(1) as u16) as u16) << 8 | self.read(address as u16) as u16;
+        let indexed: u16 = base.wrapping_add(y as u16);
+        (indexed, (base & 0xFF00) != (indexed & 0xFF00))
+    }
+
+    pub fn read_indirect_pre_index(&self, address: u8, x: u8) -> u8 {
+        self.read(self.pre_index_address(address, x))
+    }
+
+    pub fn read_indirect_post_index(&self, address: u8, y: u8) -> (u8, bool) {
+        let (indexed, page_crossed) = self.post_index_address(address, y);
+        (self.read(indexed), page_crossed)
+    }
+
+    pub fn write_indirect_pre_index(&mut self, address: u8, x: u8, value: u8) {
+        let target: u16 = self.pre_index_address(address, x);
+        self.write(target, value);
+    }
+
+    pub fn write_indirect_post_index(&mut self, address: u8, y: u8, value: u8) {
+        let (indexed, _) = self.post_index_address(address, y);
+        self.write(indexed, value);
+    }
+}
@@ -1,15 +1,527 @@
-use crate::nes::mbc::Mbc;
+use crate::nes::mapper::Mapper;
+use crate::nes::rom::Mirroring;
 
 pub const SCREEN_WIDTH: usize = 256;
 pub const SCREEN_HEIGHT: usize = 240;
 
+const VBLANK_SCANLINE: u16 = 241;
+const PRERENDER_SCANLINE: u16 = 261;
+const DOTS_PER_SCANLINE: u16 = 341;
+
+const STATUS_OVERFLOW: u8 = 0b0010_0000;
+const STATUS_SPRITE_ZERO: u8 = 0b0100_0000;
+const STATUS_VBLANK: u8 = 0b1000_0000;
+
+// 2C02 palette, 0x00RRGGBB as minifb wants it
+const PALETTE: [u32; 64] = [
+    0x666666, 0x002A88, 0x1412A7, 0x3B00A4, 0x5C007E, 0x6E0040, 0x6C0600, 0x561D00,
+    0x333500, 0x0B4800, 0x005200, 0x004F08, 0x00404D, 0x000000, 0x000000, 0x000000,
+    0xADADAD, 0x155FD9, 0x4240FF, 0x7527FE, 0xA01ACC, 0xB71E7B, 0xB53120, 0x994E00,
+    0x6B6D00, 0x388700, 0x0C9300, 0x008F32, 0x007C8D, 0x000000, 0x000000, 0x000000,
+    0xFFFEFF, 0x64B0FF, 0x9290FF, 0xC676FF, 0xF36AFF, 0xFE6ECC, 0xFE8170, 0xEA9E22,
+    0xBCBE00, 0x88D800, 0x5CE430, 0x45E082, 0x48CDDE, 0x4F4F4F, 0x000000, 0x000000,
+    0xFFFEFF, 0xC0DFFF, 0xD3D2FF, 0xE8C8FF, 0xFBC2FF, 0xFEC4EA, 0xFECCC5, 0xF7D8A5,
+    0xE4E594, 0xCFEF96, 0xBDF4AB, 0xB3F3CC, 0xB5EBF2, 0xB8B8B8, 0x000000, 0x000000
+];
+
+#[derive(Clone, Copy, Default)]
+struct Sprite {
+    x: u8,
+    attribute: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+    zero: bool
+}
+
 pub struct Ppu {
-    pub screen_buffer: [u32; SCREEN_WIDTH*SCREEN_HEIGHT]
-    //pub character_mem ?
+    pub screen_buffer: [u32; SCREEN_WIDTH*SCREEN_HEIGHT],
+    pub vram: [u8; 0x800],
+    pub palette: [u8; 0x20],
+    pub oam: [u8; 0x100],
+
+    pub ctrl: u8,
+    pub mask: u8,
+    pub status: u8,
+    oam_address: u8,
+
+    // loopy registers: current/temporary VRAM address, fine x scroll and the $2005/$2006 write toggle
+    v: u16,
+    t: u16,
+    fine_x: u8,
+    write_toggle: bool,
+    read_buffer: u8,
+    io_latch: u8,
+
+    pub scanline: u16,
+    pub dot: u16,
+    odd_frame: bool,
+    nmi: bool,
+    pub frame_complete: bool,
+
+    tile_id: u8,
+    tile_attribute: u8,
+    tile_lo: u8,
+    tile_hi: u8,
+    pattern_shift_lo: u16,
+    pattern_shift_hi: u16,
+    attribute_shift_lo: u16,
+    attribute_shift_hi: u16,
+
+    // OAM entries found for the next scanline and the sprites being drawn on this one
+    found: [(u8, u8); 8],
+    found_count: usize,
+    sprites: [Sprite; 8],
+    sprite_count: usize
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Ppu {
-    pub fn update_screen(&mut self, mbc: &Mbc){
-        
+    pub fn new() -> Self {
+        Self {
+            screen_buffer: [0xFF000000; SCREEN_WIDTH*SCREEN_HEIGHT],
+            vram: [0; 0x800],
+            palette: [0; 0x20],
+            oam: [0; 0x100],
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_address: 0,
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            write_toggle: false,
+            read_buffer: 0,
+            io_latch: 0,
+            scanline: 0,
+            dot: 0,
+            odd_frame: false,
+            nmi: false,
+            frame_complete: false,
+            tile_id: 0,
+            tile_attribute: 0,
+            tile_lo: 0,
+            tile_hi: 0,
+            pattern_shift_lo: 0,
+            pattern_shift_hi: 0,
+            attribute_shift_lo: 0,
+            attribute_shift_hi: 0,
+            found: [(0, 0); 8],
+            found_count: 0,
+            sprites: [Sprite::default(); 8],
+            sprite_count: 0
+        }
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask & 0b0001_1000 != 0
+    }
+
+    // NMI requests are edge triggered, the bus collects them once
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::replace(&mut self.nmi, false)
+    }
+
+    /*-------------------------------Registers-------------------------------*/
+
+    pub fn read_register(&mut self, address: u16, mapper: &mut dyn Mapper) -> u8 {
+        match address & 0x0007 {
+            2 => {
+                let result: u8 = (self.status & 0xE0) | (self.io_latch & 0x1F);
+                self.status &= !STATUS_VBLANK;
+                self.write_toggle = false;
+                self.io_latch = result;
+            }
+            4 => {
+                self.io_latch = self.oam[self.oam_address as usize];
+            }
+            7 => {
+                let address: u16 = self.v & 0x3FFF;
+                if address >= 0x3F00 {
+                    // palette reads come back immediately, the buffer gets the nametable underneath
+                    self.io_latch = (self.io_latch & 0xC0) | self.palette[palette_index(address)];
+                    self.read_buffer = self.read(address - 0x1000, mapper);
+                } else {
+                    self.io_latch = self.read_buffer;
+                    self.read_buffer = self.read(address, mapper);
+                }
+                self.increment_address();
+            }
+            _ => {}
+        }
+        self.io_latch
+    }
+
+    pub fn write_register(&mut self, address: u16, value: u8, mapper: &mut dyn Mapper) {
+        self.io_latch = value;
+        match address & 0x0007 {
+            0 => {
+                if value & 0x80 != 0 && self.ctrl & 0x80 == 0 && self.status & STATUS_VBLANK != 0 {
+                    self.nmi = true;
+                }
+                self.ctrl = value;
+                self.t = (self.t & 0xF3FF) | ((value as u16 & 0b11) << 10);
+            }
+            1 => self.mask = value,
+            3 => self.oam_address = value,
+            4 => {
+                self.oam[self.oam_address as usize] = value;
+                self.oam_address = self.oam_address.wrapping_add(1);
+            }
+            5 => {
+                if !self.write_toggle {
+                    self.t = (self.t & 0xFFE0) | (value as u16 >> 3);
+                    self.fine_x = value & 0b111;
+                } else {
+                    self.t = (self.t & 0x8C1F) | ((value as u16 & 0b111) << 12) | ((value as u16 & 0xF8) << 2);
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            6 => {
+                if !self.write_toggle {
+                    self.t = (self.t & 0x80FF) | ((value as u16 & 0x3F) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.v = self.t;
+                    // the new address goes straight onto the PPU bus, games use this to clock MMC3 IRQs
+                    mapper.ppu_address(self.v & 0x3FFF);
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            7 => {
+                self.write(self.v & 0x3FFF, value, mapper);
+                self.increment_address();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn write_oam_dma(&mut self, data: &[u8; 0x100]) {
+        for &byte in data.iter() {
+            self.oam[self.oam_address as usize] = byte;
+            self.oam_address = self.oam_address.wrapping_add(1);
+        }
+    }
+
+    fn increment_address(&mut self) {
+        let step: u16 = if self.ctrl & 0b0000_0100 != 0 { 32 } else { 1 };
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+    }
+
+    /*-------------------------------Memory----------------------------------*/
+
+    pub fn read(&mut self, address: u16, mapper: &mut dyn Mapper) -> u8 {
+        mapper.ppu_address(address & 0x3FFF);
+        match address & 0x3FFF {
+            0x0000..=0x1FFF => match mapper.chr_ciram_page(address & 0x1FFF) {
+                Some(page) => self.vram[ciram_index(page, address)],
+                None => mapper.ppu_read(address & 0x3FFF)
+            },
+            0x2000..=0x3EFF => match mapper.nametable_read(address & 0x2FFF) {
+                Some(value) => value,
+                None => self.vram[nametable_index(address, mapper.mirroring())]
+            },
+            address => self.palette[palette_index(address)]
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8, mapper: &mut dyn Mapper) {
+        mapper.ppu_address(address & 0x3FFF);
+        match address & 0x3FFF {
+            0x0000..=0x1FFF => match mapper.chr_ciram_page(address & 0x1FFF) {
+                Some(page) => self.vram[ciram_index(page, address)] = value,
+                None => mapper.ppu_write(address & 0x3FFF, value)
+            },
+            0x2000..=0x3EFF => {
+                if !mapper.nametable_write(address & 0x2FFF, value) {
+                    self.vram[nametable_index(address, mapper.mirroring())] = value;
+                }
+            }
+            address => self.palette[palette_index(address)] = value & 0x3F
+        }
+    }
+
+    /*-------------------------------Rendering-------------------------------*/
+
+    // Advances the PPU by one dot
+    pub fn step(&mut self, mapper: &mut dyn Mapper) {
+        let rendering: bool = self.rendering_enabled();
+        let visible: bool = self.scanline < SCREEN_HEIGHT as u16;
+        let prerender: bool = self.scanline == PRERENDER_SCANLINE;
+
+        if prerender && self.dot == 1 {
+            self.status &= !(STATUS_VBLANK | STATUS_SPRITE_ZERO | STATUS_OVERFLOW);
+        }
+
+        if rendering && (visible || prerender) {
+            self.fetch_background(mapper);
+            self.fetch_sprites(mapper);
+
+            if self.dot == 260 {
+                mapper.scanline();
+            }
+            if prerender && (280..=304).contains(&self.dot) {
+                self.v = (self.v & 0x841F) | (self.t & 0x7BE0);
+            }
+        }
+
+        if visible && (1..=256).contains(&self.dot) {
+            self.draw_pixel();
+        }
+
+        if self.scanline == VBLANK_SCANLINE && self.dot == 1 {
+            self.status |= STATUS_VBLANK;
+            if self.ctrl & 0x80 != 0 {
+                self.nmi = true;
+            }
+        }
+
+        // odd frames skip the last dot of the pre-render line while rendering
+        if prerender && self.dot == 339 && self.odd_frame && rendering {
+            self.dot = 340;
+        }
+
+        self.dot += 1;
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > PRERENDER_SCANLINE {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+                self.frame_complete = true;
+            }
+        }
+    }
+
+    fn fetch_background(&mut self, mapper: &mut dyn Mapper) {
+        let dot: u16 = self.dot;
+
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.pattern_shift_lo <<= 1;
+            self.pattern_shift_hi <<= 1;
+            self.attribute_shift_lo <<= 1;
+            self.attribute_shift_hi <<= 1;
+        }
+        if dot % 8 == 1 && ((9..=257).contains(&dot) || dot == 329 || dot == 337) {
+            self.pattern_shift_lo |= self.tile_lo as u16;
+            self.pattern_shift_hi |= self.tile_hi as u16;
+            self.attribute_shift_lo |= if self.tile_attribute & 0b01 != 0 { 0x00FF } else { 0 };
+            self.attribute_shift_hi |= if self.tile_attribute & 0b10 != 0 { 0x00FF } else { 0 };
+        }
+
+        if (1..=256).contains(&dot) || (321..=336).contains(&dot) {
+            let table: u16 = if self.ctrl & 0b0001_0000 != 0 { 0x1000 } else { 0 };
+            let fine_y: u16 = (self.v >> 12) & 0b111;
+            match dot % 8 {
+                1 => self.tile_id = self.read(0x2000 | (self.v & 0x0FFF), mapper),
+                3 => {
+                    let address: u16 = 0x23C0 | (self.v & 0x0C00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07);
+                    let shift: u16 = ((self.v >> 4) & 0b100) | (self.v & 0b10);
+                    self.tile_attribute = (self.read(address, mapper) >> shift) & 0b11;
+                }
+                5 => self.tile_lo = self.read(table + self.tile_id as u16 * 16 + fine_y, mapper),
+                7 => self.tile_hi = self.read(table + self.tile_id as u16 * 16 + fine_y + 8, mapper),
+                0 => self.increment_x(),
+                _ => {}
+            }
+        }
+
+        match dot {
+            256 => self.increment_y(),
+            257 => self.v = (self.v & 0xFBE0) | (self.t & 0x041F),
+            // unused nametable fetches at the end of the line
+            337 | 339 => self.tile_id = self.read(0x2000 | (self.v & 0x0FFF), mapper),
+            _ => {}
+        }
+    }
+
+    fn fetch_sprites(&mut self, mapper: &mut dyn Mapper) {
+        if self.dot == 257 {
+            self.evaluate_sprites();
+        }
+        if !(257..=320).contains(&self.dot) {
+            return;
+        }
+        self.oam_address = 0;
+
+        let slot: usize = (self.dot as usize - 257) / 8;
+        match (self.dot - 257) % 8 {
+            // the sprite unit reuses the background's nametable/attribute slots for garbage reads
+            0 | 2 => {
+                self.read(0x2000 | (self.v & 0x0FFF), mapper);
+            }
+            4 | 6 => {
+                let high: bool = (self.dot - 257) % 8 == 6;
+                let (index, row) = if slot < self.found_count { self.found[slot] } else { (0xFF, 0) };
+                let address: u16 = self.sprite_pattern_address(index, row, slot < self.found_count) + if high { 8 } else { 0 };
+                let mut pattern: u8 = self.read(address, mapper);
+
+                if slot < self.found_count {
+                    let attribute: u8 = self.oam[index as usize * 4 + 2];
+                    if attribute & 0b0100_0000 != 0 {
+                        pattern = pattern.reverse_bits();
+                    }
+                    let sprite: &mut Sprite = &mut self.sprites[slot];
+                    sprite.x = self.oam[index as usize * 4 + 3];
+                    sprite.attribute = attribute;
+                    sprite.zero = index == 0;
+                    if high {
+                        sprite.pattern_hi = pattern;
+                    } else {
+                        sprite.pattern_lo = pattern;
+                    }
+                }
+                if slot == 7 && high {
+                    self.sprite_count = self.found_count;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Finds the (up to 8) sprites that land on the next scanline
+    fn evaluate_sprites(&mut self) {
+        self.found_count = 0;
+        if self.scanline == PRERENDER_SCANLINE {
+            return;
+        }
+
+        let height: u16 = if self.ctrl & 0b0010_0000 != 0 { 16 } else { 8 };
+        for index in 0..64 {
+            let y: u16 = self.oam[index * 4] as u16;
+            if self.scanline >= y && self.scanline - y < height {
+                if self.found_count == 8 {
+                    self.status |= STATUS_OVERFLOW;
+                    break;
+                }
+                self.found[self.found_count] = (index as u8, (self.scanline - y) as u8);
+                self.found_count += 1;
+            }
+        }
+    }
+
+    fn sprite_pattern_address(&self, index: u8, row: u8, used: bool) -> u16 {
+        let (tile, attribute): (u8, u8) = if used {
+            (self.oam[index as usize * 4 + 1], self.oam[index as usize * 4 + 2])
+        } else {
+            (0xFF, 0)
+        };
+        let flip_vertical: bool = attribute & 0b1000_0000 != 0;
+
+        if self.ctrl & 0b0010_0000 != 0 {
+            let row: u8 = if flip_vertical { 15 - row } else { row };
+            let table: u16 = (tile as u16 & 1) * 0x1000;
+            let tile: u16 = (tile as u16 & 0xFE) + if row >= 8 { 1 } else { 0 };
+            table + tile * 16 + (row as u16 & 0b111)
+        } else {
+            let row: u8 = if flip_vertical { 7 - row } else { row };
+            let table: u16 = if self.ctrl & 0b0000_1000 != 0 { 0x1000 } else { 0 };
+            table + tile as u16 * 16 + row as u16
+        }
     }
-}
\ No newline at end of file
+
+    fn draw_pixel(&mut self) {
+        let x: usize = self.dot as usize - 1;
+        let y: usize = self.scanline as usize;
+
+        let mut background: u8 = 0;
+        if self.mask & 0b0000_1000 != 0 && (x >= 8 || self.mask & 0b0000_0010 != 0) {
+            let bit: u16 = 0x8000 >> self.fine_x;
+            let pattern: u8 = ((self.pattern_shift_hi & bit != 0) as u8) << 1 | (self.pattern_shift_lo & bit != 0) as u8;
+            let attribute: u8 = ((self.attribute_shift_hi & bit != 0) as u8) << 1 | (self.attribute_shift_lo & bit != 0) as u8;
+            if pattern != 0 {
+                background = attribute << 2 | pattern;
+            }
+        }
+
+        let mut sprite: u8 = 0;
+        let mut behind: bool = false;
+        if self.mask & 0b0001_0000 != 0 && (x >= 8 || self.mask & 0b0000_0100 != 0) {
+            for slot in self.sprites[..self.sprite_count].iter() {
+                let offset: usize = x.wrapping_sub(slot.x as usize);
+                if offset >= 8 {
+                    continue;
+                }
+                let shift: usize = 7 - offset;
+                let pattern: u8 = ((slot.pattern_hi >> shift) & 1) << 1 | ((slot.pattern_lo >> shift) & 1);
+                if pattern == 0 {
+                    continue;
+                }
+                if slot.zero && background != 0 && x != 255 {
+                    self.status |= STATUS_SPRITE_ZERO;
+                }
+                sprite = 0x10 | (slot.attribute & 0b11) << 2 | pattern;
+                behind = slot.attribute & 0b0010_0000 != 0;
+                break;
+            }
+        }
+
+        let color: u8 = match (background, sprite) {
+            (0, 0) => 0,
+            (0, sprite) => sprite,
+            (background, 0) => background,
+            (background, _) if behind => background,
+            (_, sprite) => sprite
+        };
+
+        let mut entry: u8 = self.palette[palette_index(0x3F00 | color as u16)];
+        if self.mask & 0b0000_0001 != 0 {
+            entry &= 0x30; // greyscale
+        }
+        self.screen_buffer[y * SCREEN_WIDTH + x] = PALETTE[entry as usize & 0x3F];
+    }
+
+    fn increment_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut coarse_y: u16 = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+}
+
+// 1 KB page of nametable RAM mapped into the pattern tables
+fn ciram_index(page: u8, address: u16) -> usize {
+    ((page as usize & 1) * 0x400) | (address as usize & 0x3FF)
+}
+
+// Index into the console's 2 KB of nametable RAM
+pub fn nametable_index(address: u16, mirroring: Mirroring) -> usize {
+    let address: usize = address as usize & 0x0FFF;
+    match mirroring {
+        Mirroring::Horizontal => (address >> 1) & 0x400 | (address & 0x3FF),
+        Mirroring::Vertical | Mirroring::FourScreen => address & 0x7FF,
+        Mirroring::SingleScreenLower => address & 0x3FF,
+        Mirroring::SingleScreenUpper => 0x400 | (address & 0x3FF),
+        Mirroring::Pages(pages) => (pages[address >> 10] as usize & 1) << 10 | (address & 0x3FF)
+    }
+}
+
+// $3F10/$3F14/$3F18/$3F1C are mirrors of the background entries
+fn palette_index(address: u16) -> usize {
+    let index: usize = address as usize & 0x1F;
+    if index & 0x13 == 0x10 { index & 0x0F } else { index }
+}
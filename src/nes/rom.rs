@@ -0,0 +1,437 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use zip::result::ZipError;
+
+use crate::nes::fds_bios::BIOS_SIZE;
+
+pub const HEADER_SIZE: usize = 16;
+pub const TRAINER_SIZE: usize = 512;
+pub const PRG_BANK_SIZE: usize = 0x4000;
+pub const CHR_BANK_SIZE: usize = 0x2000;
+
+const MAGIC: [u8; 4] = *b"NES\x1A";
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    MissingHeader(usize),
+    InvalidMagic([u8; 4]),
+    Truncated { section: &'static str, expected: usize, found: usize },
+    NoPrgRom,
+    UnsupportedMapper(u16),
+    InvalidDiskSide(usize),
+    NoDiskSides,
+    UnknownBoard(String),
+    MissingChunk(&'static str),
+    Zip(ZipError),
+    MissingArchiveEntry(Option<String>), // the requested entry, None when looking for any ROM
+    MissingBios(PathBuf, io::Error),
+    InvalidBios(PathBuf, usize)
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "Couldn't read ROM file: {}", err),
+            RomError::MissingHeader(size) => write!(f, "ROM is {} bytes, too short to hold a {} byte header", size, HEADER_SIZE),
+            RomError::InvalidMagic(magic) => write!(f, "Bad header magic {:02X?}, expected \"NES\\x1A\"", magic),
+            RomError::Truncated { section, expected, found } => write!(f, "ROM is truncated: {} needs {} bytes but only {} are left", section, expected, found),
+            RomError::NoPrgRom => write!(f, "Header declares no PRG ROM"),
+            RomError::UnsupportedMapper(mapper) => write!(f, "Mapper {} is not supported", mapper),
+            RomError::InvalidDiskSide(side) => write!(f, "Disk side {} doesn't start with a disk info block", side),
+            RomError::NoDiskSides => write!(f, "Disk image has no sides"),
+            RomError::UnknownBoard(board) => write!(f, "UNIF board \"{}\" is not supported", board),
+            RomError::MissingChunk(id) => write!(f, "UNIF image has no {} chunk", id),
+            RomError::Zip(err) => write!(f, "Couldn't read zip archive: {}", err),
+            RomError::MissingArchiveEntry(Some(entry)) => write!(f, "Archive has no entry named {}", entry),
+            RomError::MissingArchiveEntry(None) => write!(f, "Archive has no .nes, .fds, .qd or .unf entry"),
+            RomError::MissingBios(path, err) => write!(f, "Couldn't read the disk system BIOS {}: {}", path.display(), err),
+            RomError::InvalidBios(path, size) => write!(f, "Disk system BIOS {} is {} bytes, expected {}", path.display(), size, BIOS_SIZE)
+        }
+    }
+}
+
+impl Error for RomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RomError::Io(err) | RomError::MissingBios(_, err) => Some(err),
+            RomError::Zip(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+impl From<ZipError> for RomError {
+    fn from(err: ZipError) -> Self {
+        RomError::Zip(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
+    Pages([u8; 4]) // console nametable page (0 or 1) used by each of $2000/$2400/$2800/$2C00
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    INes,
+    Nes20,
+    Unif
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem { ppu: u8, hardware: u8 },
+    Playchoice10,
+    Extended(u8)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpansionDevice {
+    Unspecified,
+    StandardControllers,
+    FourScore,
+    FamicomFourPlayers,
+    VsSystem,
+    VsSystemReversed,
+    VsPinball,
+    VsZapper,
+    Zapper,
+    TwoZappers,
+    BandaiHyperShot,
+    PowerPadSideA,
+    PowerPadSideB,
+    FamilyTrainerSideA,
+    FamilyTrainerSideB,
+    ArkanoidNes,
+    ArkanoidFamicom,
+    FamilyBasicKeyboard,
+    SnesMouse,
+    Other(u8)
+}
+
+impl ExpansionDevice {
+    fn from_id(id: u8) -> Self {
+        match id {
+            0x00 => ExpansionDevice::Unspecified,
+            0x01 => ExpansionDevice::StandardControllers,
+            0x02 => ExpansionDevice::FourScore,
+            0x03 => ExpansionDevice::FamicomFourPlayers,
+            0x04 => ExpansionDevice::VsSystem,
+            0x05 => ExpansionDevice::VsSystemReversed,
+            0x06 => ExpansionDevice::VsPinball,
+            0x07 => ExpansionDevice::VsZapper,
+            0x08 => ExpansionDevice::Zapper,
+            0x09 => ExpansionDevice::TwoZappers,
+            0x0A => ExpansionDevice::BandaiHyperShot,
+            0x0B => ExpansionDevice::PowerPadSideA,
+            0x0C => ExpansionDevice::PowerPadSideB,
+            0x0D => ExpansionDevice::FamilyTrainerSideA,
+            0x0E => ExpansionDevice::FamilyTrainerSideB,
+            0x0F => ExpansionDevice::ArkanoidNes,
+            0x10 => ExpansionDevice::ArkanoidFamicom,
+            0x23 => ExpansionDevice::FamilyBasicKeyboard,
+            0x29 => ExpansionDevice::SnesMouse,
+            _ => ExpansionDevice::Other(id)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub format: HeaderFormat,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub nametable_flags: u8, // flags 6 bits 0 and 3, which some boards give their own meaning
+    pub battery: bool,
+    pub trainer: bool,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub timing: Timing,
+    pub console: ConsoleType,
+    pub expansion_device: ExpansionDevice
+}
+
+// NES 2.0 ROM sizes: a 0xF upper nibble switches the lower byte to 2^E * (MM*2+1) bytes
+fn nes20_rom_size(msb: u8, lsb: u8, bank_size: usize) -> usize {
+    if msb == 0x0F {
+        let exponent: u32 = (lsb >> 2) as u32;
+        let multiplier: usize = (lsb & 0b11) as usize * 2 + 1;
+        2usize.checked_pow(exponent).and_then(|size| size.checked_mul(multiplier)).unwrap_or(usize::MAX)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * bank_size
+    }
+}
+
+// NES 2.0 RAM sizes are stored as shift counts: 64 << n bytes, with 0 meaning none
+fn nes20_ram_size(shift: u8) -> usize {
+    if shift == 0 { 0 } else { 64 << shift }
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Self, RomError> {
+        if data.len() < HEADER_SIZE {
+            return Err(RomError::MissingHeader(data.len()));
+        }
+
+        let magic: [u8; 4] = [data[0], data[1], data[2], data[3]];
+        if magic != MAGIC {
+            return Err(RomError::InvalidMagic(magic));
+        }
+
+        let flags6: u8 = data[6];
+        let format: HeaderFormat = if data[7] & 0b0000_1100 == 0b0000_1000 { HeaderFormat::Nes20 } else { HeaderFormat::INes };
+        // Old dumping tools scribbled their name ("DiskDude!") over bytes 7-15, in which case
+        // the upper mapper nibble is garbage and has to be ignored.
+        let dirty: bool = format == HeaderFormat::INes && data[12..16].iter().any(|&byte| byte != 0);
+        let flags7: u8 = if dirty { 0 } else { data[7] };
+
+        let mirroring: Mirroring = if flags6 & 0b0000_1000 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0b0000_0001 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let battery: bool = flags6 & 0b0000_0010 != 0;
+        let mapper: u16 = (flags7 & 0xF0) as u16 | (flags6 >> 4) as u16;
+
+        let console: ConsoleType = match flags7 & 0b11 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem { ppu: data[13] & 0x0F, hardware: data[13] >> 4 },
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(data[13] & 0x0F)
+        };
+
+        let mut header = Self {
+            format,
+            prg_rom_size: data[4] as usize * PRG_BANK_SIZE,
+            chr_rom_size: data[5] as usize * CHR_BANK_SIZE,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            mirroring,
+            nametable_flags: flags6 & 0b0000_1001,
+            battery,
+            trainer: flags6 & 0b0000_0100 != 0,
+            mapper,
+            submapper: 0,
+            timing: Timing::Ntsc,
+            console,
+            expansion_device: ExpansionDevice::Unspecified
+        };
+
+        match format {
+            HeaderFormat::INes => {
+                // iNES has no RAM sizes, assume the usual 8 KB work RAM (byte 8, 0 meaning 1 bank)
+                let prg_ram_size: usize = data[8].max(1) as usize * 0x2000;
+                if battery {
+                    header.prg_nvram_size = prg_ram_size;
+                } else {
+                    header.prg_ram_size = prg_ram_size;
+                }
+                if header.chr_rom_size == 0 {
+                    header.chr_ram_size = CHR_BANK_SIZE;
+                }
+                if !dirty && data[9] & 0b0000_0001 != 0 {
+                    header.timing = Timing::Pal;
+                }
+            }
+            HeaderFormat::Nes20 => {
+                header.mapper |= ((data[8] & 0x0F) as u16) << 8;
+                header.submapper = data[8] >> 4;
+                header.prg_rom_size = nes20_rom_size(data[9] & 0x0F, data[4], PRG_BANK_SIZE);
+                header.chr_rom_size = nes20_rom_size(data[9] >> 4, data[5], CHR_BANK_SIZE);
+                header.prg_ram_size = nes20_ram_size(data[10] & 0x0F);
+                header.prg_nvram_size = nes20_ram_size(data[10] >> 4);
+                header.chr_ram_size = nes20_ram_size(data[11] & 0x0F);
+                header.chr_nvram_size = nes20_ram_size(data[11] >> 4);
+                header.timing = match data[12] & 0b11 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy
+                };
+                header.expansion_device = ExpansionDevice::from_id(data[15] & 0x3F);
+            }
+            // only built from UNIF chunks, never read from an iNES header
+            HeaderFormat::Unif => unreachable!()
+        }
+
+        Ok(header)
+    }
+}
+
+pub struct Cartridge {
+    pub header: Header,
+    pub trainer: Option<Vec<u8>>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>
+}
+
+impl Cartridge {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, RomError> {
+        let header: Header = Header::parse(data)?;
+        if header.prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+
+        let mut offset: usize = HEADER_SIZE;
+        let mut take = |section: &'static str, size: usize| -> Result<Vec<u8>, RomError> {
+            let remaining: usize = data.len() - offset;
+            if remaining < size {
+                return Err(RomError::Truncated { section, expected: size, found: remaining });
+            }
+            let chunk: Vec<u8> = data[offset..offset + size].to_vec();
+            offset += size;
+            Ok(chunk)
+        };
+
+        let trainer: Option<Vec<u8>> = if header.trainer { Some(take("trainer", TRAINER_SIZE)?) } else { None };
+        let prg_rom: Vec<u8> = take("PRG ROM", header.prg_rom_size)?;
+        let chr_rom: Vec<u8> = take("CHR ROM", header.chr_rom_size)?;
+
+        Ok(Self {
+            header,
+            trainer,
+            prg_rom,
+            chr_rom
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Header from the 12 bytes after the magic
+    fn header(bytes: [u8; 12]) -> Header {
+        let mut data: Vec<u8> = MAGIC.to_vec();
+        data.extend(bytes);
+        Header::parse(&data).unwrap()
+    }
+
+    #[test]
+    fn ines_header() {
+        let header: Header = header([2, 1, 0x13, 0x40, 0, 0x01, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.mapper, 0x41);
+        assert_eq!((header.prg_rom_size, header.chr_rom_size), (0x8000, 0x2000));
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert_eq!(header.timing, Timing::Pal);
+        // iNES has no RAM sizes, 8 KB of work RAM is assumed and the battery makes it NVRAM
+        assert_eq!((header.prg_ram_size, header.prg_nvram_size, header.chr_ram_size), (0, 0x2000, 0));
+
+        let header = self::header([1, 0, 0x08, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(header.mirroring, Mirroring::FourScreen);
+        assert_eq!((header.prg_ram_size, header.chr_ram_size), (0x4000, 0x2000));
+    }
+
+    #[test]
+    fn nes20_header() {
+        let header: Header = header([0x02, 0x01, 0x40, 0x48, 0x31, 0x10, 0x97, 0x07, 0x01, 0, 0, 0x08]);
+        assert_eq!(header.format, HeaderFormat::Nes20);
+        assert_eq!((header.mapper, header.submapper), (0x144, 3));
+        assert_eq!(header.prg_rom_size, 0x002 * PRG_BANK_SIZE);
+        assert_eq!(header.chr_rom_size, 0x101 * CHR_BANK_SIZE);
+        assert_eq!(header.timing, Timing::Pal);
+        assert_eq!(header.expansion_device, ExpansionDevice::Zapper);
+    }
+
+    #[test]
+    fn nes20_exponent_sizes() {
+        // 2^14 * 3 bytes of PRG and 2^13 * 1 of CHR
+        let header: Header = header([14 << 2 | 1, 13 << 2, 0, 0x08, 0, 0xFF, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(header.prg_rom_size, 0xC000);
+        assert_eq!(header.chr_rom_size, 0x2000);
+
+        // 2^7 * 7
+        let header = self::header([7 << 2 | 3, 0, 0, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(header.prg_rom_size, 896);
+
+        // sizes too big to hold saturate and fail the length checks later on
+        let header = self::header([63 << 2 | 3, 0, 0, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(header.prg_rom_size, usize::MAX);
+    }
+
+    #[test]
+    fn nes20_ram_shift_sizes() {
+        let header: Header = header([1, 0, 0, 0x08, 0, 0, 0x97, 0x0A, 0, 0, 0, 0]);
+        assert_eq!(header.prg_ram_size, 64 << 7);
+        assert_eq!(header.prg_nvram_size, 64 << 9);
+        assert_eq!(header.chr_ram_size, 64 << 10);
+        assert_eq!(header.chr_nvram_size, 0);
+
+        // unlike iNES, no RAM means none
+        let header = self::header([1, 0, 0x02, 0x08, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!((header.prg_ram_size, header.prg_nvram_size, header.chr_ram_size), (0, 0, 0));
+    }
+
+    #[test]
+    fn diskdude_header_drops_flags_7() {
+        let mut bytes: [u8; 12] = [1, 1, 0x41, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes[3..12].copy_from_slice(b"DiskDude!");
+        let header: Header = header(bytes);
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.mapper, 4);
+        assert_eq!(header.console, ConsoleType::Nes);
+        assert_eq!(header.timing, Timing::Ntsc);
+
+        // a clean header keeps the upper mapper nibble
+        let header = self::header([1, 1, 0x41, 0x40, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(header.mapper, 0x44);
+
+        // NES 2.0 uses bytes 12-15 for real
+        let header = self::header([1, 1, 0x41, 0x48, 0, 0, 0, 0, 0x01, 0, 0, 0x01]);
+        assert_eq!(header.mapper, 0x44);
+        assert_eq!(header.expansion_device, ExpansionDevice::StandardControllers);
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        assert!(matches!(Header::parse(b"NES\x1A"), Err(RomError::MissingHeader(4))));
+        assert!(matches!(Header::parse(&[0; 16]), Err(RomError::InvalidMagic([0, 0, 0, 0]))));
+
+        let mut rom: Vec<u8> = MAGIC.to_vec();
+        rom.extend([0; 12]);
+        assert!(matches!(Cartridge::from_bytes(&rom), Err(RomError::NoPrgRom)));
+
+        let mut rom: Vec<u8> = MAGIC.to_vec();
+        rom.extend([1, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        rom.extend(vec![0; TRAINER_SIZE + PRG_BANK_SIZE]);
+        let result = Cartridge::from_bytes(&rom);
+        assert!(matches!(result, Err(RomError::Truncated { section: "CHR ROM", expected: CHR_BANK_SIZE, found: 0 })));
+        rom.extend(vec![0; CHR_BANK_SIZE]);
+        let cartridge: Cartridge = Cartridge::from_bytes(&rom).unwrap();
+        assert_eq!(cartridge.trainer.map(|trainer| trainer.len()), Some(TRAINER_SIZE));
+    }
+}
@@ -0,0 +1,342 @@
+use crate::nes::rom::{
+    Cartridge,
+    ConsoleType,
+    ExpansionDevice,
+    Header,
+    HeaderFormat,
+    Mirroring,
+    RomError,
+    Timing
+};
+
+const MAGIC: [u8; 4] = *b"UNIF";
+const HEADER_SIZE: usize = 32;
+const CHUNK_HEADER_SIZE: usize = 8;
+const UNROM_512: u16 = 30;
+
+// Maker prefixes in front of the board names ("NES-TLROM", "UNL-...") that don't change the board
+const BOARD_PREFIXES: [&str; 5] = ["NES-", "HVC-", "UNL-", "BTL-", "BMC-"];
+
+// Boards wired for four screen mirroring when the image has no MIRR chunk
+const FOUR_SCREEN_BOARDS: [&str; 3] = ["TR1ROM", "TVROM", "DRROM"];
+
+// Board names, the mapper.submapper they correspond to, and how much PRG RAM and CHR RAM
+// (used when the image has no CHR chunks) they carry
+const BOARDS: [(&str, u16, u8, usize, usize); 67] = [
+    ("NROM", 0, 0, 0, 0x2000),
+    ("NROM-128", 0, 0, 0, 0x2000),
+    ("NROM-256", 0, 0, 0, 0x2000),
+    ("RROM", 0, 0, 0, 0x2000),
+    ("SAROM", 1, 0, 0x2000, 0x2000),
+    ("SBROM", 1, 0, 0, 0x2000),
+    ("SCROM", 1, 0, 0, 0x2000),
+    ("SEROM", 1, 0, 0, 0x2000),
+    ("SFROM", 1, 0, 0, 0x2000),
+    ("SGROM", 1, 0, 0, 0x2000),
+    ("SHROM", 1, 0, 0, 0x2000),
+    ("SJROM", 1, 0, 0x2000, 0x2000),
+    ("SKROM", 1, 0, 0x2000, 0x2000),
+    ("SLROM", 1, 0, 0, 0x2000),
+    ("SL1ROM", 1, 0, 0, 0x2000),
+    ("SNROM", 1, 0, 0x2000, 0x2000),
+    ("SOROM", 1, 0, 0x4000, 0x2000),
+    ("SUROM", 1, 0, 0x2000, 0x2000),
+    ("SXROM", 1, 0, 0x8000, 0x2000),
+    ("UNROM", 2, 2, 0, 0x2000),
+    ("UOROM", 2, 2, 0, 0x2000),
+    ("CNROM", 3, 2, 0, 0x2000),
+    ("TBROM", 4, 0, 0, 0x2000),
+    ("TEROM", 4, 0, 0, 0x2000),
+    ("TFROM", 4, 0, 0, 0x2000),
+    ("TGROM", 4, 0, 0, 0x2000),
+    ("TKROM", 4, 0, 0x2000, 0x2000),
+    ("TLROM", 4, 0, 0, 0x2000),
+    ("TL1ROM", 4, 0, 0, 0x2000),
+    ("TNROM", 4, 0, 0x2000, 0x2000),
+    ("TR1ROM", 4, 0, 0, 0x2000),
+    ("TSROM", 4, 0, 0x2000, 0x2000),
+    ("TVROM", 4, 0, 0, 0x2000),
+    ("B4", 4, 0, 0, 0x2000),
+    ("HKROM", 4, 1, 0x0400, 0x2000),
+    ("EKROM", 5, 0, 0x2000, 0x2000),
+    ("ELROM", 5, 0, 0, 0x2000),
+    ("ETROM", 5, 0, 0x4000, 0x2000),
+    ("EWROM", 5, 0, 0x8000, 0x2000),
+    ("AMROM", 7, 2, 0, 0x2000),
+    ("ANROM", 7, 1, 0, 0x2000),
+    ("AN1ROM", 7, 1, 0, 0x2000),
+    ("AOROM", 7, 2, 0, 0x2000),
+    ("PEEOROM", 9, 0, 0, 0x2000),
+    ("PNROM", 9, 0, 0, 0x2000),
+    ("FJROM", 10, 0, 0x2000, 0x2000),
+    ("FKROM", 10, 0, 0x2000, 0x2000),
+    ("UNROM-512-8", 30, 0, 0, 0x2000),
+    ("UNROM-512-16", 30, 0, 0, 0x4000),
+    ("UNROM-512-32", 30, 0, 0, 0x8000),
+    ("BNROM", 34, 2, 0, 0x2000),
+    ("GNROM", 66, 0, 0, 0x2000),
+    ("MHROM", 66, 0, 0, 0x2000),
+    ("NTBROM", 68, 0, 0x2000, 0x2000),
+    ("BTR", 69, 0, 0x2000, 0x2000),
+    ("JLROM", 69, 0, 0, 0x2000),
+    ("JSROM", 69, 0, 0x2000, 0x2000),
+    ("TKSROM", 118, 0, 0x2000, 0x2000),
+    ("TLSROM", 118, 0, 0, 0x2000),
+    ("TQROM", 119, 0, 0, 0x2000),
+    ("DEROM", 206, 0, 0, 0x2000),
+    ("DRROM", 206, 0, 0, 0x2000),
+    // unlicensed boards, named after their maker rather than with a UNL- prefix
+    ("AVE-NINA-01", 34, 1, 0x2000, 0x2000),
+    ("AVE-NINA-03", 79, 0, 0, 0x2000),
+    ("AVE-NINA-06", 79, 0, 0, 0x2000),
+    ("CAMERICA-BF9093", 71, 0, 0, 0x2000),
+    ("CAMERICA-BF9097", 71, 1, 0, 0x2000)
+];
+
+/// UNIF image: a 32 byte header followed by tagged chunks (4 byte ID, 32 bit length, data)
+/// naming the board (MAPR) and holding the ROM (PRG0-PRGF, CHR0-CHRF) and board details.
+/// The board name is translated to the mapper the iNES header would have used.
+pub struct Unif {
+    pub board: String,
+    pub cartridge: Cartridge
+}
+
+fn strip_prefix(board: &str) -> &str {
+    BOARD_PREFIXES.iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board)
+}
+
+// Mapper, submapper, PRG RAM and CHR RAM size of a board
+fn board_details(name: &str) -> Option<(u16, u8, usize, usize)> {
+    BOARDS.iter()
+        .find(|(known, _, _, _, _)| known.eq_ignore_ascii_case(name))
+        .map(|&(_, mapper, submapper, prg_ram_size, chr_ram_size)| (mapper, submapper, prg_ram_size, chr_ram_size))
+}
+
+impl Unif {
+    pub fn is_unif(data: &[u8]) -> bool {
+        data.starts_with(&MAGIC)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, RomError> {
+        if data.len() < HEADER_SIZE {
+            return Err(RomError::MissingHeader(data.len()));
+        }
+
+        let mut board: Option<String> = None;
+        let mut prg_chunks: [Vec<u8>; 16] = Default::default();
+        let mut chr_chunks: [Vec<u8>; 16] = Default::default();
+        let mut mirroring: Option<u8> = None;
+        let mut battery: bool = false;
+        let mut timing: Timing = Timing::Ntsc;
+
+        let mut offset: usize = HEADER_SIZE;
+        while offset + CHUNK_HEADER_SIZE <= data.len() {
+            let id: &[u8] = &data[offset..offset + 4];
+            let length: usize = u32::from_le_bytes([data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7]]) as usize;
+            offset += CHUNK_HEADER_SIZE;
+
+            let remaining: usize = data.len() - offset;
+            if remaining < length {
+                return Err(RomError::Truncated { section: "UNIF chunk", expected: length, found: remaining });
+            }
+            let chunk: &[u8] = &data[offset..offset + length];
+            offset += length;
+
+            // PRGn/CHRn chunks are numbered with a hex digit
+            let index: Option<usize> = (id[3] as char).to_digit(16).map(|digit| digit as usize);
+            match (&id[..3], index) {
+                (b"PRG", Some(index)) => prg_chunks[index] = chunk.to_vec(),
+                (b"CHR", Some(index)) => chr_chunks[index] = chunk.to_vec(),
+                _ => match id {
+                    b"MAPR" => {
+                        let end: usize = chunk.iter().position(|&byte| byte == 0).unwrap_or(chunk.len());
+                        board = Some(String::from_utf8_lossy(&chunk[..end]).trim().to_string());
+                    }
+                    b"MIRR" => mirroring = chunk.first().copied(),
+                    b"BATR" => battery = chunk.first() != Some(&0),
+                    b"TVCI" => timing = match chunk.first() {
+                        Some(1) => Timing::Pal,
+                        Some(2) => Timing::MultiRegion,
+                        _ => Timing::Ntsc
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        let board: String = board.ok_or(RomError::MissingChunk("MAPR"))?;
+        let name: &str = strip_prefix(&board);
+        let (mapper, submapper, prg_ram_size, chr_ram_size): (u16, u8, usize, usize) = board_details(name).ok_or_else(|| RomError::UnknownBoard(board.clone()))?;
+
+        let prg_rom: Vec<u8> = prg_chunks.concat();
+        let chr_rom: Vec<u8> = chr_chunks.concat();
+        if prg_rom.is_empty() {
+            return Err(RomError::NoPrgRom);
+        }
+
+        let four_screen: bool = FOUR_SCREEN_BOARDS.iter().any(|known| known.eq_ignore_ascii_case(name));
+        let (mirroring, nametable_flags): (Mirroring, u8) = if mapper == UNROM_512 {
+            // UNROM-512 takes its nametable layout from flags 6 bits 3 and 0 like in an iNES
+            // header, where bit 3 alone is the switchable single screen
+            let nametable_flags: u8 = match mirroring {
+                Some(1) => 0b0000_0001,
+                Some(2 | 3 | 5) => 0b0000_1000,
+                Some(4) => 0b0000_1001,
+                _ => 0
+            };
+            let mirroring: Mirroring = match nametable_flags {
+                0b0000_0000 => Mirroring::Horizontal,
+                0b0000_0001 => Mirroring::Vertical,
+                _ => Mirroring::FourScreen
+            };
+            (mirroring, nametable_flags)
+        } else {
+            let mirroring: Mirroring = match mirroring {
+                Some(1) => Mirroring::Vertical,
+                Some(2) => Mirroring::SingleScreenLower,
+                Some(3) => Mirroring::SingleScreenUpper,
+                Some(4) => Mirroring::FourScreen,
+                None if four_screen => Mirroring::FourScreen,
+                // 0 is horizontal, 5 leaves it to the mapper
+                _ => Mirroring::Horizontal
+            };
+            let nametable_flags: u8 = match mirroring {
+                Mirroring::Vertical => 0b0000_0001,
+                Mirroring::FourScreen => 0b0000_1000,
+                _ => 0
+            };
+            (mirroring, nametable_flags)
+        };
+
+        let header = Header {
+            format: HeaderFormat::Unif,
+            prg_rom_size: prg_rom.len(),
+            chr_rom_size: chr_rom.len(),
+            prg_ram_size: if battery { 0 } else { prg_ram_size },
+            prg_nvram_size: if battery { prg_ram_size } else { 0 },
+            chr_ram_size: if chr_rom.is_empty() { chr_ram_size } else { 0 },
+            chr_nvram_size: 0,
+            mirroring,
+            nametable_flags,
+            battery,
+            trainer: false,
+            mapper,
+            submapper,
+            timing,
+            console: ConsoleType::Nes,
+            expansion_device: ExpansionDevice::Unspecified
+        };
+
+        Ok(Self {
+            board,
+            cartridge: Cartridge {
+                header,
+                trainer: None,
+                prg_rom,
+                chr_rom
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::mapper;
+    use crate::nes::mapper::Mapper;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk: Vec<u8> = id.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    fn image(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut image: Vec<u8> = MAGIC.to_vec();
+        image.resize(HEADER_SIZE, 0);
+        image.extend(chunks.concat());
+        image
+    }
+
+    #[test]
+    fn chunks_in_any_order() {
+        let image: Vec<u8> = image(&[
+            chunk(b"PRG1", &[1; 0x4000]),
+            chunk(b"BATR", &[1]),
+            chunk(b"CHR0", &[2; 0x2000]),
+            chunk(b"MIRR", &[1]),
+            chunk(b"PRG0", &[0; 0x4000]),
+            chunk(b"MAPR", b"AVE-NINA-01\0")
+        ]);
+        let unif: Unif = Unif::from_bytes(&image).unwrap();
+        let (cartridge, header): (&Cartridge, &Header) = (&unif.cartridge, &unif.cartridge.header);
+
+        assert_eq!(unif.board, "AVE-NINA-01");
+        assert_eq!((header.mapper, header.submapper), (34, 1));
+        // PRG chunks are put together by number, not by where they are in the file
+        assert_eq!((cartridge.prg_rom[0x3FFF], cartridge.prg_rom[0x4000], cartridge.prg_rom.len()), (0, 1, 0x8000));
+        assert_eq!((cartridge.chr_rom.len(), header.chr_ram_size), (0x2000, 0));
+        assert_eq!((header.mirroring, header.nametable_flags), (Mirroring::Vertical, 0b0000_0001));
+        assert!(header.battery);
+        assert_eq!((header.prg_ram_size, header.prg_nvram_size), (0, 0x2000));
+    }
+
+    #[test]
+    fn board_defaults() {
+        // no MIRR on a four screen board, no BATR, no CHR chunks
+        let image: Vec<u8> = image(&[chunk(b"MAPR", b"NES-TVROM\0"), chunk(b"PRG0", &[0; 0x8000])]);
+        let header: Header = Unif::from_bytes(&image).unwrap().cartridge.header;
+        assert_eq!((header.mapper, header.mirroring), (4, Mirroring::FourScreen));
+        assert!(!header.battery);
+        assert_eq!((header.prg_ram_size, header.prg_nvram_size, header.chr_ram_size), (0, 0, 0x2000));
+    }
+
+    #[test]
+    fn board_ram_sizes() {
+        let sorom: Vec<u8> = image(&[chunk(b"MAPR", b"NES-SOROM\0"), chunk(b"PRG0", &[0; 0x40000]), chunk(b"BATR", &[1])]);
+        let cartridge: Cartridge = Unif::from_bytes(&sorom).unwrap().cartridge;
+        assert_eq!((cartridge.header.mapper, cartridge.header.prg_nvram_size, cartridge.header.chr_ram_size), (1, 0x4000, 0x2000));
+        // CHR bank 0 bit 3 picks one of the two 8 KB RAM banks
+        let mut board: Box<dyn Mapper> = mapper::from_cartridge(cartridge).unwrap();
+        let select_ram_bank = |board: &mut Box<dyn Mapper>, bank: u8| {
+            for bit in 0..5 {
+                board.cpu_write(0xA000, (bank << 3) >> bit & 1);
+                board.cpu_cycle();
+                board.cpu_cycle();
+            }
+        };
+        board.cpu_write(0x6000, 0x11);
+        select_ram_bank(&mut board, 1);
+        board.cpu_write(0x6000, 0x22);
+        assert_eq!(board.save_ram().map(<[u8]>::len), Some(0x4000));
+        select_ram_bank(&mut board, 0);
+        assert_eq!(board.cpu_read(0x6000), 0x11);
+        select_ram_bank(&mut board, 1);
+        assert_eq!(board.cpu_read(0x6000), 0x22);
+
+        let ewrom: Vec<u8> = image(&[chunk(b"MAPR", b"NES-EWROM\0"), chunk(b"PRG0", &[0; 0x40000]), chunk(b"CHR0", &[0; 0x40000])]);
+        let header: Header = Unif::from_bytes(&ewrom).unwrap().cartridge.header;
+        assert_eq!((header.mapper, header.prg_ram_size, header.chr_ram_size), (5, 0x8000, 0));
+    }
+
+    #[test]
+    fn unrom_512_nametable_flags() {
+        for (mirr, flags, mirroring) in [(0, 0b0000, Mirroring::Horizontal), (1, 0b0001, Mirroring::Vertical), (2, 0b1000, Mirroring::FourScreen), (4, 0b1001, Mirroring::FourScreen)] {
+            let unrom_512: Vec<u8> = image(&[chunk(b"MAPR", b"UNL-UNROM-512-32\0"), chunk(b"PRG0", &[0; 0x80000]), chunk(b"MIRR", &[mirr])]);
+            let header: Header = Unif::from_bytes(&unrom_512).unwrap().cartridge.header;
+            assert_eq!((header.mapper, header.chr_ram_size), (30, 0x8000));
+            assert_eq!((header.nametable_flags, header.mirroring), (flags, mirroring), "MIRR {}", mirr);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_boards() {
+        let unknown: Vec<u8> = image(&[chunk(b"MAPR", b"UNL-NOT-A-BOARD\0"), chunk(b"PRG0", &[0; 0x8000])]);
+        assert!(matches!(Unif::from_bytes(&unknown), Err(RomError::UnknownBoard(board)) if board == "UNL-NOT-A-BOARD"));
+        let unnamed: Vec<u8> = image(&[chunk(b"PRG0", &[0; 0x8000])]);
+        assert!(matches!(Unif::from_bytes(&unnamed), Err(RomError::MissingChunk("MAPR"))));
+    }
+}